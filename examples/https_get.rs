@@ -0,0 +1,22 @@
+//! Fetch a page over HTTPS with tls-core's own ClientConnection instead of
+//! rustls, which is what main.rs does without any arguments. The server's
+//! chain is verified against the roots bundled by webpki-roots and its
+//! CertificateVerify against the end-entity key, so the example needs the
+//! webpki-verifier feature:
+//!
+//!     cargo run --example https_get --features webpki-verifier [hostname]
+fn main() {
+    let hostname = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "www.rust-lang.org".to_string());
+    match tls_core::cli::https_get(&hostname) {
+        Ok(response) => {
+            let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+            println!("{}", body);
+        }
+        Err(reason) => {
+            eprintln!("{}", reason);
+            std::process::exit(1);
+        }
+    }
+}
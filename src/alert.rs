@@ -0,0 +1,229 @@
+//! Alert messages convey the closure of a connection or an error. Each alert
+//! is two bytes wide: one byte for the level and one for the description
+use crate::error::TlsError;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AlertLevel {
+    Warning, // 1
+    Fatal,   // 2
+}
+
+impl From<AlertLevel> for u8 {
+    fn from(value: AlertLevel) -> Self {
+        match value {
+            AlertLevel::Warning => 1,
+            AlertLevel::Fatal => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for AlertLevel {
+    type Error = TlsError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        return match value {
+            1 => Ok(Self::Warning),
+            2 => Ok(Self::Fatal),
+            _ => Err(TlsError::DecodeError("invalid alert level")),
+        };
+    }
+}
+
+/// The alert descriptions defined in RFC 8446, section 6
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AlertDescription {
+    CloseNotify,
+    UnexpectedMessage,
+    BadRecordMac,
+    RecordOverflow,
+    HandshakeFailure,
+    BadCertificate,
+    UnsupportedCertificate,
+    CertificateRevoked,
+    CertificateExpired,
+    CertificateUnknown,
+    IllegalParameter,
+    UnknownCa,
+    AccessDenied,
+    DecodeError,
+    DecryptError,
+    ProtocolVersion,
+    InsufficientSecurity,
+    InternalError,
+    InappropriateFallback,
+    UserCanceled,
+    MissingExtension,
+    UnsupportedExtension,
+    UnrecognizedName,
+    BadCertificateStatusResponse,
+    UnknownPskIdentity,
+    CertificateRequired,
+    NoApplicationProtocol,
+}
+
+impl From<AlertDescription> for u8 {
+    fn from(value: AlertDescription) -> Self {
+        match value {
+            AlertDescription::CloseNotify => 0,
+            AlertDescription::UnexpectedMessage => 10,
+            AlertDescription::BadRecordMac => 20,
+            AlertDescription::RecordOverflow => 22,
+            AlertDescription::HandshakeFailure => 40,
+            AlertDescription::BadCertificate => 42,
+            AlertDescription::UnsupportedCertificate => 43,
+            AlertDescription::CertificateRevoked => 44,
+            AlertDescription::CertificateExpired => 45,
+            AlertDescription::CertificateUnknown => 46,
+            AlertDescription::IllegalParameter => 47,
+            AlertDescription::UnknownCa => 48,
+            AlertDescription::AccessDenied => 49,
+            AlertDescription::DecodeError => 50,
+            AlertDescription::DecryptError => 51,
+            AlertDescription::ProtocolVersion => 70,
+            AlertDescription::InsufficientSecurity => 71,
+            AlertDescription::InternalError => 80,
+            AlertDescription::InappropriateFallback => 86,
+            AlertDescription::UserCanceled => 90,
+            AlertDescription::MissingExtension => 109,
+            AlertDescription::UnsupportedExtension => 110,
+            AlertDescription::UnrecognizedName => 112,
+            AlertDescription::BadCertificateStatusResponse => 113,
+            AlertDescription::UnknownPskIdentity => 115,
+            AlertDescription::CertificateRequired => 116,
+            AlertDescription::NoApplicationProtocol => 120,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl AlertDescription {
+    /// The level that the alert is sent at. TLS 1.3 treats every alert as
+    /// fatal except for the two closure alerts (RFC 8446, section 6).
+    pub(crate) fn level(&self) -> AlertLevel {
+        return match self {
+            Self::CloseNotify | Self::UserCanceled => AlertLevel::Warning,
+            _ => AlertLevel::Fatal,
+        };
+    }
+}
+
+impl TryFrom<u8> for AlertDescription {
+    type Error = TlsError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        return match value {
+            0 => Ok(Self::CloseNotify),
+            10 => Ok(Self::UnexpectedMessage),
+            20 => Ok(Self::BadRecordMac),
+            22 => Ok(Self::RecordOverflow),
+            40 => Ok(Self::HandshakeFailure),
+            42 => Ok(Self::BadCertificate),
+            43 => Ok(Self::UnsupportedCertificate),
+            44 => Ok(Self::CertificateRevoked),
+            45 => Ok(Self::CertificateExpired),
+            46 => Ok(Self::CertificateUnknown),
+            47 => Ok(Self::IllegalParameter),
+            48 => Ok(Self::UnknownCa),
+            49 => Ok(Self::AccessDenied),
+            50 => Ok(Self::DecodeError),
+            51 => Ok(Self::DecryptError),
+            70 => Ok(Self::ProtocolVersion),
+            71 => Ok(Self::InsufficientSecurity),
+            80 => Ok(Self::InternalError),
+            86 => Ok(Self::InappropriateFallback),
+            90 => Ok(Self::UserCanceled),
+            109 => Ok(Self::MissingExtension),
+            110 => Ok(Self::UnsupportedExtension),
+            112 => Ok(Self::UnrecognizedName),
+            113 => Ok(Self::BadCertificateStatusResponse),
+            115 => Ok(Self::UnknownPskIdentity),
+            116 => Ok(Self::CertificateRequired),
+            120 => Ok(Self::NoApplicationProtocol),
+            _ => Err(TlsError::DecodeError("invalid alert description")),
+        };
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Alert {
+    pub level: AlertLevel,
+    pub description: AlertDescription,
+}
+
+#[allow(dead_code)]
+impl Alert {
+    /// The alert at the level that its description calls for
+    pub(crate) fn new(description: AlertDescription) -> Self {
+        return Self {
+            level: description.level(),
+            description,
+        };
+    }
+}
+
+impl From<Alert> for Vec<u8> {
+    fn from(value: Alert) -> Self {
+        return vec![value.level.into(), value.description.into()];
+    }
+}
+
+impl TryFrom<&[u8]> for Alert {
+    type Error = TlsError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 2 {
+            return Err(TlsError::DecodeError("alert must be two bytes"));
+        }
+
+        return Ok(Self {
+            level: AlertLevel::try_from(value[0])?,
+            description: AlertDescription::try_from(value[1])?,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alert_round_trip() {
+        let alert = Alert {
+            level: AlertLevel::Fatal,
+            description: AlertDescription::DecodeError,
+        };
+        let encoding: Vec<u8> = alert.into();
+        assert_eq!(encoding, vec![2, 50]);
+        assert_eq!(Alert::try_from(encoding.as_slice()).unwrap(), alert);
+    }
+
+    #[test]
+    fn invalid_alert_encoding() {
+        assert!(Alert::try_from([2].as_slice()).is_err());
+        assert!(Alert::try_from([3, 0].as_slice()).is_err());
+        assert!(Alert::try_from([2, 255].as_slice()).is_err());
+    }
+
+    #[test]
+    fn only_closure_alerts_are_warnings() {
+        assert_eq!(
+            Alert::new(AlertDescription::CloseNotify).level,
+            AlertLevel::Warning
+        );
+        assert_eq!(
+            Alert::new(AlertDescription::UserCanceled).level,
+            AlertLevel::Warning
+        );
+        assert_eq!(
+            Alert::new(AlertDescription::DecodeError).level,
+            AlertLevel::Fatal
+        );
+        assert_eq!(
+            Alert::new(AlertDescription::BadRecordMac).level,
+            AlertLevel::Fatal
+        );
+    }
+}
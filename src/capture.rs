@@ -0,0 +1,29 @@
+//! A capture of every record that a connection sends or receives, for
+//! debugging interop problems against other implementations. Each record is
+//! written as one frame: the length of the record as four big-endian bytes,
+//! followed by the record itself, header included.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub(crate) struct RecordCapture {
+    file: File,
+}
+
+impl RecordCapture {
+    /// Truncate the file if it already exists
+    pub(crate) fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        return Ok(Self {
+            file: File::create(path)?,
+        });
+    }
+
+    /// Frames are not buffered, so the file holds every record up to the last
+    /// one even if the connection is never dropped cleanly
+    pub(crate) fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        let mut frame = (record.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(record);
+
+        return self.file.write_all(&frame);
+    }
+}
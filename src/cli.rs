@@ -0,0 +1,164 @@
+//! The subcommands of the tls-core binary. Each one returns what it would
+//! print, so that the binary only has to pick a subcommand and print.
+use crate::config::ClientConfig;
+use crate::conn::{ClientConnection, Stream};
+use crate::record_layer::{describe_record, inspect_record, RecordLayer};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long https_get waits on the server before giving up
+const HTTPS_GET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `parse <hexstring>`: decode the hex string as one TLS record and describe
+/// it, or explain why it does not parse
+pub fn parse(hexstring: &str) -> Result<String, String> {
+    let bytes = hex::decode(hexstring.trim()).map_err(|err| format!("invalid hex: {}", err))?;
+
+    return inspect_record(&bytes).map_err(|err| err.to_string());
+}
+
+/// The record that carries the ClientHello a connection to the host would
+/// start with, without the compatibility ChangeCipherSpec after it
+fn client_hello_record(hostname: &str) -> Result<Vec<u8>, String> {
+    let config = Arc::new(ClientConfig {
+        send_compat_ccs: false,
+        ..Default::default()
+    });
+    let mut conn = ClientConnection::new(config, hostname).map_err(|err| err.to_string())?;
+    let mut record = vec![];
+    conn.write_tls(&mut record).map_err(|err| err.to_string())?;
+
+    return Ok(record);
+}
+
+/// `client-hello <hostname>`: the ClientHello record for the host, in hex
+pub fn client_hello(hostname: &str) -> Result<String, String> {
+    return Ok(hex::encode(client_hello_record(hostname)?));
+}
+
+/// `client-hello <hostname> --connect`: send the ClientHello to port 443 of
+/// the host, then describe the records of the first response
+pub fn send_client_hello(hostname: &str) -> Result<String, String> {
+    let record = client_hello_record(hostname)?;
+    let mut lines = vec![hex::encode(&record)];
+    let mut sock = TcpStream::connect((hostname, 443)).map_err(|err| err.to_string())?;
+    sock.write_all(&record).map_err(|err| err.to_string())?;
+
+    let mut record_layer = RecordLayer::new();
+    while lines.len() == 1 {
+        if record_layer
+            .read_chunk(&mut sock)
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            break;
+        }
+        for record in record_layer.take_records().map_err(|err| err.to_string())? {
+            lines.push(describe_record(&record));
+        }
+    }
+
+    return Ok(lines.join("\n"));
+}
+
+/// Handshake with port 443 of the host using ClientConnection, then send
+/// `GET /` and return the whole response, headers and all. The server is
+/// authenticated against the bundled roots, so without the webpki-verifier
+/// feature this fails before connecting.
+pub fn https_get(hostname: &str) -> Result<String, String> {
+    if cfg!(not(feature = "webpki-verifier")) {
+        return Err(
+            "https_get needs the webpki-verifier feature to authenticate the server".to_string(),
+        );
+    }
+    let config = ClientConfig {
+        alpn_protocols: vec![b"http/1.1".to_vec()],
+        ..Default::default()
+    };
+    let mut conn =
+        ClientConnection::new(Arc::new(config), hostname).map_err(|err| err.to_string())?;
+    let mut sock = TcpStream::connect((hostname, 443)).map_err(|err| err.to_string())?;
+    sock.set_read_timeout(Some(HTTPS_GET_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    conn.handshake(&mut sock, Some(HTTPS_GET_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nAccept-Encoding: identity\r\n\r\n",
+        hostname
+    );
+    let mut stream = Stream::new(&mut conn, &mut sock);
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|err| err.to_string())?;
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .map_err(|err| err.to_string())?;
+
+    return Ok(String::from_utf8_lossy(&response).into_owned());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_encrypted_extensions_record() {
+        assert_eq!(
+            parse("1603030006080000020000").unwrap(),
+            "Handshake record, legacy version TLSv1_2, 6 bytes: EncryptedExtensions"
+        );
+    }
+
+    #[test]
+    fn client_hello_for_hostname() {
+        let encoding = client_hello("example.com").unwrap();
+
+        assert!(!encoding.is_empty());
+        assert_eq!(
+            parse(&encoding).unwrap(),
+            format!(
+                "Handshake record, legacy version TLSv1_2, {} bytes: ClientHello",
+                encoding.len() / 2 - 5
+            )
+        );
+    }
+
+    #[cfg(not(feature = "webpki-verifier"))]
+    #[test]
+    fn https_get_needs_verifier() {
+        assert!(https_get("www.rust-lang.org")
+            .unwrap_err()
+            .contains("webpki-verifier"));
+    }
+
+    #[cfg(feature = "webpki-verifier")]
+    #[test]
+    #[ignore = "needs the network"]
+    fn https_get_from_real_host() {
+        let response = https_get("www.rust-lang.org").unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 2") || response.starts_with("HTTP/1.1 3"),
+            "{}",
+            response.lines().next().unwrap_or_default()
+        );
+    }
+
+    #[test]
+    fn reject_invalid_hex() {
+        assert!(parse("16030300060800000200zz")
+            .unwrap_err()
+            .starts_with("invalid hex"));
+    }
+
+    #[test]
+    fn parse_truncated_record() {
+        assert_eq!(
+            parse("16030300060800").unwrap_err(),
+            "decode error: incomplete TLS record"
+        );
+    }
+}
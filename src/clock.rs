@@ -0,0 +1,31 @@
+//! Wall-clock time, which the handshake needs for the age of a session ticket
+//! and for the validity of certificates. It sits behind a trait so that tests
+//! can fix the time.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the UNIX epoch
+pub(crate) trait Clock {
+    fn now_millis(&self) -> u64;
+}
+
+/// The operating system's clock. A clock set before the epoch reads zero.
+#[allow(dead_code)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        return SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    }
+}
+
+/// Always reads the same time
+#[allow(dead_code)]
+pub(crate) struct FixedClock(pub(crate) u64);
+
+impl Clock for FixedClock {
+    fn now_millis(&self) -> u64 {
+        return self.0;
+    }
+}
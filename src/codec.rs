@@ -0,0 +1,303 @@
+//! The big-endian integers that TLS uses for lengths and code points, in one
+//! place so that no parser or serializer gets the byte order wrong
+use crate::error::TlsError;
+
+/// The result of running a parser to completion over the bytes at hand. A
+/// streaming caller reads more bytes on Incomplete, and aborts on Error.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) enum ParseOutcome<T> {
+    Complete(T),
+    Incomplete,
+    Error(TlsError),
+}
+
+/// A cursor over bytes that may not all have arrived yet. A read past the end
+/// returns None rather than an error, so that the caller can tell a buffer
+/// that is short from one that is malformed.
+#[allow(dead_code)]
+pub(crate) struct Reader<'a> {
+    remainder: &'a [u8],
+}
+
+#[allow(dead_code)]
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        return Self { remainder: buf };
+    }
+
+    /// The next n bytes, if they are there
+    pub(crate) fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.remainder.len() < n {
+            return None;
+        }
+        let (taken, remainder) = self.remainder.split_at(n);
+        self.remainder = remainder;
+
+        return Some(taken);
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Option<u8> {
+        return self.take(1).map(|bytes| bytes[0]);
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Option<u16> {
+        return self
+            .take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+
+    /// The bytes not read yet
+    pub(crate) fn remainder(&self) -> &'a [u8] {
+        return self.remainder;
+    }
+}
+
+/// A fixed-size field that is written to and read from the wire as is
+pub(crate) trait Codec: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode the value at the start of the buffer, returning it with the
+    /// bytes after it
+    fn decode(buf: &[u8]) -> Result<(Self, &[u8]), TlsError>;
+}
+
+/// A 24-bit unsigned integer, which TLS uses for the length of a handshake
+/// message and for the lengths within a Certificate message
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct U24(u32);
+
+#[allow(dead_code)]
+impl U24 {
+    pub(crate) const MAX: u32 = (1 << 24) - 1;
+
+    /// None if the value does not fit in 24 bits
+    pub(crate) fn new(value: u32) -> Option<Self> {
+        if value > Self::MAX {
+            return None;
+        }
+        return Some(Self(value));
+    }
+
+    pub(crate) fn get(self) -> u32 {
+        return self.0;
+    }
+}
+
+impl TryFrom<usize> for U24 {
+    type Error = TlsError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        return u32::try_from(value)
+            .ok()
+            .and_then(Self::new)
+            .ok_or(TlsError::DecodeError("value does not fit in 24 bits"));
+    }
+}
+
+impl From<U24> for usize {
+    fn from(value: U24) -> Self {
+        return value.0 as usize;
+    }
+}
+
+impl Codec for U24 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0.to_be_bytes()[1..]);
+    }
+
+    fn decode(buf: &[u8]) -> Result<(Self, &[u8]), TlsError> {
+        if buf.len() < 3 {
+            return Err(TlsError::DecodeError("truncated u24"));
+        }
+        let (encoding, rest) = buf.split_at(3);
+
+        return Ok((
+            Self(u32::from_be_bytes([
+                0,
+                encoding[0],
+                encoding[1],
+                encoding[2],
+            ])),
+            rest,
+        ));
+    }
+}
+
+/// A length field that is written before what it covers is known: begin_u8,
+/// begin_u16, or begin_u24 reserves its bytes and returns this marker, and end
+/// fills them in with the number of bytes written since. This lets a nested
+/// structure be encoded in a single pass over one buffer.
+#[allow(dead_code)]
+#[must_use]
+pub(crate) struct LengthPrefixed {
+    offset: usize,
+    width: usize,
+}
+
+#[allow(dead_code)]
+impl LengthPrefixed {
+    pub(crate) fn begin_u8(out: &mut Vec<u8>) -> Self {
+        return Self::begin(out, 1);
+    }
+
+    pub(crate) fn begin_u16(out: &mut Vec<u8>) -> Self {
+        return Self::begin(out, 2);
+    }
+
+    pub(crate) fn begin_u24(out: &mut Vec<u8>) -> Self {
+        return Self::begin(out, 3);
+    }
+
+    fn begin(out: &mut Vec<u8>, width: usize) -> Self {
+        let offset = out.len();
+        out.resize(offset + width, 0);
+
+        return Self { offset, width };
+    }
+
+    /// Markers must end in the reverse order of their begin, innermost first.
+    /// A body too long for its length field is a bug in the encoder, and
+    /// panics in release builds too rather than put a wrong length on the
+    /// wire.
+    pub(crate) fn end(out: &mut [u8], marker: Self) {
+        let start = marker.offset + marker.width;
+        let length = out.len() - start;
+        assert!(
+            length < 1 << (8 * marker.width),
+            "body too long for its length field"
+        );
+        out[marker.offset..start]
+            .copy_from_slice(&(length as u32).to_be_bytes()[4 - marker.width..]);
+    }
+}
+
+pub(crate) fn encode_u16(value: u16) -> [u8; 2] {
+    return value.to_be_bytes();
+}
+
+/// Decode the u16 at the start of the buffer, returning it with the bytes
+/// after it
+pub(crate) fn decode_u16(buf: &[u8]) -> Result<(u16, &[u8]), TlsError> {
+    if buf.len() < 2 {
+        return Err(TlsError::DecodeError("truncated u16"));
+    }
+    let (encoding, rest) = buf.split_at(2);
+
+    return Ok((u16::from_be_bytes([encoding[0], encoding[1]]), rest));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn u16_round_trip() {
+        assert_eq!(encode_u16(0x0102), [0x01, 0x02]);
+        let (value, rest) = decode_u16(&[0x40, 0x00, 0xff]).unwrap();
+        assert_eq!(value, 0x4000);
+        assert_eq!(rest, [0xff]);
+        assert_eq!(
+            decode_u16(&encode_u16(0xbeef)).unwrap(),
+            (0xbeef, [].as_slice())
+        );
+    }
+
+    #[test]
+    fn reader_runs_out_without_error() {
+        let mut reader = Reader::new(&[0x01, 0x02, 0x03]);
+        assert_eq!(reader.read_u16(), Some(0x0102));
+        assert_eq!(reader.read_u16(), None);
+        assert_eq!(reader.remainder(), [0x03]);
+        assert_eq!(reader.read_u8(), Some(0x03));
+        assert_eq!(reader.take(0), Some([].as_slice()));
+        assert!(reader.take(1).is_none());
+    }
+
+    #[test]
+    fn u24_round_trip() {
+        let mut buf = vec![];
+        U24::new(0x010203).unwrap().encode(&mut buf);
+        assert_eq!(buf, [0x01, 0x02, 0x03]);
+
+        buf.push(0xff);
+        let (value, rest) = U24::decode(&buf).unwrap();
+        assert_eq!(value.get(), 0x010203);
+        assert_eq!(rest, [0xff]);
+        assert!(matches!(
+            U24::decode(&buf[..2]),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn u24_overflow_at_2_to_the_24() {
+        let max = U24::new(U24::MAX).unwrap();
+        let mut buf = vec![];
+        max.encode(&mut buf);
+        assert_eq!(buf, [0xff, 0xff, 0xff]);
+        assert_eq!(U24::decode(&buf).unwrap().0, max);
+
+        assert!(U24::new(1 << 24).is_none());
+        assert!(U24::try_from(1usize << 24).is_err());
+        assert_eq!(
+            usize::from(U24::try_from((1usize << 24) - 1).unwrap()),
+            0xffffff
+        );
+    }
+
+    #[test]
+    fn longest_body_fits_its_length_field() {
+        let mut out = vec![];
+        let marker = LengthPrefixed::begin_u8(&mut out);
+        out.extend_from_slice(&[0xab; 255]);
+        LengthPrefixed::end(&mut out, marker);
+
+        assert_eq!(out[0], 0xff);
+        assert_eq!(out.len(), 256);
+    }
+
+    #[test]
+    #[should_panic(expected = "body too long for its length field")]
+    fn body_too_long_for_its_length_field() {
+        let mut out = vec![];
+        let marker = LengthPrefixed::begin_u8(&mut out);
+        out.extend_from_slice(&[0xab; 256]);
+        LengthPrefixed::end(&mut out, marker);
+    }
+
+    #[test]
+    fn backpatch_nested_lengths() {
+        let mut out = vec![0xaa];
+        let outer = LengthPrefixed::begin_u24(&mut out);
+        let list = LengthPrefixed::begin_u16(&mut out);
+        for item in [b"ab".as_slice(), b"", b"cde"] {
+            let entry = LengthPrefixed::begin_u8(&mut out);
+            out.extend_from_slice(item);
+            LengthPrefixed::end(&mut out, entry);
+        }
+        LengthPrefixed::end(&mut out, list);
+        out.push(0xbb);
+        LengthPrefixed::end(&mut out, outer);
+
+        assert_eq!(
+            out,
+            [
+                0xaa, // before the structure
+                0x00, 0x00, 0x0b, // outer u24
+                0x00, 0x08, // list u16
+                0x02, b'a', b'b', // first entry
+                0x00, // empty entry
+                0x03, b'c', b'd', b'e', // last entry
+                0xbb, // after the list, inside the outer length
+            ]
+        );
+    }
+
+    #[test]
+    fn u16_underflow() {
+        assert!(matches!(decode_u16(&[0x01]), Err(TlsError::DecodeError(_))));
+        assert!(decode_u16(&[]).is_err());
+    }
+}
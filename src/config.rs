@@ -0,0 +1,138 @@
+//! The settings of the client that outlive any single connection
+use crate::clock::{Clock, SystemClock};
+use crate::constants::{CipherSuite, NamedGroup, SignatureScheme};
+use crate::crypto::transcript::TranscriptMode;
+use crate::crypto::{OsRng, Rng};
+use crate::verify::{self, DangerousAcceptAny, ServerCertVerifier};
+use std::sync::{Arc, Mutex};
+
+/// What to do once the write key has sealed its limit of records
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum RecordLimitAction {
+    /// Send a KeyUpdate and carry on under the next key
+    KeyUpdate,
+
+    /// Refuse to seal any more records
+    Error,
+}
+
+#[allow(dead_code)]
+pub(crate) struct ClientConfig {
+    /// Offered in ClientHello in order of preference
+    pub(crate) cipher_suites: Vec<CipherSuite>,
+
+    /// Offered in supported_groups in order of preference. A
+    /// HelloRetryRequest may ask for any group from this list that the first
+    /// ClientHello has no key share for.
+    pub(crate) supported_groups: Vec<NamedGroup>,
+
+    /// The groups that the first ClientHello generates key shares for, which
+    /// saves the work of a key for every supported group
+    pub(crate) key_share_groups: Vec<NamedGroup>,
+
+    /// The signatures the client accepts in CertificateVerify and in the
+    /// certificate chain
+    pub(crate) signature_schemes: Vec<SignatureScheme>,
+
+    /// The protocols to offer in the ALPN extension, in order of preference.
+    /// ALPN is not offered at all if this is empty.
+    pub(crate) alpn_protocols: Vec<Vec<u8>>,
+
+    /// Send a dummy ChangeCipherSpec right after ClientHello, for middleboxes
+    /// that expect a TLS 1.3 handshake to look like a resumed TLS 1.2 one
+    /// (RFC 8446, appendix D.4). Some test servers choke on it.
+    pub(crate) send_compat_ccs: bool,
+
+    /// Send 32 random bytes as the legacy_session_id, which makes ClientHello
+    /// look like an attempt to resume a TLS 1.2 session for the same
+    /// middleboxes (RFC 8446, appendix D.4). The server must echo it.
+    pub(crate) compat_session_id: bool,
+
+    /// Put a GREASE cipher suite, group, and extension (RFC 8701) in
+    /// ClientHello, the way browsers do, so that servers that choke on
+    /// unknown values show up in testing
+    pub(crate) grease: bool,
+
+    /// Decides whether the server's certificate chain is trusted. The default
+    /// is WebPkiVerifier with the bundled roots, or with no webpki-verifier
+    /// feature a verifier that trusts nothing.
+    pub(crate) verifier: Arc<dyn ServerCertVerifier + Send + Sync>,
+
+    /// How many application records to seal under one key; None for the
+    /// limit of the negotiated AEAD
+    pub(crate) records_per_key: Option<u64>,
+
+    pub(crate) on_record_limit: RecordLimitAction,
+
+    /// Whether connections keep the handshake messages around after hashing
+    /// them. Keeping them helps with debugging but costs memory for a long
+    /// certificate chain.
+    pub(crate) transcript_mode: TranscriptMode,
+
+    /// Shared by every connection made with this config
+    pub(crate) rng: Mutex<Box<dyn Rng + Send>>,
+
+    /// The time that ticket ages and certificate validity are measured from
+    pub(crate) clock: Arc<dyn Clock + Send + Sync>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        return Self {
+            cipher_suites: vec![
+                CipherSuite::Aes128GcmSha256,
+                CipherSuite::Aes256GcmSha384,
+                CipherSuite::Chacha20Poly1305Sha256,
+            ],
+            supported_groups: vec![NamedGroup::X25519],
+            key_share_groups: vec![NamedGroup::X25519],
+            signature_schemes: vec![
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                SignatureScheme::EcdsaSecp384r1Sha384,
+                SignatureScheme::Ed25519,
+                SignatureScheme::RsaPssRsaeSha256,
+                SignatureScheme::RsaPssRsaeSha384,
+                SignatureScheme::RsaPssRsaeSha512,
+                SignatureScheme::RsaPkcs1Sha256,
+                SignatureScheme::RsaPkcs1Sha384,
+                SignatureScheme::RsaPkcs1Sha512,
+            ],
+            alpn_protocols: vec![],
+            send_compat_ccs: true,
+            compat_session_id: false,
+            grease: false,
+            verifier: verify::default_verifier(),
+            records_per_key: None,
+            on_record_limit: RecordLimitAction::KeyUpdate,
+            transcript_mode: TranscriptMode::HashOnly,
+            rng: Mutex::new(Box::new(OsRng)),
+            clock: Arc::new(SystemClock),
+        };
+    }
+}
+
+#[allow(dead_code)]
+impl ClientConfig {
+    /// The settings that weaken the security of every connection made with
+    /// the config, kept apart so that using them is explicit and easy to find
+    pub(crate) fn dangerous(&mut self) -> DangerousClientConfig<'_> {
+        return DangerousClientConfig { config: self };
+    }
+}
+
+/// The dangerous half of a ClientConfig, after rustls's
+/// DangerousClientConfig
+pub(crate) struct DangerousClientConfig<'a> {
+    config: &'a mut ClientConfig,
+}
+
+#[allow(dead_code)]
+impl DangerousClientConfig<'_> {
+    /// Trust any certificate chain the server presents, such as that of a
+    /// local server with a self-signed certificate. Anyone in the middle can
+    /// then impersonate the server.
+    pub(crate) fn with_no_cert_verification(self) {
+        self.config.verifier = Arc::new(DangerousAcceptAny);
+    }
+}
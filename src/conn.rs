@@ -0,0 +1,2865 @@
+//! The client side of a TLS connection. Like rustls's ClientConnection, it
+//! does no I/O of its own: the caller moves TLS bytes between the connection
+//! and the socket with read_tls and write_tls, and exchanges plaintext with
+//! send_application_data and recv_application_data.
+use crate::alert::{Alert, AlertDescription};
+#[cfg(feature = "record-capture")]
+use crate::capture::RecordCapture;
+use crate::config::{ClientConfig, RecordLimitAction};
+#[cfg(feature = "record-capture")]
+use crate::constants::RECORD_HEADER_LEN;
+use crate::constants::{
+    self, CipherSuite, ContentType, ExtensionType, HandshakeType, NamedGroup, ProtocolVersion,
+    RANDOM_LEN, SESSION_ID_MAX_LEN,
+};
+use crate::crypto;
+use crate::crypto::aead::{self, TrafficKey};
+use crate::crypto::key_schedule::{hash_algorithm, KeySchedule};
+#[cfg(feature = "dangerous")]
+use crate::crypto::key_schedule::{Secrets, TrafficKeys};
+use crate::crypto::key_share::KeySharePrivate;
+use crate::crypto::transcript::Transcript;
+use crate::error::TlsError;
+use crate::handshake::{
+    self, CertificateVerify, Extension, Finished, HandshakeJoiner, HandshakeMessage, KeyShare,
+    KeyShareEntry, ServerHello,
+};
+use crate::observer::{HandshakeObserver, KeyEpoch, NoopObserver};
+#[cfg(feature = "buffer-pool")]
+use crate::pool::BufferPool;
+#[cfg(feature = "metrics")]
+use crate::record_layer::Metrics;
+use crate::record_layer::{
+    Epoch, PaddingPolicy, RecordLayer, TLSCiphertext, TLS_PLAINTEXT_MAX_LENGTH,
+};
+use crate::verify;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+#[cfg(feature = "record-capture")]
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// KeyUpdate.request_update when the peer need not update its own key
+const KEY_UPDATE_NOT_REQUESTED: u8 = 0;
+
+/// Where the client is in the handshake (RFC 8446, appendix A.1), named after
+/// the message it waits for next
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum HandshakeState {
+    WaitServerHello,
+    WaitEncryptedExtensions,
+    WaitCertificate,
+    WaitCertificateVerify,
+    WaitFinished,
+    Connected,
+}
+
+/// What a call to read_tls left the caller with
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ReadStatus {
+    /// This many bytes were read, and there are records to write or
+    /// application data to receive
+    Ready(usize),
+
+    /// This many bytes were read and processed, but nothing comes of them
+    /// until more arrives from the peer, such as the rest of its flight
+    Blocked(usize),
+
+    /// The reader has reached its end, or the peer has sent close_notify
+    Closed,
+}
+
+#[allow(dead_code)]
+pub(crate) struct ClientConnection {
+    config: Arc<ClientConfig>,
+    server_name: String,
+    state: HandshakeState,
+    record_layer: RecordLayer,
+    handshake_joiner: HandshakeJoiner,
+
+    /// The types of the extensions sent in ClientHello. The server may only
+    /// respond with extensions from this list.
+    offered_extensions: Vec<ExtensionType>,
+
+    /// The types of the extensions in ServerHello and EncryptedExtensions
+    negotiated_extensions: Vec<ExtensionType>,
+
+    /// The protocol that the server picked from alpn_protocols
+    alpn_protocol: Option<Vec<u8>>,
+
+    /// The server answered server_name with the empty extension, i.e. it used
+    /// the name to pick its certificate (RFC 6066, section 3)
+    server_name_acknowledged: bool,
+
+    /// The private key of every key share sent in ClientHello
+    key_shares: Vec<KeySharePrivate>,
+
+    /// What a second ClientHello, sent in answer to a HelloRetryRequest,
+    /// repeats from the first one
+    random: [u8; RANDOM_LEN],
+    legacy_session_id: Vec<u8>,
+    cipher_suites: Vec<CipherSuite>,
+    client_hello_extensions: Vec<Extension<'static>>,
+
+    /// The identities offered in pre_shared_key, which a ServerHello may pick
+    /// one of by index. Empty, as the client does not resume sessions yet.
+    psk_identities: Vec<Vec<u8>>,
+
+    /// The cipher suite of the HelloRetryRequest, if there was one; the
+    /// ServerHello must agree with it
+    hello_retry_cipher_suite: Option<CipherSuite>,
+
+    transcript: Transcript,
+
+    /// Known once ServerHello has picked the cipher suite
+    key_schedule: Option<KeySchedule>,
+
+    /// The DER chain from the server's Certificate message, once the verifier
+    /// has accepted it
+    peer_certificates: Option<Vec<Vec<u8>>>,
+
+    observer: Box<dyn HandshakeObserver + Send>,
+
+    /// Serialized records waiting to be written to the socket
+    outgoing: Vec<u8>,
+
+    /// Decrypted application data not yet handed to the application. Records
+    /// are concatenated, so record boundaries are not visible to the caller.
+    received: VecDeque<u8>,
+
+    /// We have sent close_notify and may send nothing more, but the peer can
+    /// keep sending until it closes too
+    half_closed_local: bool,
+
+    /// The peer has sent close_notify, so no more records will be read
+    half_closed_remote: bool,
+}
+
+#[allow(dead_code)]
+impl ClientConnection {
+    /// Start a connection to the named server. The ClientHello, and the
+    /// compatibility ChangeCipherSpec if the config asks for it, are queued
+    /// right away, so the first write_tls already has something to send.
+    pub(crate) fn new(config: Arc<ClientConfig>, server_name: &str) -> Result<Self, TlsError> {
+        let transcript = Transcript::with_mode(config.transcript_mode);
+        let mut conn = Self {
+            config,
+            server_name: String::new(),
+            state: HandshakeState::WaitServerHello,
+            record_layer: RecordLayer::new(),
+            handshake_joiner: HandshakeJoiner::new(),
+            offered_extensions: vec![],
+            negotiated_extensions: vec![],
+            alpn_protocol: None,
+            server_name_acknowledged: false,
+            key_shares: vec![],
+            random: [0; RANDOM_LEN],
+            legacy_session_id: vec![],
+            cipher_suites: vec![],
+            client_hello_extensions: vec![],
+            psk_identities: vec![],
+            hello_retry_cipher_suite: None,
+            transcript,
+            key_schedule: None,
+            peer_certificates: None,
+            observer: Box::new(NoopObserver),
+            outgoing: vec![],
+            received: VecDeque::new(),
+            half_closed_local: false,
+            half_closed_remote: false,
+        };
+        conn.start(server_name)?;
+
+        return Ok(conn);
+    }
+
+    /// Forget the current connection, so that the same struct can start a
+    /// fresh handshake with the named server, e.g. when it goes back to a
+    /// pool. The secrets are zeroized and the buffers keep their capacity;
+    /// the config and the observer stay.
+    pub(crate) fn reset(&mut self, server_name: &str) -> Result<(), TlsError> {
+        self.state = HandshakeState::WaitServerHello;
+        self.record_layer.reset();
+        self.handshake_joiner.clear();
+        self.offered_extensions.clear();
+        self.negotiated_extensions.clear();
+        self.alpn_protocol = None;
+        self.server_name_acknowledged = false;
+        for key_share in self.key_shares.iter_mut() {
+            if let KeySharePrivate::X25519(private_key) = key_share {
+                crypto::zeroize(private_key);
+            }
+        }
+        self.key_shares.clear();
+        self.legacy_session_id.clear();
+        self.cipher_suites.clear();
+        self.client_hello_extensions.clear();
+        self.psk_identities.clear();
+        self.hello_retry_cipher_suite = None;
+        self.transcript.clear();
+        self.key_schedule = None;
+        self.peer_certificates = None;
+        self.outgoing.clear();
+        self.received.clear();
+        self.half_closed_local = false;
+        self.half_closed_remote = false;
+
+        return self.start(server_name);
+    }
+
+    /// Queue the ClientHello, and the compatibility ChangeCipherSpec if the
+    /// config asks for it
+    fn start(&mut self, server_name: &str) -> Result<(), TlsError> {
+        let mut key_share_entries = vec![];
+        // The cipher suite, group, and extension type
+        let mut grease_seeds = [0; 3];
+        {
+            let mut rng = self.config.rng.lock().unwrap();
+            rng.fill(&mut self.random);
+            if self.config.compat_session_id {
+                self.legacy_session_id = vec![0; SESSION_ID_MAX_LEN];
+                rng.fill(&mut self.legacy_session_id);
+            }
+            for group in self.config.key_share_groups.iter() {
+                let (private_key, public_key) = KeySharePrivate::generate(*group, &mut **rng)?;
+                self.key_shares.push(private_key);
+                key_share_entries.push((*group, public_key));
+            }
+            if self.config.grease {
+                rng.fill(&mut grease_seeds);
+            }
+        }
+
+        let mut supported_groups = self.config.supported_groups.clone();
+        let mut extensions = vec![];
+        if self.config.grease {
+            self.cipher_suites
+                .push(CipherSuite::Unknown(constants::grease(grease_seeds[0])));
+            supported_groups.insert(0, NamedGroup::Unknown(constants::grease(grease_seeds[1])));
+            extensions.push(Extension::raw(constants::grease(grease_seeds[2]), vec![]));
+        }
+        self.cipher_suites
+            .extend_from_slice(&self.config.cipher_suites);
+        extensions.extend([
+            handshake::server_name(server_name),
+            handshake::supported_versions(&[ProtocolVersion::TLSv1_3]),
+            handshake::supported_groups(&supported_groups),
+            handshake::signature_algorithms(&self.config.signature_schemes),
+            handshake::key_share(&key_share_entries),
+        ]);
+        if !self.config.alpn_protocols.is_empty() {
+            extensions.push(handshake::application_layer_protocol_negotiation(
+                &self.config.alpn_protocols,
+            ));
+        }
+        // A server may never answer a GREASE extension
+        self.offered_extensions.extend(
+            extensions
+                .iter()
+                .map(|extension| extension.extension_type)
+                .filter(|extension_type| !constants::is_grease(u16::from(*extension_type))),
+        );
+        let client_hello = handshake::build_client_hello(
+            &self.random,
+            &self.legacy_session_id,
+            &self.cipher_suites,
+            extensions.clone(),
+        );
+        self.server_name = server_name.to_string();
+        self.client_hello_extensions = extensions;
+
+        self.send_handshake_message(client_hello)?;
+        if self.config.send_compat_ccs {
+            let records = self
+                .record_layer
+                .write_records(ContentType::ChangeCipherSpec, &[0x01])?;
+            self.outgoing.extend_from_slice(&records);
+        }
+
+        return Ok(());
+    }
+
+    pub(crate) fn state(&self) -> HandshakeState {
+        return self.state;
+    }
+
+    /// The extensions that the server answered so far, such as
+    /// ApplicationLayerProtocolNegotiation when the server picked a protocol
+    pub(crate) fn negotiated_extensions(&self) -> &[ExtensionType] {
+        return &self.negotiated_extensions;
+    }
+
+    /// The protocol that the server selected with ALPN, if any
+    pub(crate) fn alpn_protocol(&self) -> Option<&[u8]> {
+        return self.alpn_protocol.as_deref();
+    }
+
+    /// Whether the server acknowledged the server name that was sent
+    pub(crate) fn server_name_acknowledged(&self) -> bool {
+        return self.server_name_acknowledged;
+    }
+
+    /// The certificate chain that the server presented, end-entity first and
+    /// each certificate in DER, for checks on top of the verifier such as
+    /// pinning. None until the Certificate message has been processed.
+    pub(crate) fn peer_certificates(&self) -> Option<&[Vec<u8>]> {
+        return self.peer_certificates.as_deref();
+    }
+
+    /// The counters of the record layer so far
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> Metrics {
+        return self.record_layer.metrics();
+    }
+
+    /// Draw the record layer's scratch buffers from the pool, which may serve
+    /// other connections too
+    #[cfg(feature = "buffer-pool")]
+    pub(crate) fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.record_layer.set_buffer_pool(pool);
+    }
+
+    /// The pool of the record layer's scratch buffers
+    #[cfg(feature = "buffer-pool")]
+    pub(crate) fn buffer_pool(&self) -> &BufferPool {
+        return self.record_layer.buffer_pool();
+    }
+
+    /// Report the rest of the handshake to the observer. The ClientHello has
+    /// already been queued by then and is not reported.
+    pub(crate) fn set_observer(&mut self, observer: Box<dyn HandshakeObserver + Send>) {
+        self.observer = observer;
+    }
+
+    /// Capture every record of the connection into the file at the path, in
+    /// the framing of RecordCapture. This must come before the first
+    /// write_tls, so that the queued ClientHello is captured too.
+    #[cfg(feature = "record-capture")]
+    pub(crate) fn with_record_capture(mut self, path: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let mut capture = RecordCapture::create(path)?;
+        let mut offset = 0;
+        while offset < self.outgoing.len() {
+            let length = u16::from_be_bytes([self.outgoing[offset + 3], self.outgoing[offset + 4]]);
+            let record_length = RECORD_HEADER_LEN + usize::from(length);
+            capture.write_record(&self.outgoing[offset..offset + record_length])?;
+            offset += record_length;
+        }
+        self.record_layer.set_capture(capture);
+
+        return Ok(self);
+    }
+
+    fn set_state(&mut self, state: HandshakeState) {
+        self.observer.on_state_change(self.state, state);
+        self.state = state;
+    }
+
+    fn send_handshake_message(&mut self, message: HandshakeMessage) -> Result<(), TlsError> {
+        self.observer.on_message_sent(&message);
+        let encoding: Vec<u8> = message.into();
+        self.transcript.update(&encoding);
+        let records = self
+            .record_layer
+            .write_records(ContentType::Handshake, &encoding)?;
+        self.outgoing.extend_from_slice(&records);
+
+        return Ok(());
+    }
+
+    /// Pad every sealed record from now on according to the policy
+    pub(crate) fn set_padding_policy(&mut self, padding: PaddingPolicy) {
+        self.record_layer.set_padding_policy(padding);
+    }
+
+    /// Queue the data for sending. Data larger than a record is split across
+    /// as many records as needed, each sealed under its own sequence number.
+    /// Once the write key reaches its record limit, it is updated or the data
+    /// is refused, depending on the config.
+    pub(crate) fn send_application_data(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        if self.half_closed_local {
+            return Err(TlsError::WriteAfterClose);
+        }
+        for fragment in data.chunks(TLS_PLAINTEXT_MAX_LENGTH as usize) {
+            self.enforce_record_limit()?;
+            let records = self
+                .record_layer
+                .write_records(ContentType::ApplicationData, fragment)?;
+            self.outgoing.extend_from_slice(&records);
+        }
+
+        return Ok(());
+    }
+
+    /// Make sure that the write key may seal one more application record. The
+    /// KeyUpdate itself is sealed under the old key, so it goes out while the
+    /// old key still has a record to spare.
+    fn enforce_record_limit(&mut self) -> Result<(), TlsError> {
+        let cipher_suite = match &self.key_schedule {
+            Some(key_schedule) if self.record_layer.write_epoch() == Epoch::Application => {
+                key_schedule.cipher_suite()
+            }
+            _ => return Ok(()),
+        };
+        let limit = self
+            .config
+            .records_per_key
+            .unwrap_or(aead::record_limit(cipher_suite));
+        let sequence = self.record_layer.write_sequence();
+
+        return match self.config.on_record_limit {
+            RecordLimitAction::KeyUpdate if sequence + 1 >= limit => self.send_key_update(),
+            RecordLimitAction::Error if sequence >= limit => Err(TlsError::RecordLimitReached),
+            _ => Ok(()),
+        };
+    }
+
+    /// Send KeyUpdate under the current write key, then switch to the next
+    /// client application key. The server is not asked to update its own.
+    fn send_key_update(&mut self) -> Result<(), TlsError> {
+        let key_update = HandshakeMessage {
+            msg_type: HandshakeType::KeyUpdate,
+            body: vec![KEY_UPDATE_NOT_REQUESTED],
+        };
+        self.observer.on_message_sent(&key_update);
+        let records = self
+            .record_layer
+            .write_records(ContentType::Handshake, &Vec::from(key_update))?;
+        self.outgoing.extend_from_slice(&records);
+
+        let key_schedule = self
+            .key_schedule
+            .as_mut()
+            .ok_or(TlsError::Internal("key update before the application keys"))?;
+        let cipher_suite = key_schedule.cipher_suite();
+        let (key, iv) = key_schedule.update_client_application_keys()?;
+        self.record_layer.set_write_key(
+            Epoch::Application,
+            TrafficKey::new(cipher_suite, &key, &iv)?,
+        );
+        self.observer.on_keys_derived(KeyEpoch::Application);
+
+        return Ok(());
+    }
+
+    /// Queue an alert, at the level that its description calls for
+    pub(crate) fn send_alert(&mut self, description: AlertDescription) -> Result<(), TlsError> {
+        let records = self
+            .record_layer
+            .write_records(ContentType::Alert, &Vec::from(Alert::new(description)))?;
+        self.outgoing.extend_from_slice(&records);
+
+        return Ok(());
+    }
+
+    /// Queue a close_notify alert. Sending fails from then on, while reading
+    /// continues until the peer sends its own close_notify.
+    pub(crate) fn send_close_notify(&mut self) -> Result<(), TlsError> {
+        self.send_alert(AlertDescription::CloseNotify)?;
+        self.half_closed_local = true;
+
+        return Ok(());
+    }
+
+    /// Whether close_notify has been sent
+    pub(crate) fn half_closed_local(&self) -> bool {
+        return self.half_closed_local;
+    }
+
+    /// Whether close_notify has been received
+    pub(crate) fn half_closed_remote(&self) -> bool {
+        return self.half_closed_remote;
+    }
+
+    /// Move as much received application data as fits into the buffer,
+    /// returning the number of bytes moved. The data of consecutive records
+    /// reads as one continuous stream.
+    pub(crate) fn recv_application_data(&mut self, buf: &mut [u8]) -> usize {
+        let nbytes = buf.len().min(self.received.len());
+        for (dst, src) in buf.iter_mut().zip(self.received.drain(..nbytes)) {
+            *dst = src;
+        }
+
+        return nbytes;
+    }
+
+    /// Whether there are records waiting for write_tls
+    pub(crate) fn wants_write(&self) -> bool {
+        return !self.outgoing.is_empty();
+    }
+
+    /// Write as many of the queued records as the writer accepts, returning
+    /// the number of bytes written
+    pub(crate) fn write_tls(&mut self, w: &mut impl Write) -> Result<usize, TlsError> {
+        let nbytes = w.write(&self.outgoing)?;
+        self.outgoing.drain(..nbytes);
+
+        return Ok(nbytes);
+    }
+
+    /// Move as many of the queued bytes as fit into the buffer, returning the
+    /// number of bytes moved; the rest stay queued for the next call, so that
+    /// the caller decides how much goes out at a time
+    pub(crate) fn write_tls_into(&mut self, out: &mut [u8]) -> usize {
+        let nbytes = out.len().min(self.outgoing.len());
+        out[..nbytes].copy_from_slice(&self.outgoing[..nbytes]);
+        self.outgoing.drain(..nbytes);
+
+        return nbytes;
+    }
+
+    /// Whether there is application data waiting for recv_application_data
+    pub(crate) fn has_received_data(&self) -> bool {
+        return !self.received.is_empty();
+    }
+
+    /// Read once from the reader and process every record that is now
+    /// complete. Return whether that left anything for the caller to do, or
+    /// Closed once the reader has reached its end or the peer has sent
+    /// close_notify. A record that cannot be taken off the buffer queues the
+    /// matching alert before failing, and an alert other than close_notify
+    /// fails as its error.
+    pub(crate) fn read_tls(&mut self, r: &mut impl Read) -> Result<ReadStatus, TlsError> {
+        if self.half_closed_remote {
+            return Ok(ReadStatus::Closed);
+        }
+        let nbytes = self.record_layer.read_chunk(r)?;
+        let records = match self.record_layer.take_records() {
+            Ok(records) => records,
+            Err(err) => {
+                if let Some(alert) = err.to_alert() {
+                    self.send_alert(alert.description)?;
+                }
+                return Err(err);
+            }
+        };
+        for record in records {
+            if self.half_closed_remote {
+                return Err(TlsError::UnexpectedMessage("record after close_notify"));
+            }
+            // From the handshake epoch on, handshake messages are only ever
+            // carried inside protected records
+            if record.content_type() == ContentType::Handshake
+                && self.record_layer.read_epoch() != Epoch::Plaintext
+            {
+                return Err(TlsError::UnexpectedPlaintextRecord);
+            }
+            // A server in middlebox compatibility mode sends one in the clear
+            // before its first protected record; TLS 1.3 drops it unprocessed
+            if record.content_type() == ContentType::ChangeCipherSpec {
+                if self.state == HandshakeState::Connected {
+                    return Err(TlsError::UnexpectedMessage(
+                        "change_cipher_spec after the handshake",
+                    ));
+                }
+                if record.fragment() != [0x01] {
+                    return Err(TlsError::UnexpectedMessage("invalid change_cipher_spec"));
+                }
+                continue;
+            }
+            let (content_type, content) = self.record_layer.unprotect(record)?;
+            match content_type {
+                ContentType::Handshake => {
+                    self.handshake_joiner.push(&content);
+                    while let Some(message) = self.handshake_joiner.next_message()? {
+                        self.process_handshake_message(message)?;
+                    }
+                }
+                ContentType::ApplicationData if self.state == HandshakeState::Connected => {
+                    self.received.extend(content)
+                }
+                // Never protected (RFC 8446, section 5)
+                ContentType::ChangeCipherSpec => {
+                    return Err(TlsError::UnexpectedMessage("protected change_cipher_spec"));
+                }
+                ContentType::Alert => {
+                    let alert = Alert::try_from(content.as_slice())?;
+                    if alert.description != AlertDescription::CloseNotify {
+                        return Err(TlsError::from(alert));
+                    }
+                    self.half_closed_remote = true;
+                }
+                _ => return Err(TlsError::UnexpectedMessage("unexpected record")),
+            }
+        }
+        if nbytes == 0 || (self.half_closed_remote && !self.has_received_data()) {
+            return Ok(ReadStatus::Closed);
+        }
+        if self.wants_write() || self.has_received_data() {
+            return Ok(ReadStatus::Ready(nbytes));
+        }
+
+        return Ok(ReadStatus::Blocked(nbytes));
+    }
+
+    /// Process the protected records of the server flight after ServerHello,
+    /// for a caller that splits the records off the wire itself. Each record
+    /// is opened, the handshake messages are reassembled across records, and
+    /// each message is processed in turn. Return the messages so processed.
+    pub(crate) fn process_encrypted_flight(
+        &mut self,
+        records: &[TLSCiphertext<Vec<u8>>],
+    ) -> Result<Vec<HandshakeMessage>, TlsError> {
+        let mut messages = vec![];
+        for record in records {
+            let (content_type, content) = self.record_layer.open(record)?;
+            if content_type != ContentType::Handshake {
+                return Err(TlsError::UnexpectedMessage(
+                    "encrypted flight carries a record other than handshake",
+                ));
+            }
+            self.handshake_joiner.push(&content);
+            while let Some(message) = self.handshake_joiner.next_message()? {
+                messages.push(message.clone());
+                self.process_handshake_message(message)?;
+            }
+        }
+
+        return Ok(messages);
+    }
+
+    /// Advance the handshake with the next message from the server. Each
+    /// message joins the transcript once it has been processed, except for
+    /// ServerHello, whose processing needs a transcript that includes it.
+    fn process_handshake_message(&mut self, message: HandshakeMessage) -> Result<(), TlsError> {
+        self.observer.on_message_received(&message);
+        let encoding: Vec<u8> = message.clone().into();
+        match (self.state, message.msg_type) {
+            (HandshakeState::WaitServerHello, HandshakeType::ServerHello)
+                if ServerHello::try_from(message.body.as_slice())?.is_hello_retry_request() =>
+            {
+                return self.process_hello_retry_request(&message.body, &encoding);
+            }
+            (HandshakeState::WaitServerHello, HandshakeType::ServerHello) => {
+                self.transcript.update(&encoding);
+                self.process_server_hello(&message.body)?;
+                self.handshake_joiner.end_epoch()?;
+                self.set_state(HandshakeState::WaitEncryptedExtensions);
+                return Ok(());
+            }
+            (HandshakeState::WaitEncryptedExtensions, HandshakeType::EncryptedExtensions) => {
+                self.process_encrypted_extensions(&message.body)?;
+                self.set_state(HandshakeState::WaitCertificate);
+            }
+            (HandshakeState::WaitCertificate, HandshakeType::Certificate) => {
+                self.process_certificate(&message.body)?;
+                self.set_state(HandshakeState::WaitCertificateVerify);
+            }
+            (HandshakeState::WaitCertificateVerify, HandshakeType::CertificateVerify) => {
+                self.process_certificate_verify(&message.body)?;
+                self.set_state(HandshakeState::WaitFinished);
+            }
+            (HandshakeState::WaitFinished, HandshakeType::Finished) => {
+                self.process_server_finished(&message.body, &encoding)?;
+                self.handshake_joiner.end_epoch()?;
+                self.set_state(HandshakeState::Connected);
+                return Ok(());
+            }
+            // Tickets are not stored yet, so there is nothing to resume
+            // with; the message is after the handshake and not in the
+            // transcript
+            (HandshakeState::Connected, HandshakeType::NewSessionTicket) => return Ok(()),
+            _ => return Err(TlsError::UnexpectedMessage("unexpected handshake message")),
+        }
+        self.transcript.update(&encoding);
+
+        return Ok(());
+    }
+
+    /// The obfuscated_ticket_age of a PSK identity (RFC 8446, section
+    /// 4.2.11): the milliseconds since the ticket was received, by the
+    /// config's clock, plus the ticket's ticket_age_add, modulo 2^32
+    fn obfuscated_ticket_age(&self, received_at_millis: u64, ticket_age_add: u32) -> u32 {
+        let age = self
+            .config
+            .clock
+            .now_millis()
+            .saturating_sub(received_at_millis);
+
+        return (age as u32).wrapping_add(ticket_age_add);
+    }
+
+    /// The traffic secrets derived so far, for building test vectors. The
+    /// client application secret is that of the current generation.
+    #[cfg(feature = "dangerous")]
+    pub(crate) fn dump_secrets(&self) -> Secrets {
+        return self
+            .key_schedule
+            .as_ref()
+            .map(KeySchedule::dump_secrets)
+            .unwrap_or_default();
+    }
+
+    /// The AEAD key and IV that the client seals records with in the current
+    /// epoch, for decrypting captured traffic by hand. None before ServerHello.
+    #[cfg(feature = "dangerous")]
+    pub(crate) fn client_traffic_key_iv(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (client_key, client_iv, _, _) = self.current_traffic_keys()?;
+        return Some((client_key, client_iv));
+    }
+
+    /// The AEAD key and IV that the server seals records with in the current
+    /// epoch. None before ServerHello.
+    #[cfg(feature = "dangerous")]
+    pub(crate) fn server_traffic_key_iv(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (_, _, server_key, server_iv) = self.current_traffic_keys()?;
+        return Some((server_key, server_iv));
+    }
+
+    /// The application keys once the server Finished has been processed, the
+    /// handshake keys before that
+    #[cfg(feature = "dangerous")]
+    fn current_traffic_keys(&self) -> Option<TrafficKeys> {
+        let key_schedule = self.key_schedule.as_ref()?;
+        let cipher_suite = key_schedule.cipher_suite();
+        return key_schedule
+            .application_keys(cipher_suite)
+            .or_else(|_| key_schedule.handshake_keys(cipher_suite))
+            .ok();
+    }
+
+    /// The shared secret of the server's key share, i.e. the data of the
+    /// key_share extension in ServerHello: the group, then the length-prefixed
+    /// public key. The server must pick one of the key shares the client sent;
+    /// picking a group the client has no key share for is illegal_parameter.
+    fn key_exchange(&mut self, server_key_share: &[u8]) -> Result<Vec<u8>, TlsError> {
+        let KeyShareEntry {
+            group,
+            key_exchange,
+        } = KeyShare::parse_server_hello(server_key_share)?;
+        let private_key = match self
+            .key_shares
+            .iter()
+            .position(|private_key| private_key.group() == group)
+        {
+            Some(index) => self.key_shares.swap_remove(index),
+            None => {
+                return Err(TlsError::IllegalParameter(
+                    "server key share uses a group that was not offered",
+                ))
+            }
+        };
+
+        return private_key.agree(&key_exchange);
+    }
+
+    /// The server wants a key share for another group. The first ClientHello
+    /// collapses into a message_hash in the transcript (RFC 8446, section
+    /// 4.4.1), and a second ClientHello goes out that differs from the first
+    /// only in its key share and in echoing the server's cookie, if any.
+    fn process_hello_retry_request(
+        &mut self,
+        body: &[u8],
+        encoding: &[u8],
+    ) -> Result<(), TlsError> {
+        if self.hello_retry_cipher_suite.is_some() {
+            return Err(TlsError::UnexpectedMessage("second HelloRetryRequest"));
+        }
+        let hello_retry_request = ServerHello::try_from(body)?;
+        require_tls13(&hello_retry_request)?;
+        self.check_session_id_echo(&hello_retry_request)?;
+        let cipher_suite = hello_retry_request.cipher_suite;
+        if !self.config.cipher_suites.contains(&cipher_suite) {
+            return Err(TlsError::IllegalParameter(
+                "HelloRetryRequest picked a cipher suite that was not offered",
+            ));
+        }
+        let key_share = hello_retry_request
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::KeyShare)
+            .ok_or(TlsError::MissingExtension(ExtensionType::KeyShare))?;
+        if key_share.extension_data.len() != 2 {
+            return Err(TlsError::DecodeError("invalid selected group"));
+        }
+        let group = NamedGroup::from(u16::from_be_bytes([
+            key_share.extension_data[0],
+            key_share.extension_data[1],
+        ]));
+        if !self.config.supported_groups.contains(&group)
+            || self
+                .key_shares
+                .iter()
+                .any(|offered| offered.group() == group)
+        {
+            return Err(TlsError::IllegalParameter(
+                "HelloRetryRequest selected a group that cannot be used",
+            ));
+        }
+
+        self.transcript
+            .replace_with_message_hash(hash_algorithm(cipher_suite));
+        self.transcript.update(encoding);
+
+        let (private_key, public_key) = {
+            let mut rng = self.config.rng.lock().unwrap();
+            KeySharePrivate::generate(group, &mut **rng)?
+        };
+        let mut extensions: Vec<Extension> = self
+            .client_hello_extensions
+            .iter()
+            .map(|extension| match extension.extension_type {
+                ExtensionType::KeyShare => handshake::key_share(&[(group, public_key.clone())]),
+                _ => extension.clone(),
+            })
+            .collect();
+        if let Some(cookie) = hello_retry_request
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::Cookie)
+        {
+            extensions.push(Extension {
+                extension_type: ExtensionType::Cookie,
+                extension_data: Cow::Owned(cookie.extension_data.to_vec()),
+            });
+        }
+        let client_hello = handshake::build_client_hello(
+            &self.random,
+            &self.legacy_session_id,
+            &self.cipher_suites,
+            extensions,
+        );
+        self.key_shares = vec![private_key];
+        self.hello_retry_cipher_suite = Some(cipher_suite);
+
+        return self.send_handshake_message(client_hello);
+    }
+
+    /// ServerHello and HelloRetryRequest repeat the session id of ClientHello,
+    /// whether or not it is empty (RFC 8446, section 4.1.3)
+    fn check_session_id_echo(&self, server_hello: &ServerHello) -> Result<(), TlsError> {
+        if server_hello.legacy_session_id_echo != self.legacy_session_id.as_slice() {
+            return Err(TlsError::IllegalParameter(
+                "legacy_session_id_echo does not match the session id",
+            ));
+        }
+
+        return Ok(());
+    }
+
+    /// The shared secret of the key exchange leads to the handshake traffic
+    /// keys, and everything the server sends from here on is protected with
+    /// its handshake key.
+    fn process_server_hello(&mut self, body: &[u8]) -> Result<(), TlsError> {
+        let server_hello = ServerHello::try_from(body)?;
+        require_tls13(&server_hello)?;
+        self.check_session_id_echo(&server_hello)?;
+        let key_share = server_hello
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::KeyShare)
+            .ok_or(TlsError::MissingExtension(ExtensionType::KeyShare))?;
+        let cipher_suite = server_hello.cipher_suite;
+        if !self.config.cipher_suites.contains(&cipher_suite) {
+            return Err(TlsError::IllegalParameter(
+                "ServerHello picked a cipher suite that was not offered",
+            ));
+        }
+        if self
+            .hello_retry_cipher_suite
+            .is_some_and(|retry_cipher_suite| retry_cipher_suite != cipher_suite)
+        {
+            return Err(TlsError::IllegalParameter(
+                "ServerHello changed the cipher suite of the HelloRetryRequest",
+            ));
+        }
+        if let Some(index) = server_hello.selected_psk_identity()? {
+            if usize::from(index) >= self.psk_identities.len() {
+                return Err(TlsError::IllegalParameter(
+                    "ServerHello selected a PSK that was not offered",
+                ));
+            }
+        }
+        let shared_secret = self.key_exchange(&key_share.extension_data)?;
+        self.transcript.start_hash(hash_algorithm(cipher_suite));
+
+        let mut key_schedule = KeySchedule::new(cipher_suite);
+        key_schedule.derive_handshake_secrets(
+            &shared_secret,
+            &self.transcript.current_hash(hash_algorithm(cipher_suite)),
+        )?;
+        let (_, _, server_key, server_iv) = key_schedule.handshake_keys(cipher_suite)?;
+        self.record_layer.set_read_key(
+            Epoch::Handshake,
+            TrafficKey::new(cipher_suite, &server_key, &server_iv)?,
+        );
+        self.key_schedule = Some(key_schedule);
+        self.observer.on_keys_derived(KeyEpoch::Handshake);
+        self.negotiated_extensions.extend(
+            server_hello
+                .extensions
+                .iter()
+                .map(|extension| extension.extension_type),
+        );
+
+        return Ok(());
+    }
+
+    /// Every extension in EncryptedExtensions must answer one that the client
+    /// offered, except for GREASE
+    fn process_encrypted_extensions(&mut self, body: &[u8]) -> Result<(), TlsError> {
+        for extension in handshake::parse_encrypted_extensions(body)? {
+            // RFC 8701, section 3.1: the client ignores GREASE from the
+            // server
+            if constants::is_grease(u16::from(extension.extension_type)) {
+                continue;
+            }
+            if !self.offered_extensions.contains(&extension.extension_type) {
+                return Err(TlsError::UnsupportedExtension(extension.extension_type));
+            }
+            match extension.extension_type {
+                ExtensionType::ApplicationLayerProtocolNegotiation => {
+                    let protocol = handshake::parse_alpn_selection(&extension.extension_data)?;
+                    if !self
+                        .config
+                        .alpn_protocols
+                        .iter()
+                        .any(|offered| offered == protocol)
+                    {
+                        return Err(TlsError::IllegalParameter(
+                            "server selected a protocol that was not offered",
+                        ));
+                    }
+                    self.alpn_protocol = Some(protocol.to_vec());
+                }
+                ExtensionType::ServerName => {
+                    if !extension.extension_data.is_empty() {
+                        return Err(TlsError::DecodeError(
+                            "server_name acknowledgement must be empty",
+                        ));
+                    }
+                    self.server_name_acknowledged = true;
+                }
+                _ => {}
+            }
+            self.negotiated_extensions.push(extension.extension_type);
+        }
+
+        return Ok(());
+    }
+
+    /// The chain must not be empty, and the verifier must trust it for the
+    /// server name
+    fn process_certificate(&mut self, body: &[u8]) -> Result<(), TlsError> {
+        let entries = handshake::parse_certificate(body)?;
+        let (end_entity, intermediates) = match entries.split_first() {
+            Some(chain) => chain,
+            None => return Err(TlsError::DecodeError("empty certificate list")),
+        };
+        let intermediates: Vec<&[u8]> = intermediates
+            .iter()
+            .map(|entry| entry.cert_data.as_slice())
+            .collect();
+
+        self.config.verifier.verify_server_cert(
+            &end_entity.cert_data,
+            &intermediates,
+            &self.server_name,
+            UNIX_EPOCH + Duration::from_millis(self.config.clock.now_millis()),
+        )?;
+        self.peer_certificates = Some(entries.into_iter().map(|entry| entry.cert_data).collect());
+
+        return Ok(());
+    }
+
+    /// The scheme must be one that the client offered, and the signature must
+    /// verify with the key of the end-entity certificate over the transcript
+    /// up to Certificate
+    fn process_certificate_verify(&mut self, body: &[u8]) -> Result<(), TlsError> {
+        let certificate_verify = CertificateVerify::try_from(body)?;
+        if !self
+            .config
+            .signature_schemes
+            .contains(&certificate_verify.scheme)
+        {
+            return Err(TlsError::IllegalParameter(
+                "CertificateVerify scheme was not offered",
+            ));
+        }
+        let end_entity = self
+            .peer_certificates
+            .as_ref()
+            .and_then(|chain| chain.first())
+            .ok_or(TlsError::Internal("CertificateVerify before Certificate"))?;
+        let cipher_suite = self
+            .key_schedule
+            .as_ref()
+            .ok_or(TlsError::Internal(
+                "CertificateVerify before the key schedule",
+            ))?
+            .cipher_suite();
+
+        return verify::verify_server_signature(
+            end_entity,
+            certificate_verify.scheme,
+            &certificate_verify.signature,
+            &self.transcript.current_hash(hash_algorithm(cipher_suite)),
+        );
+    }
+
+    /// The server Finished must verify against the transcript before it; the
+    /// transcript including it then yields the application traffic keys. The
+    /// client answers with its own Finished under its handshake key, and
+    /// every record after that is protected with the application keys.
+    fn process_server_finished(&mut self, body: &[u8], encoding: &[u8]) -> Result<(), TlsError> {
+        let key_schedule = self.key_schedule.as_mut().ok_or(TlsError::Internal(
+            "server Finished before the key schedule",
+        ))?;
+        let cipher_suite = key_schedule.cipher_suite();
+        let algorithm = hash_algorithm(cipher_suite);
+        key_schedule.verify_server_finished(&self.transcript.current_hash(algorithm), body)?;
+        self.transcript.update(encoding);
+
+        let handshake_hash = self.transcript.current_hash(algorithm);
+        key_schedule.derive_application_secrets(&handshake_hash)?;
+        let (client_handshake_key, client_handshake_iv, _, _) =
+            key_schedule.handshake_keys(cipher_suite)?;
+        let (client_key, client_iv, server_key, server_iv) =
+            key_schedule.application_keys(cipher_suite)?;
+        let finished = HandshakeMessage::from(Finished {
+            verify_data: key_schedule.client_finished_verify_data(&handshake_hash)?,
+        });
+
+        self.record_layer.set_read_key(
+            Epoch::Application,
+            TrafficKey::new(cipher_suite, &server_key, &server_iv)?,
+        );
+        self.record_layer.set_write_key(
+            Epoch::Handshake,
+            TrafficKey::new(cipher_suite, &client_handshake_key, &client_handshake_iv)?,
+        );
+        self.send_handshake_message(finished)?;
+        self.record_layer.set_write_key(
+            Epoch::Application,
+            TrafficKey::new(cipher_suite, &client_key, &client_iv)?,
+        );
+        self.observer.on_keys_derived(KeyEpoch::Application);
+
+        return Ok(());
+    }
+
+    /// Drive the handshake over a blocking socket until the connection is
+    /// established, flushing the client Finished before returning. The
+    /// timeout is checked between reads, so a read that blocks for good needs
+    /// a read timeout on the socket itself.
+    pub(crate) fn handshake(
+        &mut self,
+        sock: &mut (impl Read + Write),
+        timeout: Option<Duration>,
+    ) -> Result<(), TlsError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while self.state != HandshakeState::Connected {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            self.write_all_tls(sock)?;
+            if self.read_tls(sock)? == ReadStatus::Closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during the handshake",
+                )
+                .into());
+            }
+        }
+        self.write_all_tls(sock)?;
+
+        return Ok(());
+    }
+
+    /// Write queued records until there are none left
+    fn write_all_tls(&mut self, w: &mut impl Write) -> io::Result<()> {
+        while self.wants_write() {
+            if self.write_tls(w)? == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Everything a TLS 1.3 client sends first: the ClientHello record, then the
+/// compatibility ChangeCipherSpec record if the config asks for it, ready to
+/// be written to the socket. The connection that would continue the handshake
+/// is dropped, key shares and all.
+#[allow(dead_code)]
+pub(crate) fn client_first_flight(
+    config: Arc<ClientConfig>,
+    server_name: &str,
+) -> Result<Vec<u8>, TlsError> {
+    let conn = ClientConnection::new(config, server_name)?;
+    return Ok(conn.outgoing);
+}
+
+/// A connection paired with its socket, so that the two together can be used
+/// wherever std::io::Read and std::io::Write are expected, similar to
+/// rustls::Stream
+#[allow(dead_code)]
+pub(crate) struct Stream<'a, T: Read + Write> {
+    pub(crate) conn: &'a mut ClientConnection,
+    pub(crate) sock: &'a mut T,
+}
+
+#[allow(dead_code)]
+impl<'a, T: Read + Write> Stream<'a, T> {
+    pub(crate) fn new(conn: &'a mut ClientConnection, sock: &'a mut T) -> Self {
+        return Self { conn, sock };
+    }
+}
+
+/// Pull records from the socket until there is application data to return.
+/// Reaching the end of the socket reads as the end of the stream.
+impl<'a, T: Read + Write> Read for Stream<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.conn.write_all_tls(self.sock)?;
+        while !self.conn.has_received_data() {
+            if self.conn.read_tls(self.sock)? == ReadStatus::Closed {
+                return Ok(0);
+            }
+        }
+
+        return Ok(self.conn.recv_application_data(buf));
+    }
+}
+
+/// Every write is sealed and flushed to the socket before returning
+impl<'a, T: Read + Write> Write for Stream<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.conn.send_application_data(buf)?;
+        self.conn.write_all_tls(self.sock)?;
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.write_all_tls(self.sock)?;
+        return self.sock.flush();
+    }
+}
+
+/// The legacy version of a TLS 1.3 ServerHello is frozen at TLS 1.2, so the
+/// version is only ever selected in supported_versions. A server that leaves
+/// it out, or selects anything but TLS 1.3 in it, is illegal_parameter.
+fn require_tls13(server_hello: &ServerHello) -> Result<(), TlsError> {
+    let version = server_hello.negotiated_version()?;
+    if !server_hello
+        .extensions
+        .iter()
+        .any(|extension| extension.extension_type == ExtensionType::SupportedVersions)
+    {
+        return Err(TlsError::IllegalParameter(
+            "server hello without supported_versions",
+        ));
+    }
+
+    return match version {
+        ProtocolVersion::TLSv1_3 => Ok(()),
+        #[cfg(feature = "draft-versions")]
+        ProtocolVersion::DraftVersion(_) => Ok(()),
+        _ => Err(TlsError::IllegalParameter("server did not select TLS 1.3")),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alert::AlertLevel;
+    use crate::clock::FixedClock;
+    use crate::constants::{ProtocolVersion, SignatureScheme};
+    use crate::crypto::transcript::TranscriptMode;
+    use crate::crypto::Rng;
+    use crate::error::ErrorCategory;
+    use crate::handshake::HELLO_RETRY_REQUEST_RANDOM;
+    use crate::record_layer::seal_record;
+    use crate::verify::ServerCertVerifier;
+    use ring::signature::Ed25519KeyPair;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    fn connection(config: ClientConfig) -> ClientConnection {
+        return ClientConnection::new(Arc::new(config), "localhost").unwrap();
+    }
+
+    /// The default config, told to trust the self-signed certificate of
+    /// TestServer
+    fn trust_test_server() -> ClientConfig {
+        let mut config = ClientConfig::default();
+        config.dangerous().with_no_cert_verification();
+        return config;
+    }
+
+    fn test_traffic_key(byte: u8) -> TrafficKey {
+        return TrafficKey::new(CipherSuite::Aes128GcmSha256, &[byte; 16], &[byte; 12]).unwrap();
+    }
+
+    /// Two connections whose keys mirror each other, as if they had just
+    /// completed a handshake together
+    fn connected_pair() -> (ClientConnection, ClientConnection) {
+        let mut left = connection(ClientConfig::default());
+        let mut right = connection(ClientConfig::default());
+        for conn in [&mut left, &mut right] {
+            conn.outgoing.clear();
+            conn.state = HandshakeState::Connected;
+        }
+        left.record_layer
+            .set_write_key(Epoch::Application, test_traffic_key(1));
+        left.record_layer
+            .set_read_key(Epoch::Application, test_traffic_key(2));
+        right
+            .record_layer
+            .set_write_key(Epoch::Application, test_traffic_key(2));
+        right
+            .record_layer
+            .set_read_key(Epoch::Application, test_traffic_key(1));
+
+        return (left, right);
+    }
+
+    /// Move every queued byte from one connection to the other, returning the
+    /// number of records that went over the wire
+    fn transfer(from: &mut ClientConnection, to: &mut ClientConnection) -> usize {
+        let mut wire = vec![];
+        while from.wants_write() {
+            from.write_tls(&mut wire).unwrap();
+        }
+        let mut nrecords = 0;
+        let mut offset = 0;
+        while offset < wire.len() {
+            let length = u16::from_be_bytes([wire[offset + 3], wire[offset + 4]]);
+            offset += 5 + usize::from(length);
+            nrecords += 1;
+        }
+
+        let mut wire = Cursor::new(wire);
+        while (wire.position() as usize) < wire.get_ref().len() {
+            to.read_tls(&mut wire).unwrap();
+        }
+
+        return nrecords;
+    }
+
+    /// Drain everything the connection has received so far
+    fn recv_all(conn: &mut ClientConnection) -> Vec<u8> {
+        let mut received = vec![];
+        let mut buf = [0; 4096];
+        loop {
+            let nbytes = conn.recv_application_data(&mut buf);
+            if nbytes == 0 {
+                return received;
+            }
+            received.extend_from_slice(&buf[..nbytes]);
+        }
+    }
+
+    #[test]
+    fn send_application_data_across_many_records() {
+        let (mut sender, mut receiver) = connected_pair();
+        let data: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        sender.send_application_data(&data).unwrap();
+
+        assert_eq!(transfer(&mut sender, &mut receiver), 7);
+        assert_eq!(recv_all(&mut receiver), data);
+    }
+
+    #[test]
+    fn reassemble_message_fragmented_by_sender() {
+        let (mut sender, mut receiver) = connected_pair();
+        for fragment in [
+            b"GET / HTTP/1.1\r\n".as_slice(),
+            b"Host: ",
+            b"example.com\r\n\r\n",
+        ] {
+            sender.send_application_data(fragment).unwrap();
+        }
+        assert_eq!(transfer(&mut sender, &mut receiver), 3);
+
+        let mut buf = [0; 64];
+        let nbytes = receiver.recv_application_data(&mut buf);
+        assert_eq!(
+            &buf[..nbytes],
+            b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+        );
+        assert_eq!(receiver.recv_application_data(&mut buf), 0);
+    }
+
+    /// One end of a loopback transport: reads come from what the peer wrote
+    /// beforehand, and writes are collected for the peer to read
+    struct Loopback {
+        inbound: Cursor<Vec<u8>>,
+        outbound: Vec<u8>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            return self.inbound.read(buf);
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            return self.outbound.write(buf);
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    /// A loopback transport with the test server at the other end, which
+    /// answers the client's first flight as soon as the client reads
+    struct ServerSocket {
+        server: Option<TestServer>,
+        inbound: Cursor<Vec<u8>>,
+        client_flight: Vec<u8>,
+    }
+
+    impl Read for ServerSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.server.is_none() && !self.client_flight.is_empty() {
+                let (server, server_flight) = TestServer::accept(&self.client_flight);
+                self.server = Some(server);
+                self.inbound = Cursor::new(server_flight);
+                self.client_flight.clear();
+            }
+            return self.inbound.read(buf);
+        }
+    }
+
+    impl Write for ServerSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            return self.client_flight.write(buf);
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn handshake_over_blocking_socket() {
+        let mut conn = connection(trust_test_server());
+        let mut sock = ServerSocket {
+            server: None,
+            inbound: Cursor::new(vec![]),
+            client_flight: vec![],
+        };
+        conn.handshake(&mut sock, None).unwrap();
+        assert_eq!(conn.state(), HandshakeState::Connected);
+
+        // The client Finished has been flushed for the server to check
+        sock.server.unwrap().finish(&sock.client_flight);
+
+        let mut conn = connection(trust_test_server());
+        let mut sock = Loopback {
+            inbound: Cursor::new(vec![]),
+            outbound: vec![],
+        };
+        assert!(matches!(
+            conn.handshake(&mut sock, None),
+            Err(TlsError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(
+            conn.handshake(&mut sock, Some(Duration::ZERO)),
+            Err(TlsError::Io(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+    }
+
+    #[test]
+    fn stream_write_all_then_read_to_end() {
+        let (mut client, mut server) = connected_pair();
+        let mut sock = Loopback {
+            inbound: Cursor::new(vec![]),
+            outbound: vec![],
+        };
+        Stream::new(&mut client, &mut sock)
+            .write_all(b"ping")
+            .unwrap();
+
+        server.read_tls(&mut sock.outbound.as_slice()).unwrap();
+        assert_eq!(recv_all(&mut server), b"ping");
+        server.send_application_data(b"pong").unwrap();
+        server.send_application_data(b"pong").unwrap();
+        let mut reply = vec![];
+        server.write_all_tls(&mut reply).unwrap();
+        sock.inbound = Cursor::new(reply);
+
+        let mut received = vec![];
+        Stream::new(&mut client, &mut sock)
+            .read_to_end(&mut received)
+            .unwrap();
+        assert_eq!(received, b"pongpong");
+    }
+
+    #[test]
+    fn skip_empty_application_data_records() {
+        let (mut client, _) = connected_pair();
+        let mut server_key = test_traffic_key(2);
+        let mut wire: Vec<u8> = vec![];
+        for (sequence, content) in [b"".as_slice(), b"", b"data"].into_iter().enumerate() {
+            let record = seal_record(
+                &mut server_key,
+                sequence as u64,
+                ContentType::ApplicationData,
+                content,
+                &PaddingPolicy::None,
+            )
+            .unwrap();
+            wire.extend_from_slice(&Vec::<u8>::from(record));
+        }
+        let mut sock = Loopback {
+            inbound: Cursor::new(wire),
+            outbound: vec![],
+        };
+
+        let mut buf = [0; 16];
+        let nbytes = Stream::new(&mut client, &mut sock).read(&mut buf).unwrap();
+        assert_eq!(&buf[..nbytes], b"data");
+    }
+
+    #[test]
+    fn padded_records_still_carry_the_data() {
+        let (mut sender, mut receiver) = connected_pair();
+        sender.set_padding_policy(PaddingPolicy::PadToBlock(256));
+        sender.send_application_data(b"0123456789").unwrap();
+
+        // header || 256-byte inner plaintext || tag
+        assert_eq!(sender.outgoing.len(), 5 + 256 + 16);
+        transfer(&mut sender, &mut receiver);
+        assert_eq!(recv_all(&mut receiver), b"0123456789");
+    }
+
+    #[test]
+    fn half_close_then_keep_reading() {
+        let (mut client, mut server) = connected_pair();
+        client.send_application_data(b"request").unwrap();
+        client.send_close_notify().unwrap();
+        assert!(client.half_closed_local());
+        assert!(matches!(
+            client.send_application_data(b"more"),
+            Err(TlsError::WriteAfterClose)
+        ));
+        transfer(&mut client, &mut server);
+        assert!(server.half_closed_remote());
+        assert_eq!(recv_all(&mut server), b"request");
+
+        // The server can still answer, and the client can still read it
+        server.send_application_data(b"response").unwrap();
+        server.send_close_notify().unwrap();
+        transfer(&mut server, &mut client);
+        assert!(client.half_closed_remote());
+        assert_eq!(recv_all(&mut client), b"response");
+        assert_eq!(
+            client.read_tls(&mut [0; 8].as_slice()).unwrap(),
+            ReadStatus::Closed
+        );
+    }
+
+    #[test]
+    fn over_length_ciphertext_is_record_overflow() {
+        let (mut client, mut server) = connected_pair();
+        // An application data record one byte longer than 2^14 + 256
+        let mut wire = vec![0x17, 0x03, 0x03, 0x41, 0x01];
+        wire.resize(5 + 0x4101, 0);
+
+        assert!(matches!(
+            client.read_tls(&mut wire.as_slice()),
+            Err(TlsError::RecordOverflow)
+        ));
+        let mut wire = vec![];
+        client.write_all_tls(&mut wire).unwrap();
+        let record = server
+            .record_layer
+            .read_from(&mut wire.as_slice())
+            .unwrap()
+            .remove(0);
+        let (content_type, content) = server.record_layer.unprotect(record).unwrap();
+        assert_eq!(content_type, ContentType::Alert);
+        assert_eq!(
+            Alert::try_from(content.as_slice()).unwrap(),
+            Alert::new(AlertDescription::RecordOverflow)
+        );
+    }
+
+    #[test]
+    fn emit_alerts_at_their_level() {
+        let (mut client, mut server) = connected_pair();
+        client.send_alert(AlertDescription::DecodeError).unwrap();
+        client.send_close_notify().unwrap();
+        let mut wire = vec![];
+        client.write_all_tls(&mut wire).unwrap();
+
+        server
+            .record_layer
+            .read_chunk(&mut wire.as_slice())
+            .unwrap();
+        let alerts: Vec<Alert> = server
+            .record_layer
+            .take_records()
+            .unwrap()
+            .into_iter()
+            .map(|record| {
+                let (content_type, content) = server.record_layer.unprotect(record).unwrap();
+                assert_eq!(content_type, ContentType::Alert);
+                Alert::try_from(content.as_slice()).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                Alert {
+                    level: AlertLevel::Fatal,
+                    description: AlertDescription::DecodeError,
+                },
+                Alert {
+                    level: AlertLevel::Warning,
+                    description: AlertDescription::CloseNotify,
+                },
+            ]
+        );
+    }
+
+    /// Wrap the messages in a single plaintext handshake record
+    fn handshake_record(messages: Vec<HandshakeMessage>) -> Vec<u8> {
+        let fragment: Vec<u8> = messages.into_iter().flat_map(Vec::<u8>::from).collect();
+        let mut record = vec![0x16, 0x03, 0x03];
+        record.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+        record.extend_from_slice(&fragment);
+
+        return record;
+    }
+
+    fn server_hello() -> HandshakeMessage {
+        return server_hello_with_key_share(NamedGroup::X25519, &[0x09; 32]);
+    }
+
+    fn server_hello_with_key_share(group: NamedGroup, key_exchange: &[u8]) -> HandshakeMessage {
+        return server_hello_with_extensions(
+            &[0x22; RANDOM_LEN],
+            vec![
+                handshake::selected_version(ProtocolVersion::TLSv1_3),
+                handshake::server_key_share(group, key_exchange),
+            ],
+        );
+    }
+
+    /// A HelloRetryRequest asking for a key share for the group
+    fn hello_retry_request(group: NamedGroup) -> HandshakeMessage {
+        return server_hello_with_extensions(
+            &HELLO_RETRY_REQUEST_RANDOM,
+            vec![
+                handshake::selected_version(ProtocolVersion::TLSv1_3),
+                Extension::raw(
+                    u16::from(ExtensionType::KeyShare),
+                    u16::from(group).to_be_bytes().to_vec(),
+                ),
+            ],
+        );
+    }
+
+    fn server_hello_with_extensions(
+        random: &[u8; RANDOM_LEN],
+        extensions: Vec<Extension>,
+    ) -> HandshakeMessage {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(random);
+        body.extend_from_slice(&[0x00, 0x13, 0x01, 0x00]); // session id, suite, compression
+        let extensions: Vec<u8> = extensions.into_iter().flat_map(Vec::<u8>::from).collect();
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        return HandshakeMessage {
+            msg_type: HandshakeType::ServerHello,
+            body,
+        };
+    }
+
+    /// Read ServerHello off the wire, then process the message after it. That
+    /// one would be protected under the server's handshake key, which the
+    /// test does not know, so it skips the record layer.
+    fn read_server_hello_then(
+        conn: &mut ClientConnection,
+        message: HandshakeMessage,
+    ) -> Result<(), TlsError> {
+        conn.read_tls(&mut handshake_record(vec![server_hello()]).as_slice())?;
+        return conn.process_handshake_message(message);
+    }
+
+    #[test]
+    fn drain_outgoing_in_small_chunks() {
+        let (mut client, _) = connected_pair();
+        client.send_application_data(&[0x5a; 40_000]).unwrap();
+        let expected = client.outgoing.clone();
+
+        let mut drained = vec![];
+        let mut chunk = [0; 1000];
+        loop {
+            let nbytes = client.write_tls_into(&mut chunk);
+            if nbytes == 0 {
+                break;
+            }
+            drained.extend_from_slice(&chunk[..nbytes]);
+        }
+        assert_eq!(drained, expected);
+        assert!(!client.wants_write());
+    }
+
+    #[test]
+    fn partial_flight_is_blocked() {
+        let mut conn = connection(ClientConfig::default());
+        conn.write_all_tls(&mut vec![]).unwrap();
+        // ServerHello, then only the header of the next record
+        let mut flight = handshake_record(vec![server_hello()]);
+        flight.extend_from_slice(&[0x17, 0x03, 0x03, 0x00, 0x20]);
+
+        assert_eq!(
+            conn.read_tls(&mut flight.as_slice()).unwrap(),
+            ReadStatus::Blocked(flight.len())
+        );
+        assert_eq!(conn.state(), HandshakeState::WaitEncryptedExtensions);
+        assert_eq!(
+            conn.read_tls(&mut [].as_slice()).unwrap(),
+            ReadStatus::Closed
+        );
+    }
+
+    fn encrypted_extensions_with_alpn() -> HandshakeMessage {
+        let extension: Vec<u8> =
+            handshake::application_layer_protocol_negotiation(&[b"h2".to_vec()]).into();
+        let mut body = (extension.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(&extension);
+
+        return HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body,
+        };
+    }
+
+    #[test]
+    fn check_selected_psk_against_offered_identities() {
+        let server_hello = server_hello_with_extensions(
+            &[0x22; RANDOM_LEN],
+            vec![
+                handshake::selected_version(ProtocolVersion::TLSv1_3),
+                handshake::server_key_share(NamedGroup::X25519, &[0x09; 32]),
+                Extension::raw(u16::from(ExtensionType::PreSharedKey), vec![0x00, 0x00]),
+            ],
+        );
+        let flight = handshake_record(vec![server_hello]);
+
+        let mut conn = connection(ClientConfig::default());
+        assert!(matches!(
+            conn.read_tls(&mut flight.as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+
+        let mut conn = connection(ClientConfig::default());
+        conn.psk_identities.push(b"ticket".to_vec());
+        conn.read_tls(&mut flight.as_slice()).unwrap();
+        assert_eq!(conn.state(), HandshakeState::WaitEncryptedExtensions);
+    }
+
+    #[test]
+    fn reject_cipher_suite_that_was_not_offered() {
+        let mut server_hello = server_hello();
+        // The suite follows the version, the random and an empty session id
+        server_hello.body[35..37].copy_from_slice(&[0x13, 0x02]);
+        let flight = handshake_record(vec![server_hello]);
+
+        let mut conn = connection(ClientConfig {
+            cipher_suites: vec![CipherSuite::Aes128GcmSha256],
+            ..ClientConfig::default()
+        });
+        let err = conn.read_tls(&mut flight.as_slice()).unwrap_err();
+        assert!(matches!(
+            err,
+            TlsError::IllegalParameter("ServerHello picked a cipher suite that was not offered")
+        ));
+        assert_eq!(
+            err.to_alert(),
+            Some(Alert::new(AlertDescription::IllegalParameter))
+        );
+
+        let mut conn = connection(ClientConfig::default());
+        conn.read_tls(&mut flight.as_slice()).unwrap();
+        assert_eq!(conn.state(), HandshakeState::WaitEncryptedExtensions);
+    }
+
+    #[test]
+    fn require_supported_versions_in_server_hello() {
+        let without_supported_versions = server_hello_with_extensions(
+            &[0x22; RANDOM_LEN],
+            vec![handshake::server_key_share(NamedGroup::X25519, &[0x09; 32])],
+        );
+        let flight = handshake_record(vec![without_supported_versions]);
+        let mut conn = connection(ClientConfig::default());
+        let err = conn.read_tls(&mut flight.as_slice()).unwrap_err();
+        assert_eq!(
+            err.to_alert(),
+            Some(Alert::new(AlertDescription::IllegalParameter))
+        );
+
+        let tls12 = server_hello_with_extensions(
+            &[0x22; RANDOM_LEN],
+            vec![
+                handshake::selected_version(ProtocolVersion::TLSv1_2),
+                handshake::server_key_share(NamedGroup::X25519, &[0x09; 32]),
+            ],
+        );
+        let flight = handshake_record(vec![tls12]);
+        let mut conn = connection(ClientConfig::default());
+        assert!(matches!(
+            conn.read_tls(&mut flight.as_slice()),
+            Err(TlsError::IllegalParameter("server did not select TLS 1.3"))
+        ));
+    }
+
+    #[test]
+    fn malformed_server_hello_is_peer_misbehaviour() {
+        let mut truncated = server_hello();
+        truncated.body.truncate(40);
+        let flight = handshake_record(vec![truncated]);
+        let mut conn = connection(ClientConfig::default());
+        let err = conn.read_tls(&mut flight.as_slice()).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::PeerMisbehaved);
+
+        // Finished before ServerHello has nothing to verify against, which
+        // the state machine rules out before it gets that far
+        let mut conn = connection(ClientConfig::default());
+        let err = conn.process_server_finished(&[0; 32], &[]).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Internal);
+    }
+
+    #[test]
+    fn reject_message_straddling_key_change() {
+        // The start of EncryptedExtensions in the same plaintext record as
+        // ServerHello, to be finished under the handshake key
+        let mut flight = handshake_record(vec![server_hello()]);
+        flight.extend_from_slice(&[0x08, 0x00]);
+        let length = u16::from_be_bytes([flight[3], flight[4]]) + 2;
+        flight[3..5].copy_from_slice(&length.to_be_bytes());
+
+        let mut conn = connection(ClientConfig::default());
+        assert!(matches!(
+            conn.read_tls(&mut flight.as_slice()),
+            Err(TlsError::UnexpectedMessage(
+                "handshake message straddles a key change"
+            ))
+        ));
+    }
+
+    #[test]
+    fn obfuscated_ticket_age_by_fixed_clock() {
+        let conn = connection(ClientConfig {
+            clock: Arc::new(FixedClock(1_000_000)),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            conn.obfuscated_ticket_age(990_000, 0x1234_5678),
+            0x1234_7d88
+        );
+        // The sum wraps around
+        assert_eq!(conn.obfuscated_ticket_age(990_000, u32::MAX), 9_999);
+        // A ticket from the future has age zero
+        assert_eq!(conn.obfuscated_ticket_age(2_000_000, 7), 7);
+    }
+
+    #[test]
+    fn drop_change_cipher_spec_during_handshake() {
+        let mut conn = connection(trust_test_server());
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+        // ServerHello, then the change_cipher_spec, then EncryptedExtensions
+        // and the rest of the flight under the handshake key
+        let server_hello_length =
+            5 + usize::from(u16::from_be_bytes([server_flight[3], server_flight[4]]));
+        let mut flight = server_flight[..server_hello_length].to_vec();
+        flight.extend_from_slice(&[0x14, 0x03, 0x03, 0x00, 0x01, 0x01]);
+        flight.extend_from_slice(&server_flight[server_hello_length..]);
+        conn.read_tls(&mut flight.as_slice()).unwrap();
+
+        // EncryptedExtensions and everything after it went through
+        assert_eq!(conn.state(), HandshakeState::Connected);
+        assert!(conn.handshake_joiner.is_empty());
+    }
+
+    #[test]
+    fn reject_protected_change_cipher_spec() {
+        let mut conn = connection(ClientConfig::default());
+        conn.read_tls(&mut handshake_record(vec![server_hello()]).as_slice())
+            .unwrap();
+        let mut server_key = test_traffic_key(2);
+        conn.record_layer
+            .set_read_key(Epoch::Handshake, test_traffic_key(2));
+        let record = seal_record(
+            &mut server_key,
+            0,
+            ContentType::ChangeCipherSpec,
+            &[0x01],
+            &PaddingPolicy::None,
+        )
+        .unwrap();
+
+        let err = conn
+            .read_tls(&mut Vec::<u8>::from(record).as_slice())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TlsError::UnexpectedMessage("protected change_cipher_spec")
+        ));
+        assert_eq!(
+            err.to_alert(),
+            Some(Alert::new(AlertDescription::UnexpectedMessage))
+        );
+    }
+
+    #[test]
+    fn reject_change_cipher_spec_after_handshake() {
+        let (mut conn, _) = connected_pair();
+        let record = [0x14, 0x03, 0x03, 0x00, 0x01, 0x01];
+
+        assert!(matches!(
+            conn.read_tls(&mut record.as_slice()),
+            Err(TlsError::UnexpectedMessage(
+                "change_cipher_spec after the handshake"
+            ))
+        ));
+    }
+
+    #[test]
+    fn reject_alpn_that_was_never_offered() {
+        let mut conn = connection(ClientConfig::default());
+        assert!(matches!(
+            read_server_hello_then(&mut conn, encrypted_extensions_with_alpn()),
+            Err(TlsError::UnsupportedExtension(
+                ExtensionType::ApplicationLayerProtocolNegotiation
+            ))
+        ));
+    }
+
+    #[test]
+    fn accept_alpn_that_was_offered() {
+        let mut conn = connection(ClientConfig {
+            alpn_protocols: vec![b"h2".to_vec()],
+            ..Default::default()
+        });
+        read_server_hello_then(&mut conn, encrypted_extensions_with_alpn()).unwrap();
+
+        assert_eq!(conn.state(), HandshakeState::WaitCertificate);
+        assert!(conn
+            .negotiated_extensions()
+            .contains(&ExtensionType::ApplicationLayerProtocolNegotiation));
+    }
+
+    #[test]
+    fn record_alpn_and_server_name_acknowledgement() {
+        let mut conn = connection(ClientConfig {
+            alpn_protocols: vec![b"http/1.1".to_vec(), b"h2".to_vec()],
+            ..Default::default()
+        });
+        assert!(conn.alpn_protocol().is_none());
+        assert!(!conn.server_name_acknowledged());
+        let mut extensions: Vec<u8> =
+            handshake::application_layer_protocol_negotiation(&[b"h2".to_vec()]).into();
+        extensions.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // empty server_name
+        let mut body = (extensions.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(&extensions);
+        let encrypted_extensions = HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body,
+        };
+        read_server_hello_then(&mut conn, encrypted_extensions).unwrap();
+
+        assert_eq!(conn.alpn_protocol(), Some(b"h2".as_slice()));
+        assert!(conn.server_name_acknowledged());
+    }
+
+    #[test]
+    fn reject_alpn_selection_that_was_not_offered() {
+        let mut conn = connection(ClientConfig {
+            alpn_protocols: vec![b"http/1.1".to_vec()],
+            ..Default::default()
+        });
+        assert!(matches!(
+            read_server_hello_then(&mut conn, encrypted_extensions_with_alpn()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    /// Fills every buffer with consecutive byte values
+    struct CounterRng(u8);
+
+    impl Rng for CounterRng {
+        fn fill(&mut self, buf: &mut [u8]) {
+            for byte in buf.iter_mut() {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+    }
+
+    #[test]
+    fn client_hello_is_reproducible_with_fixed_rng() {
+        let client_hello = || {
+            let conn = connection(ClientConfig {
+                rng: Mutex::new(Box::new(CounterRng(0))),
+                ..Default::default()
+            });
+            return conn.outgoing;
+        };
+
+        let first = client_hello();
+        assert_eq!(first, client_hello());
+        // The random comes right after the record header, the handshake
+        // header, and the legacy version
+        assert_eq!(first[11..15], [0, 1, 2, 3]);
+        assert_ne!(first, connection(ClientConfig::default()).outgoing);
+    }
+
+    /// The key exchange of RFC 8448, section 3
+    #[test]
+    fn rfc8448_shared_secret() {
+        let mut conn = connection(ClientConfig::default());
+        let client_private =
+            hex::decode("49af42ba7f7994852d713ef2784bcbcaa7911de26adc5642cb634540e7ea5005")
+                .unwrap();
+        conn.key_shares = vec![KeySharePrivate::X25519(client_private.try_into().unwrap())];
+        let server_key_share: Vec<u8> = handshake::server_key_share(
+            NamedGroup::X25519,
+            &hex::decode("c9828876112095fe66762bdbf7c672e156d6cc253b833df1dd69b1b04e751f0f")
+                .unwrap(),
+        )
+        .extension_data
+        .into_owned();
+
+        assert_eq!(
+            conn.key_exchange(&server_key_share).unwrap().to_vec(),
+            hex::decode("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d")
+                .unwrap()
+        );
+        assert!(matches!(
+            conn.key_exchange(&server_key_share[..35]),
+            Err(TlsError::LengthMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn compat_change_cipher_spec_follows_client_hello() {
+        let ccs = [0x14, 0x03, 0x03, 0x00, 0x01, 0x01];
+        let outgoing = connection(ClientConfig::default()).outgoing;
+        assert_eq!(outgoing[outgoing.len() - ccs.len()..], ccs);
+
+        let outgoing = connection(ClientConfig {
+            send_compat_ccs: false,
+            ..Default::default()
+        })
+        .outgoing;
+        let length = u16::from_be_bytes([outgoing[3], outgoing[4]]);
+        assert_eq!(outgoing[0], 0x16);
+        assert_eq!(outgoing.len(), 5 + usize::from(length));
+    }
+
+    #[test]
+    fn first_flight_is_client_hello_then_ccs() {
+        let flight = client_first_flight(Arc::new(ClientConfig::default()), "localhost").unwrap();
+        let length = u16::from_be_bytes([flight[3], flight[4]]);
+        assert_eq!(flight[0], 0x16);
+        assert_eq!(flight[5], u8::from(HandshakeType::ClientHello));
+        assert_eq!(
+            flight[5 + usize::from(length)..],
+            [0x14, 0x03, 0x03, 0x00, 0x01, 0x01]
+        );
+
+        let config = ClientConfig {
+            send_compat_ccs: false,
+            ..Default::default()
+        };
+        let flight = client_first_flight(Arc::new(config), "localhost").unwrap();
+        let length = u16::from_be_bytes([flight[3], flight[4]]);
+        assert_eq!(flight.len(), 5 + usize::from(length));
+    }
+
+    #[test]
+    fn reject_key_share_for_group_that_was_not_offered() {
+        let mut conn = connection(ClientConfig::default());
+        let flight = handshake_record(vec![server_hello_with_key_share(
+            NamedGroup::Secp256r1,
+            &[0x09; 32],
+        )]);
+
+        assert!(matches!(
+            conn.read_tls(&mut flight.as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    /// Just enough of a TLS 1.3 server to complete a handshake with the
+    /// client: X25519 or P-256, TLS_AES_128_GCM_SHA256, and a self-signed
+    /// Ed25519 certificate, which the client must be told to trust
+    struct TestServer {
+        record_layer: RecordLayer,
+        transcript: Transcript,
+        key_schedule: KeySchedule,
+    }
+
+    impl TestServer {
+        const CIPHER_SUITE: CipherSuite = CipherSuite::Aes128GcmSha256;
+
+        /// A self-signed certificate for "localhost", which the server
+        /// presents as a chain of one, and the PKCS #8 of its Ed25519 key
+        const CERTIFICATE: &'static [u8] = include_bytes!("../testdata/self-signed/cert.der");
+        const KEY: &'static [u8] = include_bytes!("../testdata/self-signed/key.pk8");
+
+        /// Answer the client's first flight with ServerHello in the clear,
+        /// followed by the rest of the server flight under the server
+        /// handshake key
+        fn accept(client_flight: &[u8]) -> (Self, Vec<u8>) {
+            return Self::respond(RecordLayer::new(), Transcript::new(), client_flight);
+        }
+
+        /// Answer the client's first flight with a HelloRetryRequest for the
+        /// group instead. The record layer and the transcript are to be passed
+        /// on to respond, together with the second ClientHello.
+        fn retry(client_flight: &[u8], group: NamedGroup) -> (RecordLayer, Transcript, Vec<u8>) {
+            let mut record_layer = RecordLayer::new();
+            let client_hello = Self::read_message(&mut record_layer, client_flight);
+            let mut transcript = Transcript::new();
+            transcript.update(&client_hello.to_bytes());
+            transcript.replace_with_message_hash(hash_algorithm(Self::CIPHER_SUITE));
+
+            let hello_retry_request: Vec<u8> =
+                Self::echo_session_id(hello_retry_request(group), &client_hello.body).into();
+            transcript.update(&hello_retry_request);
+            let flight = record_layer
+                .write_records(ContentType::Handshake, &hello_retry_request)
+                .unwrap();
+
+            return (record_layer, transcript, flight);
+        }
+
+        /// Answer a ClientHello with ServerHello in the clear, in the group of
+        /// the client's key share, followed by the rest of the server flight
+        fn respond(
+            mut record_layer: RecordLayer,
+            mut transcript: Transcript,
+            client_flight: &[u8],
+        ) -> (Self, Vec<u8>) {
+            let client_hello = Self::read_message(&mut record_layer, client_flight);
+            transcript.update(&Vec::<u8>::from(client_hello.clone()));
+
+            let (group, client_public) = Self::client_key_share(&client_hello.body);
+            let (server_private, server_public) =
+                KeySharePrivate::generate(group, &mut CounterRng(0x42)).unwrap();
+            let server_hello: Vec<u8> = Self::echo_session_id(
+                server_hello_with_key_share(group, &server_public),
+                &client_hello.body,
+            )
+            .into();
+            transcript.update(&server_hello);
+            let mut flight = record_layer
+                .write_records(ContentType::Handshake, &server_hello)
+                .unwrap();
+
+            let shared_secret = server_private.agree(&client_public).unwrap();
+            let mut key_schedule = KeySchedule::new(Self::CIPHER_SUITE);
+            key_schedule
+                .derive_handshake_secrets(
+                    &shared_secret,
+                    &transcript.current_hash(hash_algorithm(Self::CIPHER_SUITE)),
+                )
+                .unwrap();
+            let (client_key, client_iv, server_key, server_iv) =
+                key_schedule.handshake_keys(Self::CIPHER_SUITE).unwrap();
+            record_layer.set_read_key(
+                Epoch::Handshake,
+                TrafficKey::new(Self::CIPHER_SUITE, &client_key, &client_iv).unwrap(),
+            );
+            record_layer.set_write_key(
+                Epoch::Handshake,
+                TrafficKey::new(Self::CIPHER_SUITE, &server_key, &server_iv).unwrap(),
+            );
+
+            let mut messages = vec![];
+            for (msg_type, body) in [
+                (HandshakeType::EncryptedExtensions, vec![0, 0]),
+                (HandshakeType::Certificate, Self::certificate()),
+            ] {
+                let message: Vec<u8> = HandshakeMessage { msg_type, body }.into();
+                transcript.update(&message);
+                messages.extend_from_slice(&message);
+            }
+            let certificate_verify: Vec<u8> = HandshakeMessage::from(Self::certificate_verify(
+                &transcript.current_hash(hash_algorithm(Self::CIPHER_SUITE)),
+            ))
+            .into();
+            transcript.update(&certificate_verify);
+            messages.extend_from_slice(&certificate_verify);
+            let finished: Vec<u8> = HandshakeMessage {
+                msg_type: HandshakeType::Finished,
+                body: key_schedule
+                    .server_finished_verify_data(
+                        &transcript.current_hash(hash_algorithm(Self::CIPHER_SUITE)),
+                    )
+                    .unwrap(),
+            }
+            .into();
+            transcript.update(&finished);
+            messages.extend_from_slice(&finished);
+            flight.extend_from_slice(
+                &record_layer
+                    .write_records(ContentType::Handshake, &messages)
+                    .unwrap(),
+            );
+
+            let server = Self {
+                record_layer,
+                transcript,
+                key_schedule,
+            };
+            return (server, flight);
+        }
+
+        /// An Ed25519 signature over the transcript up to Certificate
+        fn certificate_verify(transcript_hash: &[u8]) -> CertificateVerify {
+            let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(Self::KEY).unwrap();
+            let signature = key.sign(&verify::server_signed_content(transcript_hash));
+            return CertificateVerify {
+                scheme: SignatureScheme::Ed25519,
+                signature: signature.as_ref().to_vec(),
+            };
+        }
+
+        /// The body of the Certificate message
+        fn certificate() -> Vec<u8> {
+            let cert_length = Self::CERTIFICATE.len() as u32;
+            let list_length = cert_length + 5;
+            let mut body = vec![0];
+            body.extend_from_slice(&list_length.to_be_bytes()[1..]);
+            body.extend_from_slice(&cert_length.to_be_bytes()[1..]);
+            body.extend_from_slice(Self::CERTIFICATE);
+            body.extend_from_slice(&[0, 0]);
+            return body;
+        }
+
+        /// Check the client Finished, then switch to the application keys
+        fn finish(&mut self, client_flight: &[u8]) {
+            let algorithm = hash_algorithm(Self::CIPHER_SUITE);
+            let handshake_hash = self.transcript.current_hash(algorithm);
+            let finished = Self::read_message(&mut self.record_layer, client_flight);
+            assert_eq!(finished.msg_type, HandshakeType::Finished);
+            self.key_schedule
+                .derive_application_secrets(&handshake_hash)
+                .unwrap();
+            assert_eq!(
+                finished.body,
+                self.key_schedule
+                    .client_finished_verify_data(&handshake_hash)
+                    .unwrap()
+            );
+
+            let (client_key, client_iv, server_key, server_iv) = self
+                .key_schedule
+                .application_keys(Self::CIPHER_SUITE)
+                .unwrap();
+            self.record_layer.set_read_key(
+                Epoch::Application,
+                TrafficKey::new(Self::CIPHER_SUITE, &client_key, &client_iv).unwrap(),
+            );
+            self.record_layer.set_write_key(
+                Epoch::Application,
+                TrafficKey::new(Self::CIPHER_SUITE, &server_key, &server_iv).unwrap(),
+            );
+        }
+
+        /// The flight must hold exactly one handshake message, plus any number
+        /// of ChangeCipherSpec records
+        fn read_message(record_layer: &mut RecordLayer, flight: &[u8]) -> HandshakeMessage {
+            let mut joiner = HandshakeJoiner::new();
+            for record in record_layer.read_from(&mut Cursor::new(flight)).unwrap() {
+                let (content_type, content) = record_layer.unprotect(record).unwrap();
+                if content_type == ContentType::ChangeCipherSpec {
+                    continue;
+                }
+                assert_eq!(content_type, ContentType::Handshake);
+                joiner.push(&content);
+            }
+            let message = joiner.next_message().unwrap().unwrap();
+            assert!(joiner.next_message().unwrap().is_none());
+
+            return message;
+        }
+
+        /// The group and the key exchange of the ClientHello's only key share,
+        /// which follows the legacy version, the random, and the
+        /// variable-length session id, cipher suites, and compression methods
+        /// Put the session id of the ClientHello body into a ServerHello
+        /// whose own is empty
+        fn echo_session_id(
+            server_hello: HandshakeMessage,
+            client_hello: &[u8],
+        ) -> HandshakeMessage {
+            let offset = 2 + RANDOM_LEN;
+            let session_id = &client_hello[offset..offset + 1 + usize::from(client_hello[offset])];
+            let mut body = server_hello.body[..offset].to_vec();
+            body.extend_from_slice(session_id);
+            body.extend_from_slice(&server_hello.body[offset + 1..]);
+
+            return HandshakeMessage {
+                body,
+                ..server_hello
+            };
+        }
+
+        fn client_key_share(body: &[u8]) -> (NamedGroup, Vec<u8>) {
+            let mut offset = 2 + 32;
+            offset += 1 + usize::from(body[offset]);
+            offset += 2 + usize::from(u16::from_be_bytes([body[offset], body[offset + 1]]));
+            offset += 1 + usize::from(body[offset]);
+            let key_share = handshake::find_extension(&body[offset + 2..], ExtensionType::KeyShare)
+                .unwrap()
+                .unwrap();
+
+            // client_shares length, group, key_exchange length
+            let data = &key_share.extension_data;
+            let group = NamedGroup::from(u16::from_be_bytes([data[2], data[3]]));
+            return (group, data[6..].to_vec());
+        }
+    }
+
+    /// Run a full handshake between the client and a TestServer
+    fn handshake(conn: &mut ClientConnection) -> TestServer {
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (mut server, server_flight) = TestServer::accept(&client_flight);
+        conn.read_tls(&mut server_flight.as_slice()).unwrap();
+
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        server.finish(&client_flight);
+
+        return server;
+    }
+
+    #[test]
+    fn random_session_id_must_be_echoed() {
+        let config = || ClientConfig {
+            compat_session_id: true,
+            ..trust_test_server()
+        };
+        let mut conn = connection(config());
+        // After the record header, the handshake header, the legacy version,
+        // and the random
+        assert_eq!(
+            usize::from(conn.outgoing[9 + 2 + RANDOM_LEN]),
+            SESSION_ID_MAX_LEN
+        );
+        assert_eq!(conn.legacy_session_id.len(), SESSION_ID_MAX_LEN);
+        handshake(&mut conn);
+        assert_eq!(conn.state(), HandshakeState::Connected);
+
+        // server_hello() echoes an empty session id
+        let mut conn = connection(config());
+        assert!(matches!(
+            conn.read_tls(&mut handshake_record(vec![server_hello()]).as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    #[test]
+    fn skip_new_session_ticket_after_handshake() {
+        let mut conn = connection(trust_test_server());
+        let mut server = handshake(&mut conn);
+        let ticket = HandshakeMessage {
+            msg_type: HandshakeType::NewSessionTicket,
+            body: vec![0; 13],
+        };
+        let flight = server
+            .record_layer
+            .write_records(ContentType::Handshake, &Vec::from(ticket))
+            .unwrap();
+
+        conn.read_tls(&mut flight.as_slice()).unwrap();
+        assert_eq!(conn.state(), HandshakeState::Connected);
+    }
+
+    #[cfg(feature = "dangerous")]
+    #[test]
+    fn traffic_key_iv_of_the_negotiated_suite() {
+        let mut conn = connection(trust_test_server());
+        assert_eq!(conn.client_traffic_key_iv(), None);
+        handshake(&mut conn);
+
+        let key_length = crate::crypto::key_schedule::key_length(TestServer::CIPHER_SUITE);
+        for (key, iv) in [
+            conn.client_traffic_key_iv().unwrap(),
+            conn.server_traffic_key_iv().unwrap(),
+        ] {
+            assert_eq!(key.len(), key_length);
+            assert_eq!(iv.len(), crate::constants::AEAD_NONCE_LEN);
+        }
+        assert_ne!(conn.client_traffic_key_iv(), conn.server_traffic_key_iv());
+    }
+
+    #[cfg(feature = "dangerous")]
+    #[test]
+    fn dump_secrets_after_handshake() {
+        let mut conn = connection(trust_test_server());
+        assert_eq!(conn.dump_secrets(), Secrets::default());
+        handshake(&mut conn);
+
+        let secrets = conn.dump_secrets();
+        for secret in [
+            &secrets.client_handshake_traffic_secret,
+            &secrets.server_handshake_traffic_secret,
+            &secrets.client_application_traffic_secret,
+            &secrets.server_application_traffic_secret,
+        ] {
+            assert_eq!(secret.len(), 64);
+        }
+        assert_ne!(
+            secrets.client_handshake_traffic_secret,
+            secrets.server_handshake_traffic_secret
+        );
+    }
+
+    #[test]
+    fn grease_in_client_hello() {
+        let mut conn = connection(ClientConfig {
+            grease: true,
+            ..trust_test_server()
+        });
+        let body = &conn.outgoing[9..];
+        let mut offset = 2 + 32;
+        offset += 1 + usize::from(body[offset]);
+        let suites_length = usize::from(u16::from_be_bytes([body[offset], body[offset + 1]]));
+        let suites: Vec<u16> = body[offset + 2..offset + 2 + suites_length]
+            .chunks(2)
+            .map(|suite| u16::from_be_bytes([suite[0], suite[1]]))
+            .collect();
+        assert!(constants::is_grease(suites[0]));
+        assert!(suites[1..]
+            .iter()
+            .all(|suite| !constants::is_grease(*suite)));
+        assert!(!conn
+            .offered_extensions
+            .iter()
+            .any(|extension_type| constants::is_grease(u16::from(*extension_type))));
+
+        // The test server skips what it does not know
+        handshake(&mut conn);
+        assert_eq!(conn.state(), HandshakeState::Connected);
+    }
+
+    #[test]
+    fn ignore_grease_from_server() {
+        let extension: Vec<u8> = Extension::raw(constants::grease(0x3f), vec![0x00]).into();
+        let mut body = (extension.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(&extension);
+        let encrypted_extensions = HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body,
+        };
+
+        let mut conn = connection(ClientConfig::default());
+        read_server_hello_then(&mut conn, encrypted_extensions).unwrap();
+        assert!(!conn
+            .negotiated_extensions()
+            .contains(&ExtensionType::Unknown(0x3a3a)));
+    }
+
+    #[test]
+    fn reject_plaintext_handshake_after_server_hello() {
+        let mut conn = connection(ClientConfig::default());
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+        let server_hello: Vec<u8> = RecordLayer::new()
+            .read_from(&mut server_flight.as_slice())
+            .unwrap()
+            .remove(0)
+            .into();
+        conn.read_tls(&mut server_hello.as_slice()).unwrap();
+        assert_eq!(conn.record_layer.read_epoch(), Epoch::Handshake);
+
+        let encrypted_extensions = handshake_record(vec![HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body: vec![0, 0],
+        }]);
+        assert!(matches!(
+            conn.read_tls(&mut encrypted_extensions.as_slice()),
+            Err(TlsError::UnexpectedPlaintextRecord)
+        ));
+    }
+
+    #[test]
+    fn process_captured_encrypted_flight() {
+        let mut conn = connection(trust_test_server());
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+
+        // ServerHello goes in as usual, and the protected records after it
+        // are split off the capture by hand
+        let mut records = RecordLayer::new()
+            .read_from(&mut server_flight.as_slice())
+            .unwrap()
+            .into_iter();
+        let server_hello: Vec<u8> = records.next().unwrap().into();
+        conn.read_tls(&mut server_hello.as_slice()).unwrap();
+        let ciphertexts: Vec<TLSCiphertext<Vec<u8>>> = records
+            .map(|record| TLSCiphertext::try_from(record).unwrap())
+            .collect();
+
+        let messages = conn.process_encrypted_flight(&ciphertexts).unwrap();
+        let types: Vec<HandshakeType> = messages.iter().map(|message| message.msg_type).collect();
+        assert_eq!(
+            types,
+            [
+                HandshakeType::EncryptedExtensions,
+                HandshakeType::Certificate,
+                HandshakeType::CertificateVerify,
+                HandshakeType::Finished,
+            ]
+        );
+        assert_eq!(conn.state, HandshakeState::Connected);
+    }
+
+    #[test]
+    fn key_shares_only_for_key_share_groups() {
+        let conn = connection(ClientConfig {
+            supported_groups: vec![NamedGroup::X25519, NamedGroup::Secp256r1],
+            key_share_groups: vec![NamedGroup::X25519],
+            ..Default::default()
+        });
+        assert_eq!(conn.key_shares.len(), 1);
+        assert_eq!(conn.key_shares[0].group(), NamedGroup::X25519);
+
+        let extension = |extension_type| {
+            return conn
+                .client_hello_extensions
+                .iter()
+                .find(|extension| extension.extension_type == extension_type)
+                .unwrap()
+                .extension_data
+                .to_vec();
+        };
+        let entries = KeyShare::parse_client_hello(&extension(ExtensionType::KeyShare)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].group, NamedGroup::X25519);
+        assert_eq!(
+            extension(ExtensionType::SupportedGroups),
+            [0x00, 0x04, 0x00, 0x1d, 0x00, 0x17]
+        );
+    }
+
+    #[test]
+    fn retry_with_the_group_from_hello_retry_request() {
+        let mut conn = connection(ClientConfig {
+            supported_groups: vec![NamedGroup::X25519, NamedGroup::Secp256r1],
+            transcript_mode: TranscriptMode::KeepBytes,
+            ..trust_test_server()
+        });
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (record_layer, transcript, server_flight) =
+            TestServer::retry(&client_flight, NamedGroup::Secp256r1);
+        conn.read_tls(&mut server_flight.as_slice()).unwrap();
+        assert_eq!(conn.state(), HandshakeState::WaitServerHello);
+        assert_eq!(
+            conn.transcript.transcript_bytes().unwrap()[0],
+            u8::from(HandshakeType::MessageHash)
+        );
+
+        // The server completes the handshake only if the second ClientHello
+        // carries a P-256 key share and both transcripts agree
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (mut server, server_flight) =
+            TestServer::respond(record_layer, transcript, &client_flight);
+        conn.read_tls(&mut server_flight.as_slice()).unwrap();
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        server.finish(&client_flight);
+        assert_eq!(conn.state(), HandshakeState::Connected);
+
+        let hello_retry_request = handshake_record(vec![hello_retry_request(NamedGroup::X25519)]);
+        let mut conn = connection(trust_test_server());
+        assert!(matches!(
+            conn.read_tls(&mut hello_retry_request.as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    struct RejectAll;
+
+    impl ServerCertVerifier for RejectAll {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &[u8],
+            _intermediates: &[&[u8]],
+            _server_name: &str,
+            _now: SystemTime,
+        ) -> Result<(), TlsError> {
+            return Err(TlsError::BadCertificate("rejected"));
+        }
+    }
+
+    /// Read ServerHello off the wire, then process EncryptedExtensions and
+    /// the Certificate of TestServer, which leaves the client waiting for
+    /// CertificateVerify
+    fn read_until_certificate_verify(conn: &mut ClientConnection) {
+        conn.read_tls(&mut handshake_record(vec![server_hello()]).as_slice())
+            .unwrap();
+        for (msg_type, body) in [
+            (HandshakeType::EncryptedExtensions, vec![0, 0]),
+            (HandshakeType::Certificate, TestServer::certificate()),
+        ] {
+            conn.process_handshake_message(HandshakeMessage { msg_type, body })
+                .unwrap();
+        }
+        assert_eq!(conn.state(), HandshakeState::WaitCertificateVerify);
+    }
+
+    #[test]
+    fn check_certificate_verify_signature() {
+        let mut conn = connection(trust_test_server());
+        read_until_certificate_verify(&mut conn);
+        let certificate_verify = TestServer::certificate_verify(
+            &conn
+                .transcript
+                .current_hash(hash_algorithm(TestServer::CIPHER_SUITE)),
+        );
+        conn.process_handshake_message(certificate_verify.clone().into())
+            .unwrap();
+        assert_eq!(conn.state(), HandshakeState::WaitFinished);
+
+        // A chain replayed by someone without the key
+        let mut conn = connection(trust_test_server());
+        read_until_certificate_verify(&mut conn);
+        let mut forged = certificate_verify.clone();
+        forged.signature[0] ^= 1;
+        let err = conn.process_handshake_message(forged.into()).unwrap_err();
+        assert!(matches!(err, TlsError::DecryptError(_)));
+        assert_eq!(
+            err.to_alert(),
+            Some(Alert::new(AlertDescription::DecryptError))
+        );
+        assert_eq!(conn.state(), HandshakeState::WaitCertificateVerify);
+    }
+
+    #[test]
+    fn reject_certificate_verify_scheme_that_was_not_offered() {
+        let mut conn = connection(ClientConfig {
+            signature_schemes: vec![SignatureScheme::EcdsaSecp256r1Sha256],
+            ..trust_test_server()
+        });
+        read_until_certificate_verify(&mut conn);
+        let certificate_verify = TestServer::certificate_verify(
+            &conn
+                .transcript
+                .current_hash(hash_algorithm(TestServer::CIPHER_SUITE)),
+        );
+
+        assert!(matches!(
+            conn.process_handshake_message(certificate_verify.into()),
+            Err(TlsError::IllegalParameter(
+                "CertificateVerify scheme was not offered"
+            ))
+        ));
+    }
+
+    #[test]
+    fn abort_when_verifier_rejects_certificate() {
+        let mut conn = connection(ClientConfig {
+            verifier: Arc::new(RejectAll),
+            ..Default::default()
+        });
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+
+        assert!(matches!(
+            conn.read_tls(&mut server_flight.as_slice()),
+            Err(TlsError::BadCertificate("rejected"))
+        ));
+        assert_eq!(conn.state(), HandshakeState::WaitCertificate);
+    }
+
+    #[test]
+    fn handshake_with_self_signed_cert_without_verification() {
+        let mut conn = connection(ClientConfig::default());
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+        assert!(matches!(
+            conn.read_tls(&mut server_flight.as_slice()),
+            Err(TlsError::BadCertificate(_))
+        ));
+
+        let mut config = ClientConfig::default();
+        config.dangerous().with_no_cert_verification();
+        let mut conn = connection(config);
+        handshake(&mut conn);
+
+        assert_eq!(conn.state(), HandshakeState::Connected);
+        assert_eq!(
+            conn.peer_certificates().unwrap(),
+            [TestServer::CERTIFICATE.to_vec()]
+        );
+    }
+
+    #[test]
+    fn expose_server_certificate_chain() {
+        let mut conn = connection(trust_test_server());
+        assert!(conn.peer_certificates().is_none());
+        handshake(&mut conn);
+
+        assert_eq!(
+            conn.peer_certificates().unwrap(),
+            [TestServer::CERTIFICATE.to_vec()]
+        );
+    }
+
+    #[test]
+    fn reset_connection_handshakes_again() {
+        let mut conn = connection(trust_test_server());
+        handshake(&mut conn);
+        conn.send_application_data(b"ping").unwrap();
+        assert_eq!(conn.state(), HandshakeState::Connected);
+
+        conn.reset("localhost").unwrap();
+        assert_eq!(conn.state(), HandshakeState::WaitServerHello);
+        assert!(conn.key_schedule.is_none());
+        handshake(&mut conn);
+        assert_eq!(conn.state(), HandshakeState::Connected);
+    }
+
+    #[test]
+    fn reassemble_server_hello_split_across_records() {
+        let mut conn = connection(trust_test_server());
+        let mut client_flight = vec![];
+        conn.write_all_tls(&mut client_flight).unwrap();
+        let (_, server_flight) = TestServer::accept(&client_flight);
+        let length = usize::from(u16::from_be_bytes([server_flight[3], server_flight[4]]));
+        let (server_hello, protected) = server_flight.split_at(5 + length);
+
+        for fragment in server_hello[5..].chunks(length / 2 + 1) {
+            let mut record = vec![0x16, 0x03, 0x03];
+            record.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            record.extend_from_slice(fragment);
+            assert_eq!(conn.state(), HandshakeState::WaitServerHello);
+            conn.read_tls(&mut record.as_slice()).unwrap();
+        }
+        assert_eq!(conn.state(), HandshakeState::WaitEncryptedExtensions);
+
+        // The rest of the server flight only opens under the handshake key
+        // derived from the reassembled ServerHello
+        conn.read_tls(&mut &protected[..]).unwrap();
+        assert_eq!(conn.state(), HandshakeState::Connected);
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Event {
+        Sent(HandshakeType),
+        Received(HandshakeType),
+        StateChange(HandshakeState, HandshakeState),
+        KeysDerived(KeyEpoch),
+    }
+
+    /// Records every event into a log that the test keeps a handle to
+    struct RecordingObserver(Arc<Mutex<Vec<Event>>>);
+
+    impl HandshakeObserver for RecordingObserver {
+        fn on_message_sent(&mut self, message: &HandshakeMessage) {
+            self.0.lock().unwrap().push(Event::Sent(message.msg_type));
+        }
+
+        fn on_message_received(&mut self, message: &HandshakeMessage) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Event::Received(message.msg_type));
+        }
+
+        fn on_state_change(&mut self, old: HandshakeState, new: HandshakeState) {
+            self.0.lock().unwrap().push(Event::StateChange(old, new));
+        }
+
+        fn on_keys_derived(&mut self, epoch: KeyEpoch) {
+            self.0.lock().unwrap().push(Event::KeysDerived(epoch));
+        }
+    }
+
+    #[test]
+    fn observer_sees_loopback_handshake() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut conn = connection(trust_test_server());
+        conn.set_observer(Box::new(RecordingObserver(events.clone())));
+        let mut server = handshake(&mut conn);
+
+        use HandshakeState::*;
+        use HandshakeType::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::Received(ServerHello),
+                Event::KeysDerived(KeyEpoch::Handshake),
+                Event::StateChange(WaitServerHello, WaitEncryptedExtensions),
+                Event::Received(EncryptedExtensions),
+                Event::StateChange(WaitEncryptedExtensions, WaitCertificate),
+                Event::Received(Certificate),
+                Event::StateChange(WaitCertificate, WaitCertificateVerify),
+                Event::Received(CertificateVerify),
+                Event::StateChange(WaitCertificateVerify, WaitFinished),
+                Event::Received(Finished),
+                Event::Sent(Finished),
+                Event::KeysDerived(KeyEpoch::Application),
+                Event::StateChange(WaitFinished, Connected),
+            ]
+        );
+
+        // Both sides now hold the same application keys
+        conn.send_application_data(b"ping").unwrap();
+        let mut wire = vec![];
+        conn.write_all_tls(&mut wire).unwrap();
+        let record = server
+            .record_layer
+            .read_from(&mut wire.as_slice())
+            .unwrap()
+            .remove(0);
+        assert_eq!(
+            server.record_layer.unprotect(record).unwrap(),
+            (ContentType::ApplicationData, b"ping".to_vec())
+        );
+    }
+
+    #[test]
+    fn key_update_at_record_limit() {
+        let mut conn = connection(ClientConfig {
+            records_per_key: Some(3),
+            ..trust_test_server()
+        });
+        let mut server = handshake(&mut conn);
+        for _ in 0..5 {
+            conn.send_application_data(b"ping").unwrap();
+        }
+        let mut wire = vec![];
+        conn.write_all_tls(&mut wire).unwrap();
+
+        // Each key seals two pings and then the KeyUpdate to its successor
+        let mut contents = vec![];
+        for record in server.record_layer.read_from(&mut wire.as_slice()).unwrap() {
+            let (content_type, content) = server.record_layer.unprotect(record).unwrap();
+            if content_type == ContentType::Handshake {
+                assert_eq!(content, [24, 0, 0, 1, KEY_UPDATE_NOT_REQUESTED]);
+                let (key, iv) = server
+                    .key_schedule
+                    .update_client_application_keys()
+                    .unwrap();
+                server.record_layer.set_read_key(
+                    Epoch::Application,
+                    TrafficKey::new(TestServer::CIPHER_SUITE, &key, &iv).unwrap(),
+                );
+            }
+            contents.push((content_type, content));
+        }
+        let ping = (ContentType::ApplicationData, b"ping".to_vec());
+        let key_update = (ContentType::Handshake, vec![24, 0, 0, 1, 0]);
+        assert_eq!(
+            contents,
+            vec![
+                ping.clone(),
+                ping.clone(),
+                key_update.clone(),
+                ping.clone(),
+                ping.clone(),
+                key_update,
+                ping,
+            ]
+        );
+    }
+
+    #[test]
+    fn refuse_records_past_limit() {
+        let mut conn = connection(ClientConfig {
+            records_per_key: Some(2),
+            on_record_limit: RecordLimitAction::Error,
+            ..trust_test_server()
+        });
+        handshake(&mut conn);
+        conn.send_application_data(b"ping").unwrap();
+        conn.send_application_data(b"ping").unwrap();
+
+        assert!(matches!(
+            conn.send_application_data(b"ping"),
+            Err(TlsError::RecordLimitReached)
+        ));
+    }
+
+    #[cfg(feature = "record-capture")]
+    #[test]
+    fn capture_every_record_of_handshake() {
+        let path = std::env::temp_dir().join(format!("tls-core-capture-{}", std::process::id()));
+        let mut conn = connection(trust_test_server())
+            .with_record_capture(&path)
+            .unwrap();
+        handshake(&mut conn);
+
+        let capture = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut frames = vec![];
+        let mut offset = 0;
+        while offset < capture.len() {
+            let length = u32::from_be_bytes(capture[offset..offset + 4].try_into().unwrap());
+            frames.push(&capture[offset + 4..offset + 4 + length as usize]);
+            offset += 4 + length as usize;
+        }
+
+        // ClientHello, the compatibility ChangeCipherSpec, ServerHello, the
+        // rest of the server flight in a single record, and the client
+        // Finished
+        let types: Vec<u8> = frames.iter().map(|frame| frame[0]).collect();
+        assert_eq!(types, [0x16, 0x14, 0x16, 0x17, 0x17]);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn count_parsed_records() {
+        let (mut sender, mut receiver) = connected_pair();
+        let data = vec![0xab; 5 * 16384];
+        sender.send_application_data(&data).unwrap();
+        let nrecords = transfer(&mut sender, &mut receiver);
+
+        let metrics = receiver.metrics();
+        assert_eq!(nrecords, 5);
+        assert_eq!(metrics.records_parsed, 5);
+        // Each record is 2^14 bytes of content, the content type, and the tag
+        assert_eq!(metrics.bytes_decrypted, 5 * (16384 + 1 + 16));
+        assert_eq!(sender.metrics().records_parsed, 0);
+    }
+
+    #[cfg(feature = "buffer-pool")]
+    #[test]
+    fn sealing_reuses_pooled_buffers() {
+        let (mut sender, mut receiver) = connected_pair();
+        let pool = BufferPool::new();
+        sender.set_buffer_pool(pool.clone());
+        receiver.set_buffer_pool(pool.clone());
+        for i in 0..100u8 {
+            sender.send_application_data(&[i; 100]).unwrap();
+        }
+        assert_eq!(transfer(&mut sender, &mut receiver), 100);
+        assert_eq!(recv_all(&mut receiver).len(), 100 * 100);
+
+        // One buffer seals every record, and the parser and the AEAD pass
+        // another back and forth
+        let stats = sender.buffer_pool().stats();
+        assert!(stats.allocated <= 2, "{:?}", stats);
+        assert!(stats.reused >= 2 * 100 - 2, "{:?}", stats);
+    }
+}
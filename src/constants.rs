@@ -1,9 +1,22 @@
+use crate::error::TlsError;
 use std::error::Error;
 
+/// The random of ClientHello and ServerHello
+pub(crate) const RANDOM_LEN: usize = 32;
+
+/// legacy_session_id holds at most 32 bytes
+pub(crate) const SESSION_ID_MAX_LEN: usize = 32;
+
+/// All TLS 1.3 AEADs use 12-byte nonces
+pub(crate) const AEAD_NONCE_LEN: usize = 12;
+
+/// content_type (1 byte), legacy_record_version (2 bytes), and length (2 bytes)
+pub(crate) const RECORD_HEADER_LEN: usize = 5;
+
 /// Each type is exactly one byte wide
 #[allow(dead_code)]
-#[derive(Debug,Clone,Eq,PartialEq)]
-pub(crate) enum ContentType {
+#[derive(Debug,Clone,Copy,Eq,PartialEq)]
+pub enum ContentType {
     Invalid,
     ChangeCipherSpec,
     Alert,
@@ -11,6 +24,16 @@ pub(crate) enum ContentType {
     ApplicationData,
 }
 
+#[allow(dead_code)]
+impl ContentType {
+    /// Whether a record of this type may travel unprotected: before the keys
+    /// are installed only handshake messages, alerts, and the compatibility
+    /// ChangeCipherSpec do, and Invalid never appears on the wire at all
+    pub(crate) const fn allowed_plaintext(&self) -> bool {
+        return matches!(self, Self::Handshake | Self::Alert | Self::ChangeCipherSpec);
+    }
+}
+
 impl TryFrom<ContentType> for u8 {
     type Error = Box<dyn Error>;
 
@@ -42,12 +65,43 @@ impl TryFrom<u8> for ContentType {
 
 /// Each type is exactly two-byte wide
 #[allow(dead_code)]
-#[derive(Debug,Clone,Eq,PartialEq)]
-pub(crate) enum ProtocolVersion {
+#[derive(Debug,Clone,Copy,Eq,PartialEq)]
+pub enum ProtocolVersion {
     TLSv1_0,  // 0x0301
     TLSv1_1,  // 0x0302
     TLSv1_2,  // 0x0303
     TLSv1_3,  // 0x0304
+
+    /// A version we do not recognize, kept as-is so that it can be echoed
+    /// back. The legacy_record_version field must be tolerated this way.
+    Unknown([u8; 2]),
+
+    /// 0x7f00 plus the number of a TLS 1.3 draft, for testing against
+    /// servers that still speak one
+    #[cfg(feature = "draft-versions")]
+    DraftVersion(u16),
+}
+
+#[allow(dead_code)]
+impl ProtocolVersion {
+    /// Decode the first two bytes of the slice like TryFrom, except that an
+    /// unrecognized version is preserved as Self::Unknown instead of failing
+    pub(crate) fn from_wire(value: &[u8]) -> Result<Self, TlsError> {
+        return match Self::try_from(value) {
+            Err(TlsError::InvalidProtocolVersion) => Ok(Self::Unknown([value[0], value[1]])),
+            result => result,
+        };
+    }
+
+    pub(crate) fn is_known(&self) -> bool {
+        return !matches!(self, Self::Unknown(_));
+    }
+
+    /// TLS 1.0 and 1.1 are deprecated (RFC 8996) and must never be the
+    /// version a connection settles on
+    pub(crate) fn is_deprecated(&self) -> bool {
+        return matches!(self, Self::TLSv1_0 | Self::TLSv1_1);
+    }
 }
 
 impl TryFrom<ProtocolVersion> for [u8; 2] {
@@ -59,25 +113,392 @@ impl TryFrom<ProtocolVersion> for [u8; 2] {
             ProtocolVersion::TLSv1_1 => Ok([0x03, 0x02]),
             ProtocolVersion::TLSv1_2 => Ok([0x03, 0x03]),
             ProtocolVersion::TLSv1_3 => Ok([0x03, 0x04]),
+            ProtocolVersion::Unknown(encoding) => Ok(encoding),
+            #[cfg(feature = "draft-versions")]
+            ProtocolVersion::DraftVersion(version) => Ok(version.to_be_bytes()),
         }
     }
 }
 
+/// Decode the first two bytes of the slice; any trailing bytes are ignored.
+/// This is the strict decoding: unknown versions are rejected.
 impl TryFrom<&[u8]> for ProtocolVersion {
-    type Error = Box<dyn Error>;
+    type Error = TlsError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.len() < 2 {
-            return Err("Invalid length".into());
-        }
+        let encoding = match value.get(0..2) {
+            Some(encoding) => encoding,
+            None => return Err(TlsError::DecodeError("protocol version needs two bytes")),
+        };
 
-        // TODO: unwrap is okay since the length is guaranteed
-        return match value.get(0..2).unwrap() {
-            &[0x03, 0x01] => Ok(Self::TLSv1_0),
-            &[0x03, 0x02] => Ok(Self::TLSv1_1),
-            &[0x03, 0x03] => Ok(Self::TLSv1_2),
-            &[0x03, 0x04] => Ok(Self::TLSv1_3),
-            _ => Err("Invalid encoding".into()),
+        return match encoding {
+            [0x03, 0x01] => Ok(Self::TLSv1_0),
+            [0x03, 0x02] => Ok(Self::TLSv1_1),
+            [0x03, 0x03] => Ok(Self::TLSv1_2),
+            [0x03, 0x04] => Ok(Self::TLSv1_3),
+            #[cfg(feature = "draft-versions")]
+            [0x7f, draft] => Ok(Self::DraftVersion(u16::from_be_bytes([0x7f, *draft]))),
+            _ => Err(TlsError::InvalidProtocolVersion),
         };
     }
 }
+
+/// Whether a two-byte code point is one of the values that RFC 8701 reserves
+/// for GREASE: both bytes are equal and of the form 0x?A
+pub(crate) const fn is_grease(code: u16) -> bool {
+    return code & 0x0f0f == 0x0a0a && code >> 8 == code & 0xff;
+}
+
+/// The GREASE code point that a random byte picks, out of the sixteen
+pub(crate) const fn grease(seed: u8) -> u16 {
+    let byte = (seed & 0xf0) | 0x0a;
+    return u16::from_be_bytes([byte, byte]);
+}
+
+/// Each type is exactly two bytes wide. Extension types that this crate does
+/// not know about are kept as ExtensionType::Unknown so that they can be
+/// skipped over instead of failing the whole message.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ExtensionType {
+    ServerName,                          // 0
+    StatusRequest,                       // 5
+    SupportedGroups,                     // 10
+    SignatureAlgorithms,                 // 13
+    ApplicationLayerProtocolNegotiation, // 16
+    SignedCertificateTimestamp,          // 18
+    PreSharedKey,                        // 41
+    EarlyData,                           // 42
+    SupportedVersions,                   // 43
+    Cookie,                              // 44
+    PskKeyExchangeModes,                 // 45
+    KeyShare,                            // 51
+    Unknown(u16),
+}
+
+impl From<ExtensionType> for u16 {
+    fn from(value: ExtensionType) -> Self {
+        match value {
+            ExtensionType::ServerName => 0,
+            ExtensionType::StatusRequest => 5,
+            ExtensionType::SupportedGroups => 10,
+            ExtensionType::SignatureAlgorithms => 13,
+            ExtensionType::ApplicationLayerProtocolNegotiation => 16,
+            ExtensionType::SignedCertificateTimestamp => 18,
+            ExtensionType::PreSharedKey => 41,
+            ExtensionType::EarlyData => 42,
+            ExtensionType::SupportedVersions => 43,
+            ExtensionType::Cookie => 44,
+            ExtensionType::PskKeyExchangeModes => 45,
+            ExtensionType::KeyShare => 51,
+            ExtensionType::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u16> for ExtensionType {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::ServerName,
+            5 => Self::StatusRequest,
+            10 => Self::SupportedGroups,
+            13 => Self::SignatureAlgorithms,
+            16 => Self::ApplicationLayerProtocolNegotiation,
+            18 => Self::SignedCertificateTimestamp,
+            41 => Self::PreSharedKey,
+            42 => Self::EarlyData,
+            43 => Self::SupportedVersions,
+            44 => Self::Cookie,
+            45 => Self::PskKeyExchangeModes,
+            51 => Self::KeyShare,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Each cipher suite is exactly two bytes wide. TLS 1.3 only defines suites
+/// that pair an AEAD with a hash for HKDF.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum CipherSuite {
+    Aes128GcmSha256,        // 0x1301
+    Aes256GcmSha384,        // 0x1302
+    Chacha20Poly1305Sha256, // 0x1303
+    Unknown(u16),
+}
+
+impl From<CipherSuite> for u16 {
+    fn from(value: CipherSuite) -> Self {
+        match value {
+            CipherSuite::Aes128GcmSha256 => 0x1301,
+            CipherSuite::Aes256GcmSha384 => 0x1302,
+            CipherSuite::Chacha20Poly1305Sha256 => 0x1303,
+            CipherSuite::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u16> for CipherSuite {
+    fn from(value: u16) -> Self {
+        match value {
+            0x1301 => Self::Aes128GcmSha256,
+            0x1302 => Self::Aes256GcmSha384,
+            0x1303 => Self::Chacha20Poly1305Sha256,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Each handshake message starts with its one-byte type
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HandshakeType {
+    ClientHello,         // 1
+    ServerHello,         // 2
+    NewSessionTicket,    // 4
+    EndOfEarlyData,      // 5
+    EncryptedExtensions, // 8
+    Certificate,         // 11
+    CertificateRequest,  // 13
+    CertificateVerify,   // 15
+    Finished,            // 20
+    KeyUpdate,           // 24
+    MessageHash,         // 254
+    Unknown(u8),
+}
+
+impl From<HandshakeType> for u8 {
+    fn from(value: HandshakeType) -> Self {
+        match value {
+            HandshakeType::ClientHello => 1,
+            HandshakeType::ServerHello => 2,
+            HandshakeType::NewSessionTicket => 4,
+            HandshakeType::EndOfEarlyData => 5,
+            HandshakeType::EncryptedExtensions => 8,
+            HandshakeType::Certificate => 11,
+            HandshakeType::CertificateRequest => 13,
+            HandshakeType::CertificateVerify => 15,
+            HandshakeType::Finished => 20,
+            HandshakeType::KeyUpdate => 24,
+            HandshakeType::MessageHash => 254,
+            HandshakeType::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u8> for HandshakeType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::ClientHello,
+            2 => Self::ServerHello,
+            4 => Self::NewSessionTicket,
+            5 => Self::EndOfEarlyData,
+            8 => Self::EncryptedExtensions,
+            11 => Self::Certificate,
+            13 => Self::CertificateRequest,
+            15 => Self::CertificateVerify,
+            20 => Self::Finished,
+            24 => Self::KeyUpdate,
+            254 => Self::MessageHash,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl HandshakeType {
+    pub(crate) fn is_known(&self) -> bool {
+        return !matches!(self, Self::Unknown(_));
+    }
+}
+
+/// The groups for (EC)DHE key exchange; each is exactly two bytes wide
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum NamedGroup {
+    Secp256r1, // 0x0017
+    Secp384r1, // 0x0018
+    X25519,    // 0x001d
+    Unknown(u16),
+}
+
+impl From<NamedGroup> for u16 {
+    fn from(value: NamedGroup) -> Self {
+        match value {
+            NamedGroup::Secp256r1 => 0x0017,
+            NamedGroup::Secp384r1 => 0x0018,
+            NamedGroup::X25519 => 0x001d,
+            NamedGroup::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u16> for NamedGroup {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0017 => Self::Secp256r1,
+            0x0018 => Self::Secp384r1,
+            0x001d => Self::X25519,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// The signature algorithms a peer may use in CertificateVerify or in its
+/// certificates; each is exactly two bytes wide
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum SignatureScheme {
+    RsaPkcs1Sha256,       // 0x0401
+    RsaPkcs1Sha384,       // 0x0501
+    RsaPkcs1Sha512,       // 0x0601
+    EcdsaSecp256r1Sha256, // 0x0403
+    EcdsaSecp384r1Sha384, // 0x0503
+    RsaPssRsaeSha256,     // 0x0804
+    RsaPssRsaeSha384,     // 0x0805
+    RsaPssRsaeSha512,     // 0x0806
+    Ed25519,              // 0x0807
+    Unknown(u16),
+}
+
+impl From<SignatureScheme> for u16 {
+    fn from(value: SignatureScheme) -> Self {
+        match value {
+            SignatureScheme::RsaPkcs1Sha256 => 0x0401,
+            SignatureScheme::RsaPkcs1Sha384 => 0x0501,
+            SignatureScheme::RsaPkcs1Sha512 => 0x0601,
+            SignatureScheme::EcdsaSecp256r1Sha256 => 0x0403,
+            SignatureScheme::EcdsaSecp384r1Sha384 => 0x0503,
+            SignatureScheme::RsaPssRsaeSha256 => 0x0804,
+            SignatureScheme::RsaPssRsaeSha384 => 0x0805,
+            SignatureScheme::RsaPssRsaeSha512 => 0x0806,
+            SignatureScheme::Ed25519 => 0x0807,
+            SignatureScheme::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u16> for SignatureScheme {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0401 => Self::RsaPkcs1Sha256,
+            0x0501 => Self::RsaPkcs1Sha384,
+            0x0601 => Self::RsaPkcs1Sha512,
+            0x0403 => Self::EcdsaSecp256r1Sha256,
+            0x0503 => Self::EcdsaSecp384r1Sha384,
+            0x0804 => Self::RsaPssRsaeSha256,
+            0x0805 => Self::RsaPssRsaeSha384,
+            0x0806 => Self::RsaPssRsaeSha512,
+            0x0807 => Self::Ed25519,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Each mode is exactly one byte wide (RFC 8446, section 4.2.9)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum PskKeyExchangeMode {
+    /// PSK-only key establishment
+    PskKe, // 0
+
+    /// PSK with (EC)DHE key establishment
+    PskDheKe, // 1
+    Unknown(u8),
+}
+
+impl From<PskKeyExchangeMode> for u8 {
+    fn from(value: PskKeyExchangeMode) -> Self {
+        match value {
+            PskKeyExchangeMode::PskKe => 0,
+            PskKeyExchangeMode::PskDheKe => 1,
+            PskKeyExchangeMode::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u8> for PskKeyExchangeMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::PskKe,
+            1 => Self::PskDheKe,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_types_allowed_in_plaintext() {
+        assert!(ContentType::Handshake.allowed_plaintext());
+        assert!(ContentType::Alert.allowed_plaintext());
+        assert!(ContentType::ChangeCipherSpec.allowed_plaintext());
+        assert!(!ContentType::ApplicationData.allowed_plaintext());
+        assert!(!ContentType::Invalid.allowed_plaintext());
+    }
+
+    #[test]
+    fn protocol_version_from_one_byte() {
+        assert!(matches!(
+            ProtocolVersion::try_from([0x03].as_slice()),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn protocol_version_from_valid_prefix() {
+        let version = ProtocolVersion::try_from([0x03, 0x04, 0xff].as_slice()).unwrap();
+        assert_eq!(version, ProtocolVersion::TLSv1_3);
+    }
+
+    #[test]
+    fn protocol_version_from_unknown_encoding() {
+        assert!(matches!(
+            ProtocolVersion::try_from([0x03, 0x05].as_slice()),
+            Err(TlsError::InvalidProtocolVersion)
+        ));
+    }
+
+    #[test]
+    fn unknown_protocol_version_round_trip() {
+        let version = ProtocolVersion::from_wire(&[0x03, 0x99]).unwrap();
+        assert_eq!(version, ProtocolVersion::Unknown([0x03, 0x99]));
+        assert!(!version.is_known());
+
+        let encoding: [u8; 2] = version.try_into().unwrap();
+        assert_eq!(encoding, [0x03, 0x99]);
+    }
+
+    #[test]
+    fn extension_type_round_trip() {
+        assert_eq!(ExtensionType::from(51), ExtensionType::KeyShare);
+        assert_eq!(u16::from(ExtensionType::SupportedVersions), 43);
+        assert_eq!(ExtensionType::from(0xfe0d), ExtensionType::Unknown(0xfe0d));
+        assert_eq!(u16::from(ExtensionType::Unknown(0xfe0d)), 0xfe0d);
+    }
+
+    #[test]
+    fn lenient_decoding_still_needs_two_bytes() {
+        assert!(ProtocolVersion::from_wire(&[0x03]).is_err());
+        assert_eq!(
+            ProtocolVersion::from_wire(&[0x03, 0x03]).unwrap(),
+            ProtocolVersion::TLSv1_2
+        );
+    }
+
+    #[test]
+    fn content_type_and_version_are_copy() {
+        let content_type = ContentType::Handshake;
+        let version = ProtocolVersion::TLSv1_3;
+        let encoding: u8 = content_type.try_into().unwrap();
+        let version_encoding: [u8; 2] = version.try_into().unwrap();
+
+        // Both were passed by value above and are still usable
+        assert_eq!(encoding, 22);
+        assert_eq!(version_encoding, [0x03, 0x04]);
+        assert!(content_type.allowed_plaintext());
+        assert_eq!(version, ProtocolVersion::TLSv1_3);
+    }
+}
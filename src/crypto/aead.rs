@@ -0,0 +1,197 @@
+//! AEAD keys used to protect records. Every record is sealed under a nonce
+//! derived from a static IV and the record's sequence number (RFC 8446,
+//! section 5.3), so a nonce must never be derived twice for the same key.
+use crate::constants::CipherSuite;
+use crate::error::TlsError;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+
+/// All TLS 1.3 AEADs append a 16-byte authentication tag
+pub(crate) const TAG_LENGTH: usize = 16;
+
+/// How many full-size records may be sealed under one key before the key must
+/// be updated: about 2^24.5 for AES-GCM (RFC 8446, section 5.5), while
+/// ChaCha20-Poly1305 outlasts any sequence number
+pub(crate) fn record_limit(cipher_suite: CipherSuite) -> u64 {
+    return match cipher_suite {
+        CipherSuite::Aes128GcmSha256 | CipherSuite::Aes256GcmSha384 => 23_726_566,
+        _ => u64::MAX,
+    };
+}
+
+/// The nonce of the record with this sequence number (RFC 8446, section 5.3):
+/// the sequence number left-padded to the length of the IV, then XOR-ed with
+/// the IV. The IV must be exactly as long as the nonce of the AEAD, which is at
+/// least the eight bytes of the sequence number.
+pub(crate) fn compute_nonce(
+    iv: &[u8],
+    sequence: u64,
+    nonce_len: usize,
+) -> Result<Vec<u8>, TlsError> {
+    if iv.len() != nonce_len || nonce_len < 8 {
+        return Err(TlsError::DecodeError("invalid traffic iv length"));
+    }
+    let mut nonce = iv.to_vec();
+    for (byte, seq) in nonce[nonce_len - 8..]
+        .iter_mut()
+        .zip(sequence.to_be_bytes())
+    {
+        *byte ^= seq;
+    }
+
+    return Ok(nonce);
+}
+
+/// The key and the IV of one direction of one epoch
+#[allow(dead_code)]
+pub(crate) struct TrafficKey {
+    key: LessSafeKey,
+    iv: Vec<u8>,
+
+    /// The sequence number of the last record sealed under this key. Sealing
+    /// is only allowed for strictly greater sequence numbers, which rules out
+    /// ever producing the same nonce twice.
+    last_sealed: Option<u64>,
+}
+
+#[allow(dead_code)]
+impl TrafficKey {
+    pub(crate) fn new(cipher_suite: CipherSuite, key: &[u8], iv: &[u8]) -> Result<Self, TlsError> {
+        let algorithm = match cipher_suite {
+            CipherSuite::Aes128GcmSha256 => &aead::AES_128_GCM,
+            CipherSuite::Aes256GcmSha384 => &aead::AES_256_GCM,
+            CipherSuite::Chacha20Poly1305Sha256 => &aead::CHACHA20_POLY1305,
+            CipherSuite::Unknown(_) => {
+                return Err(TlsError::IllegalParameter("unsupported cipher suite"))
+            }
+        };
+        let key = UnboundKey::new(algorithm, key)
+            .map_err(|_| TlsError::DecodeError("invalid traffic key length"))?;
+        if iv.len() != algorithm.nonce_len() {
+            return Err(TlsError::DecodeError("invalid traffic iv length"));
+        }
+
+        return Ok(Self {
+            key: LessSafeKey::new(key),
+            iv: iv.to_vec(),
+            last_sealed: None,
+        });
+    }
+
+    fn nonce(&self, sequence: u64) -> Result<Nonce, TlsError> {
+        let nonce = compute_nonce(&self.iv, sequence, self.key.algorithm().nonce_len())?;
+        return Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_| TlsError::Internal("nonce of the wrong length for the AEAD"));
+    }
+
+    /// Encrypt the plaintext in place and append the tag
+    pub(crate) fn seal(
+        &mut self,
+        sequence: u64,
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), TlsError> {
+        if self.last_sealed.is_some_and(|last| sequence <= last) {
+            return Err(TlsError::NonceReuse(sequence));
+        }
+        let nonce = self.nonce(sequence)?;
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::from(aad), in_out)
+            .map_err(|_| TlsError::DecodeError("record too large to seal"))?;
+        self.last_sealed = Some(sequence);
+
+        return Ok(());
+    }
+
+    /// Check the tag and decrypt in place, returning the plaintext, which is
+    /// the input without the trailing tag
+    pub(crate) fn open<'b>(
+        &self,
+        sequence: u64,
+        aad: &[u8],
+        in_out: &'b mut [u8],
+    ) -> Result<&'b mut [u8], TlsError> {
+        let nonce = self.nonce(sequence)?;
+        return self
+            .key
+            .open_in_place(nonce, Aad::from(aad), in_out)
+            .map_err(|_| TlsError::BadRecordMac);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::constants::AEAD_NONCE_LEN;
+
+    /// The server handshake traffic key and IV of RFC 8448, section 3
+    const SERVER_HANDSHAKE_KEY: [u8; 16] = [
+        0x3f, 0xce, 0x51, 0x60, 0x09, 0xc2, 0x17, 0x27, 0xd0, 0xf2, 0xe4, 0xe8, 0x6e, 0xe4, 0x03,
+        0xbc,
+    ];
+    const SERVER_HANDSHAKE_IV: [u8; 12] = [
+        0x5d, 0x31, 0x3b, 0xb2, 0x67, 0x12, 0x76, 0xee, 0x13, 0x00, 0x0b, 0x30,
+    ];
+
+    fn server_handshake_key() -> TrafficKey {
+        return TrafficKey::new(
+            CipherSuite::Aes128GcmSha256,
+            &SERVER_HANDSHAKE_KEY,
+            &SERVER_HANDSHAKE_IV,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn nonce_of_first_records() {
+        let iv = &SERVER_HANDSHAKE_IV;
+
+        assert_eq!(compute_nonce(iv, 0, AEAD_NONCE_LEN).unwrap(), iv);
+        assert_eq!(
+            compute_nonce(iv, 0x0102, AEAD_NONCE_LEN).unwrap(),
+            [0x5d, 0x31, 0x3b, 0xb2, 0x67, 0x12, 0x76, 0xee, 0x13, 0x00, 0x0a, 0x32]
+        );
+    }
+
+    #[test]
+    fn nonce_from_iv_of_the_wrong_length() {
+        assert!(compute_nonce(&[0x5d; 8], 1, AEAD_NONCE_LEN).is_err());
+        assert!(compute_nonce(&SERVER_HANDSHAKE_IV, 1, 8).is_err());
+
+        // The sequence number lands in the last eight bytes of the IV
+        let iv = [0xff; 12];
+        let mut expected = [0xff; 12];
+        expected[4..].copy_from_slice(&(!0x0001_0203_0405_0607u64).to_be_bytes());
+        assert_eq!(
+            compute_nonce(&iv, 0x0001_0203_0405_0607, AEAD_NONCE_LEN).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn seal_then_open() {
+        let mut key = server_handshake_key();
+        let mut in_out = b"hello".to_vec();
+        key.seal(0, b"header", &mut in_out).unwrap();
+        assert_eq!(in_out.len(), 5 + TAG_LENGTH);
+
+        assert_eq!(key.open(0, b"header", &mut in_out).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn open_with_wrong_sequence() {
+        let mut key = server_handshake_key();
+        let mut in_out = b"hello".to_vec();
+        key.seal(0, b"header", &mut in_out).unwrap();
+
+        assert!(matches!(
+            key.open(1, b"header", &mut in_out),
+            Err(TlsError::BadRecordMac)
+        ));
+    }
+
+    #[test]
+    fn invalid_key_length() {
+        assert!(TrafficKey::new(CipherSuite::Aes128GcmSha256, &[0; 15], &[0; 12]).is_err());
+        assert!(TrafficKey::new(CipherSuite::Aes128GcmSha256, &[0; 16], &[0; 8]).is_err());
+    }
+}
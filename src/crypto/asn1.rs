@@ -0,0 +1,157 @@
+//! Just enough DER (X.690) to walk a certificate: the tag and length that
+//! start every value. Only single-byte tags occur in X.509, so high tag
+//! numbers are not supported.
+use crate::error::TlsError;
+
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const SEQUENCE: u8 = 0x30;
+
+/// The explicitly tagged version at the start of a TBSCertificate
+const VERSION: u8 = 0xa0;
+
+/// Split the tag and the length off the start of the buffer, returning them
+/// with the rest of the buffer, which starts with the content. The content
+/// itself must be complete. Lengths up to 127 take the short form; larger ones
+/// take the long form, whose first byte counts the big-endian length bytes
+/// that follow. DER forbids the indefinite length and non-minimal encodings.
+#[allow(dead_code)]
+pub(crate) fn read_tag_len(buf: &[u8]) -> Result<(u8, usize, &[u8]), TlsError> {
+    let (tag, rest) = match buf {
+        [tag, rest @ ..] => (*tag, rest),
+        [] => return Err(TlsError::DecodeError("truncated ASN.1 tag")),
+    };
+    if tag & 0x1f == 0x1f {
+        return Err(TlsError::DecodeError("unsupported ASN.1 high tag number"));
+    }
+    let (first, rest) = match rest {
+        [first, rest @ ..] => (*first, rest),
+        [] => return Err(TlsError::DecodeError("truncated ASN.1 length")),
+    };
+
+    let (length, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let nbytes = usize::from(first & 0x7f);
+        if nbytes == 0 {
+            return Err(TlsError::DecodeError("indefinite ASN.1 length"));
+        }
+        if nbytes > std::mem::size_of::<u32>() {
+            return Err(TlsError::DecodeError("ASN.1 length too large"));
+        }
+        if rest.len() < nbytes {
+            return Err(TlsError::DecodeError("truncated ASN.1 length"));
+        }
+        let (encoding, rest) = rest.split_at(nbytes);
+        if encoding[0] == 0 {
+            return Err(TlsError::DecodeError("non-minimal ASN.1 length"));
+        }
+        let length = encoding
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | usize::from(*byte));
+        if length < 0x80 {
+            return Err(TlsError::DecodeError("non-minimal ASN.1 length"));
+        }
+        (length, rest)
+    };
+    if rest.len() < length {
+        return Err(TlsError::DecodeError("truncated ASN.1 value"));
+    }
+
+    return Ok((tag, length, rest));
+}
+
+/// Split a value of the tag off the start of the buffer, returning its
+/// content and the rest of the buffer after it
+fn read_value(buf: &[u8], expected: u8) -> Result<(&[u8], &[u8]), TlsError> {
+    let (tag, length, rest) = read_tag_len(buf)?;
+    if tag != expected {
+        return Err(TlsError::DecodeError("unexpected ASN.1 tag"));
+    }
+
+    return Ok(rest.split_at(length));
+}
+
+/// The subjectPublicKey of an X.509 certificate, without the unused-bits
+/// byte of its BIT STRING: the point of an EC key, the RSAPublicKey of an RSA
+/// key, or the 32 bytes of an Ed25519 key, which is what ring verifies with
+#[allow(dead_code)]
+pub(crate) fn subject_public_key(cert: &[u8]) -> Result<&[u8], TlsError> {
+    let (certificate, _) = read_value(cert, SEQUENCE)?;
+    let (tbs_certificate, _) = read_value(certificate, SEQUENCE)?;
+    let mut fields = tbs_certificate;
+    if fields.first() == Some(&VERSION) {
+        (_, fields) = read_value(fields, VERSION)?;
+    }
+    (_, fields) = read_value(fields, INTEGER)?; // serialNumber
+    for _ in 0..4 {
+        // signature, issuer, validity, subject
+        (_, fields) = read_value(fields, SEQUENCE)?;
+    }
+    let (spki, _) = read_value(fields, SEQUENCE)?;
+    let (_, spki) = read_value(spki, SEQUENCE)?; // algorithm
+    let (bits, _) = read_value(spki, BIT_STRING)?;
+
+    return match bits {
+        [0, key @ ..] => Ok(key),
+        _ => Err(TlsError::DecodeError("subjectPublicKey is not whole bytes")),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_short_form_length() {
+        let (tag, length, rest) = read_tag_len(&[0x02, 0x01, 0x05, 0xff]).unwrap();
+
+        assert_eq!(tag, 0x02);
+        assert_eq!(length, 1);
+        assert_eq!(rest, [0x05, 0xff]);
+    }
+
+    #[test]
+    fn read_long_form_length() {
+        let mut der = vec![0x30, 0x82, 0x01, 0x00];
+        der.extend_from_slice(&[0xab; 256]);
+        let (tag, length, rest) = read_tag_len(&der).unwrap();
+
+        assert_eq!(tag, 0x30);
+        assert_eq!(length, 256);
+        assert_eq!(rest.len(), 256);
+
+        // 0x7f fits in the short form, so DER does not allow the long form
+        assert!(read_tag_len(&[0x04, 0x81, 0x7f]).is_err());
+        assert!(read_tag_len(&[0x04, 0x80]).is_err());
+    }
+
+    #[test]
+    fn read_subject_public_key() {
+        let cert = include_bytes!("../../testdata/self-signed/cert.der");
+        // The Ed25519 public key of the seed 0x00, 0x01, ..., 0x1f
+        assert_eq!(
+            subject_public_key(cert).unwrap(),
+            hex::decode("03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8")
+                .unwrap()
+        );
+
+        let cert = include_bytes!("../../testdata/cloudflare-dns/ee.der");
+        // An uncompressed P-256 point
+        let key = subject_public_key(cert).unwrap();
+        assert_eq!(key.len(), 65);
+        assert_eq!(key[0], 0x04);
+
+        assert!(subject_public_key(&cert[..100]).is_err());
+        assert!(subject_public_key(&[0x30, 0x00]).is_err());
+    }
+
+    #[test]
+    fn read_truncated_length() {
+        assert!(read_tag_len(&[]).is_err());
+        assert!(read_tag_len(&[0x30]).is_err());
+        assert!(read_tag_len(&[0x30, 0x82, 0x01]).is_err());
+        assert!(read_tag_len(&[0x30, 0x82, 0x01, 0x00, 0xab]).is_err());
+        assert!(read_tag_len(&[0x02, 0x02, 0x05]).is_err());
+    }
+}
@@ -0,0 +1,598 @@
+//! The TLS 1.3 key schedule (RFC 8446, section 7.1). Each stage's secret is
+//! extracted from the previous stage's, and the traffic secrets are expanded
+//! from it together with the transcript hash at that point of the handshake.
+use crate::constants::{CipherSuite, AEAD_NONCE_LEN};
+use crate::crypto::zeroize;
+use crate::error::TlsError;
+use ring::{digest, hmac};
+
+/// The hash that a cipher suite uses for HKDF and for the transcript
+pub(crate) fn hash_algorithm(cipher_suite: CipherSuite) -> &'static digest::Algorithm {
+    return match cipher_suite {
+        CipherSuite::Aes256GcmSha384 => &digest::SHA384,
+        _ => &digest::SHA256,
+    };
+}
+
+fn hmac_algorithm(cipher_suite: CipherSuite) -> hmac::Algorithm {
+    return match cipher_suite {
+        CipherSuite::Aes256GcmSha384 => hmac::HMAC_SHA384,
+        _ => hmac::HMAC_SHA256,
+    };
+}
+
+/// The length of the AEAD key of a cipher suite
+pub(crate) fn key_length(cipher_suite: CipherSuite) -> usize {
+    return match cipher_suite {
+        CipherSuite::Aes128GcmSha256 => 16,
+        _ => 32,
+    };
+}
+
+pub(crate) fn hkdf_extract(algorithm: hmac::Algorithm, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(algorithm, salt);
+    return hmac::sign(&key, ikm).as_ref().to_vec();
+}
+
+/// HKDF-Expand (RFC 5869, section 2.3)
+pub(crate) fn hkdf_expand(
+    algorithm: hmac::Algorithm,
+    prk: &[u8],
+    info: &[u8],
+    length: usize,
+) -> Vec<u8> {
+    let key = hmac::Key::new(algorithm, prk);
+    let mut okm = Vec::with_capacity(length);
+    let mut block: Vec<u8> = vec![];
+    let mut counter = 1u8;
+    while okm.len() < length {
+        let mut context = hmac::Context::with_key(&key);
+        context.update(&block);
+        context.update(info);
+        context.update(&[counter]);
+        block = context.sign().as_ref().to_vec();
+        okm.extend_from_slice(&block);
+        counter += 1;
+    }
+    okm.truncate(length);
+
+    return okm;
+}
+
+/// The info is the HkdfLabel structure: the output length, the label with
+/// the "tls13 " prefix, and the context, the latter two with a one-byte
+/// length prefix
+pub(crate) fn hkdf_expand_label(
+    algorithm: hmac::Algorithm,
+    secret: &[u8],
+    label: &str,
+    context: &[u8],
+    length: usize,
+) -> Vec<u8> {
+    let label = [b"tls13 ", label.as_bytes()].concat();
+    let mut info = (length as u16).to_be_bytes().to_vec();
+    info.push(label.len() as u8);
+    info.extend_from_slice(&label);
+    info.push(context.len() as u8);
+    info.extend_from_slice(context);
+
+    return hkdf_expand(algorithm, secret, &info, length);
+}
+
+/// Derive-Secret: expand the secret with the transcript hash as the context,
+/// to the length of the hash
+pub(crate) fn derive_secret(
+    algorithm: hmac::Algorithm,
+    secret: &[u8],
+    label: &str,
+    transcript_hash: &[u8],
+) -> Vec<u8> {
+    let length = algorithm.digest_algorithm().output_len();
+    return hkdf_expand_label(algorithm, secret, label, transcript_hash, length);
+}
+
+/// The finished_key of a handshake traffic secret (RFC 8446, section 4.4.4),
+/// with which the verify_data of that side's Finished is computed
+pub(crate) fn finished_key(traffic_secret: &[u8], hash: hmac::Algorithm) -> Vec<u8> {
+    let length = hash.digest_algorithm().output_len();
+    return hkdf_expand_label(hash, traffic_secret, "finished", &[], length);
+}
+
+/// The AEAD key and IV expanded from a traffic secret
+pub(crate) fn traffic_key_iv(cipher_suite: CipherSuite, secret: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let algorithm = hmac_algorithm(cipher_suite);
+    let key = hkdf_expand_label(algorithm, secret, "key", &[], key_length(cipher_suite));
+    let iv = hkdf_expand_label(algorithm, secret, "iv", &[], AEAD_NONCE_LEN);
+
+    return (key, iv);
+}
+
+/// The client key, client IV, server key, and server IV of one epoch
+pub(crate) type TrafficKeys = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+/// The traffic secrets as hex, in the form that RFC 8448 prints them. A
+/// secret that is not derived yet is empty.
+#[cfg(feature = "dangerous")]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Secrets {
+    pub(crate) client_handshake_traffic_secret: String,
+    pub(crate) server_handshake_traffic_secret: String,
+    pub(crate) client_application_traffic_secret: String,
+    pub(crate) server_application_traffic_secret: String,
+}
+
+#[allow(dead_code)]
+pub(crate) struct KeySchedule {
+    cipher_suite: CipherSuite,
+
+    /// The secret of the current stage: the early secret, the handshake
+    /// secret, then the master secret
+    secret: Vec<u8>,
+
+    /// Only when the early secret is extracted from a PSK
+    binder_key: Option<Vec<u8>>,
+
+    client_handshake_traffic_secret: Option<Vec<u8>>,
+    server_handshake_traffic_secret: Option<Vec<u8>>,
+    client_application_traffic_secret: Option<Vec<u8>>,
+    server_application_traffic_secret: Option<Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl KeySchedule {
+    /// Start at the early secret. Without a PSK, the input keying material is
+    /// a string of zeros as long as the hash.
+    pub(crate) fn new(cipher_suite: CipherSuite) -> Self {
+        let zeros = vec![0; hash_algorithm(cipher_suite).output_len()];
+        return Self::with_early_secret(cipher_suite, &zeros);
+    }
+
+    /// Start at the early secret extracted from a resumption PSK, and derive
+    /// the binder_key that the PSK binders in ClientHello are keyed with
+    pub(crate) fn from_psk(cipher_suite: CipherSuite, psk: &[u8]) -> Self {
+        let mut key_schedule = Self::with_early_secret(cipher_suite, psk);
+        let empty_hash = digest::digest(hash_algorithm(cipher_suite), &[]);
+        key_schedule.binder_key = Some(derive_secret(
+            key_schedule.algorithm(),
+            &key_schedule.secret,
+            "res binder",
+            empty_hash.as_ref(),
+        ));
+
+        return key_schedule;
+    }
+
+    fn with_early_secret(cipher_suite: CipherSuite, ikm: &[u8]) -> Self {
+        let algorithm = hmac_algorithm(cipher_suite);
+        let zeros = vec![0; algorithm.digest_algorithm().output_len()];
+
+        return Self {
+            cipher_suite,
+            secret: hkdf_extract(algorithm, &zeros, ikm),
+            binder_key: None,
+            client_handshake_traffic_secret: None,
+            server_handshake_traffic_secret: None,
+            client_application_traffic_secret: None,
+            server_application_traffic_secret: None,
+        };
+    }
+
+    pub(crate) fn cipher_suite(&self) -> CipherSuite {
+        return self.cipher_suite;
+    }
+
+    fn algorithm(&self) -> hmac::Algorithm {
+        return hmac_algorithm(self.cipher_suite);
+    }
+
+    pub(crate) fn binder_key(&self) -> Option<&[u8]> {
+        return self.binder_key.as_deref();
+    }
+
+    /// The handshake traffic secret of one side, which only exists once the
+    /// shared secret is mixed in
+    fn handshake_traffic_secret(secret: &Option<Vec<u8>>) -> Result<&[u8], TlsError> {
+        return secret.as_deref().ok_or(TlsError::WrongKeyScheduleState(
+            "handshake secrets are not derived yet",
+        ));
+    }
+
+    /// The application traffic secret of one side, which only exists once the
+    /// master secret is reached
+    fn application_traffic_secret(secret: &Option<Vec<u8>>) -> Result<&[u8], TlsError> {
+        return secret.as_deref().ok_or(TlsError::WrongKeyScheduleState(
+            "application secrets are not derived yet",
+        ));
+    }
+
+    /// Derive-Secret(secret, "derived", ""), the salt of the next stage
+    fn derived_salt(&self) -> Vec<u8> {
+        let empty_hash = digest::digest(hash_algorithm(self.cipher_suite), &[]);
+        return derive_secret(
+            self.algorithm(),
+            &self.secret,
+            "derived",
+            empty_hash.as_ref(),
+        );
+    }
+
+    /// Advance to the handshake secret with the (EC)DHE shared secret, then
+    /// derive both handshake traffic secrets over the hash of ClientHello and
+    /// ServerHello. The schedule must still be at the early secret.
+    pub(crate) fn derive_handshake_secrets(
+        &mut self,
+        shared_secret: &[u8],
+        hello_hash: &[u8],
+    ) -> Result<(), TlsError> {
+        if self.client_handshake_traffic_secret.is_some() {
+            return Err(TlsError::WrongKeyScheduleState(
+                "handshake secrets are already derived",
+            ));
+        }
+        self.secret = hkdf_extract(self.algorithm(), &self.derived_salt(), shared_secret);
+        self.client_handshake_traffic_secret = Some(derive_secret(
+            self.algorithm(),
+            &self.secret,
+            "c hs traffic",
+            hello_hash,
+        ));
+        self.server_handshake_traffic_secret = Some(derive_secret(
+            self.algorithm(),
+            &self.secret,
+            "s hs traffic",
+            hello_hash,
+        ));
+
+        return Ok(());
+    }
+
+    /// The client key, client IV, server key, and server IV that protect the
+    /// rest of the handshake
+    pub(crate) fn handshake_keys(
+        &self,
+        cipher_suite: CipherSuite,
+    ) -> Result<TrafficKeys, TlsError> {
+        let client_secret = Self::handshake_traffic_secret(&self.client_handshake_traffic_secret)?;
+        let server_secret = Self::handshake_traffic_secret(&self.server_handshake_traffic_secret)?;
+        let (client_key, client_iv) = traffic_key_iv(cipher_suite, client_secret);
+        let (server_key, server_iv) = traffic_key_iv(cipher_suite, server_secret);
+
+        return Ok((client_key, client_iv, server_key, server_iv));
+    }
+
+    /// The finished_key of a handshake traffic secret as an HMAC key, keyed
+    /// with which the HMAC of the transcript hash is the verify_data of
+    /// Finished
+    fn finished_hmac_key(&self, handshake_traffic_secret: &[u8]) -> hmac::Key {
+        let mut finished_key = finished_key(handshake_traffic_secret, self.algorithm());
+        let key = hmac::Key::new(self.algorithm(), &finished_key);
+        zeroize(&mut finished_key);
+
+        return key;
+    }
+
+    /// Check the verify_data of the server Finished against the hash of the
+    /// transcript up to, but not including, the Finished
+    pub(crate) fn verify_server_finished(
+        &self,
+        transcript_hash: &[u8],
+        verify_data: &[u8],
+    ) -> Result<(), TlsError> {
+        let server_secret = Self::handshake_traffic_secret(&self.server_handshake_traffic_secret)?;
+        return hmac::verify(
+            &self.finished_hmac_key(server_secret),
+            transcript_hash,
+            verify_data,
+        )
+        .map_err(|_| TlsError::DecryptError("server Finished does not verify"));
+    }
+
+    /// The verify_data of the server Finished over the hash of the transcript
+    /// up to CertificateVerify, for the server side of the handshake
+    pub(crate) fn server_finished_verify_data(
+        &self,
+        transcript_hash: &[u8],
+    ) -> Result<Vec<u8>, TlsError> {
+        let server_secret = Self::handshake_traffic_secret(&self.server_handshake_traffic_secret)?;
+        return Ok(
+            hmac::sign(&self.finished_hmac_key(server_secret), transcript_hash)
+                .as_ref()
+                .to_vec(),
+        );
+    }
+
+    /// The verify_data of the client Finished over the hash of the transcript
+    /// up to the server Finished
+    pub(crate) fn client_finished_verify_data(
+        &self,
+        transcript_hash: &[u8],
+    ) -> Result<Vec<u8>, TlsError> {
+        let client_secret = Self::handshake_traffic_secret(&self.client_handshake_traffic_secret)?;
+        return Ok(
+            hmac::sign(&self.finished_hmac_key(client_secret), transcript_hash)
+                .as_ref()
+                .to_vec(),
+        );
+    }
+
+    /// Advance to the master secret, then derive both application traffic
+    /// secrets over the hash of the transcript up to the server Finished. The
+    /// schedule must be at the handshake secret.
+    pub(crate) fn derive_application_secrets(
+        &mut self,
+        handshake_hash: &[u8],
+    ) -> Result<(), TlsError> {
+        if self.client_handshake_traffic_secret.is_none() {
+            return Err(TlsError::WrongKeyScheduleState(
+                "handshake secrets are not derived yet",
+            ));
+        }
+        if self.client_application_traffic_secret.is_some() {
+            return Err(TlsError::WrongKeyScheduleState(
+                "application secrets are already derived",
+            ));
+        }
+        let zeros = vec![0; self.algorithm().digest_algorithm().output_len()];
+        self.secret = hkdf_extract(self.algorithm(), &self.derived_salt(), &zeros);
+        self.client_application_traffic_secret = Some(derive_secret(
+            self.algorithm(),
+            &self.secret,
+            "c ap traffic",
+            handshake_hash,
+        ));
+        self.server_application_traffic_secret = Some(derive_secret(
+            self.algorithm(),
+            &self.secret,
+            "s ap traffic",
+            handshake_hash,
+        ));
+
+        return Ok(());
+    }
+
+    /// The client key, client IV, server key, and server IV that protect the
+    /// application data
+    pub(crate) fn application_keys(
+        &self,
+        cipher_suite: CipherSuite,
+    ) -> Result<TrafficKeys, TlsError> {
+        let client_secret =
+            Self::application_traffic_secret(&self.client_application_traffic_secret)?;
+        let server_secret =
+            Self::application_traffic_secret(&self.server_application_traffic_secret)?;
+        let (client_key, client_iv) = traffic_key_iv(cipher_suite, client_secret);
+        let (server_key, server_iv) = traffic_key_iv(cipher_suite, server_secret);
+
+        return Ok((client_key, client_iv, server_key, server_iv));
+    }
+
+    /// A copy of the traffic secrets, which outlives the schedule and is not
+    /// zeroized
+    #[cfg(feature = "dangerous")]
+    pub(crate) fn dump_secrets(&self) -> Secrets {
+        let encode =
+            |secret: &Option<Vec<u8>>| secret.as_deref().map(hex::encode).unwrap_or_default();
+
+        return Secrets {
+            client_handshake_traffic_secret: encode(&self.client_handshake_traffic_secret),
+            server_handshake_traffic_secret: encode(&self.server_handshake_traffic_secret),
+            client_application_traffic_secret: encode(&self.client_application_traffic_secret),
+            server_application_traffic_secret: encode(&self.server_application_traffic_secret),
+        };
+    }
+
+    /// Move the client application traffic secret on to its next generation,
+    /// as a KeyUpdate does (RFC 8446, section 7.2), and return the key and IV
+    /// that follow from it
+    pub(crate) fn update_client_application_keys(
+        &mut self,
+    ) -> Result<(Vec<u8>, Vec<u8>), TlsError> {
+        let algorithm = self.algorithm();
+        let cipher_suite = self.cipher_suite;
+        let secret = self.client_application_traffic_secret.as_mut().ok_or(
+            TlsError::WrongKeyScheduleState("application secrets are not derived yet"),
+        )?;
+        let next = hkdf_expand_label(algorithm, secret, "traffic upd", &[], secret.len());
+        zeroize(secret);
+        *secret = next;
+
+        return Ok(traffic_key_iv(cipher_suite, secret));
+    }
+}
+
+/// No secret of the schedule outlives it
+impl Drop for KeySchedule {
+    fn drop(&mut self) {
+        zeroize(&mut self.secret);
+        for secret in [
+            &mut self.binder_key,
+            &mut self.client_handshake_traffic_secret,
+            &mut self.server_handshake_traffic_secret,
+            &mut self.client_application_traffic_secret,
+            &mut self.server_application_traffic_secret,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            zeroize(secret);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::constants::HandshakeType;
+    use crate::handshake::HandshakeMessage;
+
+    fn bytes(encoding: &str) -> Vec<u8> {
+        return hex::decode(encoding).unwrap();
+    }
+
+    /// RFC 8448, section 3: the hash of ClientHello and ServerHello
+    const HELLO_HASH: &str = "860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8";
+
+    #[test]
+    fn rfc8448_early_secret() {
+        let key_schedule = KeySchedule::new(CipherSuite::Aes128GcmSha256);
+
+        assert_eq!(
+            key_schedule.secret,
+            bytes("33ad0a1c607ec03b09e6cd9893680ce210adf300aa1f2660e1b22e10f170f92a")
+        );
+        assert_eq!(
+            key_schedule.derived_salt(),
+            bytes("6f2615a108c702c5678f54fc9dbab69716c076189c48250cebeac3576c3611ba")
+        );
+    }
+
+    /// RFC 8448, section 4: the PSK from the ticket of section 3, then the
+    /// early secret and the binder_key of the resumed handshake
+    #[test]
+    fn rfc8448_resumption_binder_key() {
+        let key_schedule = KeySchedule::from_psk(
+            CipherSuite::Aes128GcmSha256,
+            &bytes("4ecd0eb6ec3b4d87f5d6028f922ca4c5851a277fd41311c9e62d2c9492e1c4f3"),
+        );
+
+        assert_eq!(
+            key_schedule.secret,
+            bytes("9b2188e9b2fc6d64d71dc329900e20bb41915000f678aa839cbb797cb7d8332c")
+        );
+        assert_eq!(
+            key_schedule.binder_key().unwrap(),
+            bytes("69fe131a3bbad5d63c64eebcc30e395b9d8107726a13d074e389dbc8a4e47256")
+        );
+        assert!(KeySchedule::new(CipherSuite::Aes128GcmSha256)
+            .binder_key()
+            .is_none());
+    }
+
+    /// RFC 8448, section 3: the hash from ClientHello to the server Finished
+    const HANDSHAKE_HASH: &str = "9608102a0f1ccc6db6250b7b7e417b1a000eaada3daae4777a7686c9ff83df13";
+
+    fn rfc8448_key_schedule() -> KeySchedule {
+        let mut key_schedule = KeySchedule::new(CipherSuite::Aes128GcmSha256);
+        key_schedule
+            .derive_handshake_secrets(
+                &bytes("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d"),
+                &bytes(HELLO_HASH),
+            )
+            .unwrap();
+        return key_schedule;
+    }
+
+    #[test]
+    fn rfc8448_handshake_keys() {
+        let key_schedule = rfc8448_key_schedule();
+        assert_eq!(
+            key_schedule.secret,
+            bytes("1dc826e93606aa6fdc0aadc12f741b01046aa6b99f691ed221a9f0ca043fbeac")
+        );
+
+        let (client_key, client_iv, server_key, server_iv) = key_schedule
+            .handshake_keys(CipherSuite::Aes128GcmSha256)
+            .unwrap();
+        assert_eq!(client_key, bytes("dbfaa693d1762c5b666af5d950258d01"));
+        assert_eq!(client_iv, bytes("5bd3c71b836e0b76bb73265f"));
+        assert_eq!(server_key, bytes("3fce516009c21727d0f2e4e86ee403bc"));
+        assert_eq!(server_iv, bytes("5d313eb2671276ee13000b30"));
+    }
+
+    #[test]
+    fn rfc8448_finished_key() {
+        let key_schedule = rfc8448_key_schedule();
+        let server_secret = key_schedule
+            .server_handshake_traffic_secret
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            *server_secret,
+            bytes("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38")
+        );
+
+        assert_eq!(
+            finished_key(server_secret, hmac::HMAC_SHA256),
+            bytes("008d3b66f816ea559f96b537e885c31fc068bf492c652f01f288a1d8cdc19fc8")
+        );
+    }
+
+    #[test]
+    fn rfc8448_server_finished() {
+        let key_schedule = rfc8448_key_schedule();
+        let transcript_hash =
+            bytes("edb7725fa7a3473b031ec8ef65a2485493900138a2b91291407d7951a06110ed");
+        let verify_data = bytes("9b9b141d906337fbd2cbdce71df4deda4ab42c309572cb7fffee5454b78f0718");
+
+        assert!(key_schedule
+            .verify_server_finished(&transcript_hash, &verify_data)
+            .is_ok());
+        assert!(matches!(
+            key_schedule.verify_server_finished(&transcript_hash, &[0; 32]),
+            Err(TlsError::DecryptError(_))
+        ));
+    }
+
+    #[test]
+    fn rfc8448_client_finished() {
+        let key_schedule = rfc8448_key_schedule();
+        let finished = HandshakeMessage {
+            msg_type: HandshakeType::Finished,
+            body: key_schedule
+                .client_finished_verify_data(&bytes(HANDSHAKE_HASH))
+                .unwrap(),
+        };
+
+        assert_eq!(
+            Vec::<u8>::from(finished),
+            bytes("14000020a8ec436d677634ae525ac1fcebe11a039ec17694fac6e98527b642f2edd5ce61")
+        );
+    }
+
+    #[test]
+    fn rfc8448_application_keys() {
+        let mut key_schedule = rfc8448_key_schedule();
+        key_schedule
+            .derive_application_secrets(&bytes(HANDSHAKE_HASH))
+            .unwrap();
+        assert_eq!(
+            key_schedule.secret,
+            bytes("18df06843d13a08bf2a449844c5f8a478001bc4d4c627984d5a41da8d0402919")
+        );
+
+        let (client_key, client_iv, server_key, server_iv) = key_schedule
+            .application_keys(CipherSuite::Aes128GcmSha256)
+            .unwrap();
+        assert_eq!(client_key, bytes("17422dda596ed5d9acd890e3c63f5051"));
+        assert_eq!(client_iv, bytes("5b78923dee08579033e523d9"));
+        assert_eq!(server_key, bytes("9f02283b6c9c07efc26bb9f2ac92e356"));
+        assert_eq!(server_iv, bytes("cf782b88dd83549aadf1e984"));
+    }
+
+    #[test]
+    fn refuse_secrets_out_of_order() {
+        let mut key_schedule = KeySchedule::new(CipherSuite::Aes128GcmSha256);
+        assert!(matches!(
+            key_schedule.application_keys(CipherSuite::Aes128GcmSha256),
+            Err(TlsError::WrongKeyScheduleState(_))
+        ));
+        assert!(matches!(
+            key_schedule.handshake_keys(CipherSuite::Aes128GcmSha256),
+            Err(TlsError::WrongKeyScheduleState(_))
+        ));
+        assert!(matches!(
+            key_schedule.derive_application_secrets(&bytes(HANDSHAKE_HASH)),
+            Err(TlsError::WrongKeyScheduleState(_))
+        ));
+
+        let mut key_schedule = rfc8448_key_schedule();
+        assert!(matches!(
+            key_schedule.derive_handshake_secrets(&[0; 32], &bytes(HELLO_HASH)),
+            Err(TlsError::WrongKeyScheduleState(_))
+        ));
+        assert!(matches!(
+            key_schedule.update_client_application_keys(),
+            Err(TlsError::WrongKeyScheduleState(_))
+        ));
+    }
+}
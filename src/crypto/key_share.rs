@@ -0,0 +1,93 @@
+//! The private half of a key share and the key exchange with the peer's
+//! public half. X25519 keys come from the injected Rng like every other
+//! secret; ring generates P-256 keys from its own RNG and lets each of them
+//! be used for exactly one key exchange.
+use crate::constants::NamedGroup;
+use crate::crypto::x25519::{self, KEY_LENGTH};
+use crate::crypto::Rng;
+use crate::error::TlsError;
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, ECDH_P256};
+use ring::rand::SystemRandom;
+
+#[allow(dead_code)]
+pub(crate) enum KeySharePrivate {
+    X25519([u8; KEY_LENGTH]),
+    Secp256r1(EphemeralPrivateKey),
+}
+
+#[allow(dead_code)]
+impl KeySharePrivate {
+    /// A fresh private key for the group, together with the public key in the
+    /// encoding of KeyShareEntry.key_exchange
+    pub(crate) fn generate(
+        group: NamedGroup,
+        rng: &mut dyn Rng,
+    ) -> Result<(Self, Vec<u8>), TlsError> {
+        return match group {
+            NamedGroup::X25519 => {
+                let mut private_key = [0; KEY_LENGTH];
+                rng.fill(&mut private_key);
+                let public_key = x25519::x25519_base(&private_key).to_vec();
+                Ok((Self::X25519(private_key), public_key))
+            }
+            NamedGroup::Secp256r1 => {
+                let rng = SystemRandom::new();
+                let private_key = EphemeralPrivateKey::generate(&ECDH_P256, &rng)
+                    .map_err(|_| TlsError::IllegalParameter("cannot generate a secp256r1 key"))?;
+                let public_key = private_key
+                    .compute_public_key()
+                    .map_err(|_| TlsError::IllegalParameter("cannot generate a secp256r1 key"))?
+                    .as_ref()
+                    .to_vec();
+                Ok((Self::Secp256r1(private_key), public_key))
+            }
+            _ => Err(TlsError::IllegalParameter("unsupported key share group")),
+        };
+    }
+
+    pub(crate) fn group(&self) -> NamedGroup {
+        return match self {
+            Self::X25519(_) => NamedGroup::X25519,
+            Self::Secp256r1(_) => NamedGroup::Secp256r1,
+        };
+    }
+
+    /// The shared secret with the peer's public key, which uses up the private
+    /// key
+    pub(crate) fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>, TlsError> {
+        return match self {
+            Self::X25519(private_key) => {
+                let peer_public_key: [u8; KEY_LENGTH] = peer_public_key
+                    .try_into()
+                    .map_err(|_| TlsError::IllegalParameter("invalid x25519 key share"))?;
+                Ok(x25519::x25519(&private_key, &peer_public_key).to_vec())
+            }
+            Self::Secp256r1(private_key) => agreement::agree_ephemeral(
+                private_key,
+                &UnparsedPublicKey::new(&ECDH_P256, peer_public_key),
+                |shared_secret| shared_secret.to_vec(),
+            )
+            .map_err(|_| TlsError::IllegalParameter("invalid secp256r1 key share")),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::OsRng;
+
+    #[test]
+    fn both_sides_agree() {
+        for group in [NamedGroup::X25519, NamedGroup::Secp256r1] {
+            let (client, client_public) = KeySharePrivate::generate(group, &mut OsRng).unwrap();
+            let (server, server_public) = KeySharePrivate::generate(group, &mut OsRng).unwrap();
+            assert_eq!(client.group(), group);
+
+            assert_eq!(
+                client.agree(&server_public).unwrap(),
+                server.agree(&client_public).unwrap()
+            );
+        }
+    }
+}
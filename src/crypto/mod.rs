@@ -0,0 +1,38 @@
+//! The cryptographic building blocks of TLS 1.3: record protection, the key
+//! exchange, the key schedule, the transcript hash, and enough DER to read
+//! certificates
+pub(crate) mod aead;
+pub(crate) mod asn1;
+pub(crate) mod key_schedule;
+pub(crate) mod key_share;
+pub(crate) mod transcript;
+pub(crate) mod x25519;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Overwrite a secret with zeros before its memory is given up. black_box
+/// keeps the compiler from treating the writes as dead stores.
+pub(crate) fn zeroize(secret: &mut [u8]) {
+    secret.fill(0);
+    std::hint::black_box(secret);
+}
+
+/// The source of every random value the client puts on the wire or keeps as a
+/// secret: the ClientHello random, the legacy session id, and the private key
+/// shares. Production code uses OsRng; tests can plug in something
+/// deterministic.
+pub(crate) trait Rng {
+    fn fill(&mut self, buf: &mut [u8]);
+}
+
+/// The operating system's RNG, by way of ring
+#[allow(dead_code)]
+pub(crate) struct OsRng;
+
+impl Rng for OsRng {
+    fn fill(&mut self, buf: &mut [u8]) {
+        SystemRandom::new()
+            .fill(buf)
+            .expect("the system RNG is unavailable");
+    }
+}
@@ -0,0 +1,322 @@
+//! The transcript covers every handshake message sent and received so far, in
+//! wire format including the handshake header. Its hash is the context of the
+//! traffic secrets, of CertificateVerify, and of Finished.
+use crate::constants::HandshakeType;
+use crate::error::TlsError;
+use ring::digest;
+
+/// Whether the transcript keeps the messages once it can hash them
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum TranscriptMode {
+    /// Keep every message, so that transcript_bytes can show them, e.g. when
+    /// debugging a handshake
+    KeepBytes,
+
+    /// Feed the messages to the hash as they come in, so that a long
+    /// certificate chain is not held on to
+    HashOnly,
+}
+
+#[allow(dead_code)]
+pub(crate) struct Transcript {
+    mode: TranscriptMode,
+
+    /// The hash is only known once ServerHello picks the cipher suite, so
+    /// until then the messages themselves are kept. In KeepBytes mode they
+    /// are kept throughout.
+    messages: Vec<u8>,
+
+    /// The running hash, from start_hash on
+    hash: Option<digest::Context>,
+}
+
+#[allow(dead_code)]
+impl Transcript {
+    pub(crate) fn new() -> Self {
+        return Self::with_mode(TranscriptMode::KeepBytes);
+    }
+
+    pub(crate) fn with_mode(mode: TranscriptMode) -> Self {
+        return Self {
+            mode,
+            messages: vec![],
+            hash: None,
+        };
+    }
+
+    /// Start over without giving up the allocated buffer
+    pub(crate) fn clear(&mut self) {
+        self.messages.clear();
+        self.hash = None;
+    }
+
+    pub(crate) fn update(&mut self, message: &[u8]) {
+        if let Some(hash) = self.hash.as_mut() {
+            hash.update(message);
+        }
+        if self.hash.is_none() || self.mode == TranscriptMode::KeepBytes {
+            self.messages.extend_from_slice(message);
+        }
+    }
+
+    /// Add every message of a flight, such as one captured from a trace, given
+    /// as concatenated handshake messages with their headers. Nothing is added
+    /// unless the whole flight is well framed.
+    pub(crate) fn update_from_flight(&mut self, flight: &[u8]) -> Result<(), TlsError> {
+        let mut messages = vec![];
+        let mut remainder = flight;
+        while !remainder.is_empty() {
+            // msg_type (1) || length (3) || body
+            if remainder.len() < 4 {
+                return Err(TlsError::DecodeError("truncated handshake header"));
+            }
+            let length = u32::from_be_bytes([0, remainder[1], remainder[2], remainder[3]]) as usize;
+            if remainder.len() - 4 < length {
+                return Err(TlsError::LengthMismatch {
+                    field: "handshake message",
+                    declared: length,
+                    actual: remainder.len() - 4,
+                });
+            }
+            let (message, rest) = remainder.split_at(4 + length);
+            messages.push(message);
+            remainder = rest;
+        }
+        for message in messages {
+            self.update(message);
+        }
+
+        return Ok(());
+    }
+
+    /// Hash the messages so far with the algorithm of the negotiated cipher
+    /// suite, and every later message as it is added. In HashOnly mode the
+    /// messages are dropped from here on. Starting again does nothing.
+    pub(crate) fn start_hash(&mut self, algorithm: &'static digest::Algorithm) {
+        if self.hash.is_some() {
+            return;
+        }
+        let mut hash = digest::Context::new(algorithm);
+        hash.update(&self.messages);
+        self.hash = Some(hash);
+        if self.mode == TranscriptMode::HashOnly {
+            self.messages.clear();
+        }
+    }
+
+    /// The hash of every message added so far. Once start_hash has been
+    /// called, the algorithm must be the one passed to it.
+    pub(crate) fn current_hash(&self, algorithm: &'static digest::Algorithm) -> Vec<u8> {
+        return match &self.hash {
+            Some(hash) => {
+                debug_assert!(hash.algorithm() == algorithm);
+                hash.clone().finish().as_ref().to_vec()
+            }
+            None => digest::digest(algorithm, &self.messages).as_ref().to_vec(),
+        };
+    }
+
+    /// The hash of every message added so far, under the algorithm passed to
+    /// start_hash, or SHA-256 before then, for comparing a replayed handshake
+    /// against the hashes of a trace message by message
+    pub(crate) fn checkpoint(&self) -> Vec<u8> {
+        return match &self.hash {
+            Some(hash) => hash.clone().finish().as_ref().to_vec(),
+            None => digest::digest(&digest::SHA256, &self.messages)
+                .as_ref()
+                .to_vec(),
+        };
+    }
+
+    /// Collapse the transcript, which holds just the first ClientHello at this
+    /// point, into the synthetic message_hash message that stands in for it
+    /// after a HelloRetryRequest (RFC 8446, section 4.4.1). The cipher suite
+    /// is known by then, so hashing starts too.
+    pub(crate) fn replace_with_message_hash(&mut self, algorithm: &'static digest::Algorithm) {
+        let hash = self.current_hash(algorithm);
+        self.clear();
+        self.update(&[u8::from(HandshakeType::MessageHash), 0, 0, hash.len() as u8]);
+        self.update(&hash);
+        self.start_hash(algorithm);
+    }
+
+    /// Every message so far in KeepBytes mode; nothing in HashOnly mode
+    pub(crate) fn transcript_bytes(&self) -> Option<&[u8]> {
+        return match self.mode {
+            TranscriptMode::KeepBytes => Some(&self.messages),
+            TranscriptMode::HashOnly => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The handshake messages of RFC 8448, section 3, up to the server
+    /// Finished
+    const CLIENT_HELLO: &str = concat!(
+        "010000c00303cb34ecb1e78163ba1c38c6dacb196a6dffa21a8d9912ec18a2ef6283024dece70000",
+        "06130113031302010000910000000b0009000006736572766572ff01000100000a00140012001d00",
+        "17001800190100010101020103010400230000003300260024001d002099381de560e4bd43d23d8e",
+        "435a7dbafeb3c06e51c13cae4d5413691e529aaf2c002b0003020304000d0020001e040305030603",
+        "020308040805080604010501060102010402050206020202002d00020101001c00024001",
+    );
+
+    const SERVER_HELLO: &str = concat!(
+        "020000560303a6af06a4121860dc5e6e60249cd34c95930c8ac5cb1434dac155772ed3e269280013",
+        "0100002e00330024001d0020c9828876112095fe66762bdbf7c672e156d6cc253b833df1dd69b1b0",
+        "4e751f0f002b00020304",
+    );
+
+    const ENCRYPTED_EXTENSIONS: &str =
+        "080000240022000a00140012001d00170018001901000101010201030104001c0002400100000000";
+
+    const CERTIFICATE: &str = concat!(
+        "0b0001b9000001b50001b0308201ac30820115a003020102020102300d06092a864886f70d01010b",
+        "0500300e310c300a06035504031303727361301e170d3136303733303031323335395a170d323630",
+        "3733303031323335395a300e310c300a0603550403130372736130819f300d06092a864886f70d01",
+        "0101050003818d0030818902818100b4bb498f8279303d980836399b36c6988c0c68de55e1bdb826",
+        "d3901a2461eafd2de49a91d015abbc9a95137ace6c1af19eaa6af98c7ced43120998e187a80ee0cc",
+        "b0524b1b018c3e0b63264d449a6d38e22a5fda430846748030530ef0461c8ca9d9efbfae8ea6d1d0",
+        "3e2bd193eff0ab9a8002c47428a6d35a8d88d79f7f1e3f0203010001a31a301830090603551d1304",
+        "023000300b0603551d0f0404030205a0300d06092a864886f70d01010b05000381810085aad2a0e5",
+        "b9276b908c65f73a7267170618a54c5f8a7b337d2df7a594365417f2eae8f8a58c8f8172f9319cf3",
+        "6b7fd6c55b80f21a03015156726096fd335e5e67f2dbf102702e608ccae6bec1fc63a42a99be5c3e",
+        "b7107c3c54e9b9eb2bd5203b1c3b84e0a8b2f759409ba3eac9d91d402dcc0cc8f8961229ac9187b4",
+        "2b4de10000",
+    );
+
+    const CERTIFICATE_VERIFY: &str = concat!(
+        "0f000084080400805a747c5d88fa9bd2e55ab085a61015b7211f824cd484145ab3ff52f1fda8477b",
+        "0b7abc90db78e2d33a5c141a078653fa6bef780c5ea248eeaaa785c4f394cab6d30bbe8d4859ee51",
+        "1f602957b15411ac027671459e46445c9ea58c181e818e95b8c3fb0bf3278409d3be152a3da5043e",
+        "063dda65cdf5aea20d53dfacd42f74f3",
+    );
+
+    const SERVER_FINISHED: &str =
+        "140000209b9b141d906337fbd2cbdce71df4deda4ab42c309572cb7fffee5454b78f0718";
+
+    #[test]
+    fn rfc8448_checkpoints() {
+        // The hashes that the trace documents as the context of the handshake
+        // traffic secrets, of the server Finished, and of the application
+        // traffic secrets
+        let flight = [
+            (CLIENT_HELLO, None),
+            (
+                SERVER_HELLO,
+                Some("860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8"),
+            ),
+            (ENCRYPTED_EXTENSIONS, None),
+            (CERTIFICATE, None),
+            (
+                CERTIFICATE_VERIFY,
+                Some("edb7725fa7a3473b031ec8ef65a2485493900138a2b91291407d7951a06110ed"),
+            ),
+            (
+                SERVER_FINISHED,
+                Some("9608102a0f1ccc6db6250b7b7e417b1a000eaada3daae4777a7686c9ff83df13"),
+            ),
+        ];
+        let mut transcript = Transcript::with_mode(TranscriptMode::HashOnly);
+        for (message, expected) in flight {
+            transcript.update(&hex::decode(message).unwrap());
+            if transcript.hash.is_none() {
+                // ServerHello picks TLS_AES_128_GCM_SHA256
+                transcript.start_hash(&digest::SHA256);
+            }
+            if let Some(expected) = expected {
+                assert_eq!(transcript.checkpoint(), hex::decode(expected).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn hash_is_over_concatenated_messages() {
+        let mut transcript = Transcript::new();
+        transcript.update(b"client hello");
+        transcript.update(b"server hello");
+
+        assert_eq!(
+            transcript.transcript_bytes().unwrap(),
+            b"client helloserver hello"
+        );
+        assert_eq!(
+            transcript.current_hash(&digest::SHA256),
+            digest::digest(&digest::SHA256, b"client helloserver hello").as_ref()
+        );
+    }
+
+    #[test]
+    fn message_hash_replaces_client_hello() {
+        let client_hello = [0x01, 0x00, 0x00, 0x02, 0x03, 0x03];
+        let mut transcript = Transcript::new();
+        transcript.update(&client_hello);
+        transcript.replace_with_message_hash(&digest::SHA384);
+
+        let hash = digest::digest(&digest::SHA384, &client_hello);
+        assert_eq!(
+            transcript.transcript_bytes().unwrap()[..4],
+            [0xfe, 0x00, 0x00, 48]
+        );
+        assert_eq!(&transcript.transcript_bytes().unwrap()[4..], hash.as_ref());
+
+        // Later messages hash after the replacement, not after ClientHello
+        transcript.update(b"hello retry request");
+        let mut expected = vec![0xfe, 0x00, 0x00, 48];
+        expected.extend_from_slice(hash.as_ref());
+        expected.extend_from_slice(b"hello retry request");
+        assert_eq!(
+            transcript.current_hash(&digest::SHA384),
+            digest::digest(&digest::SHA384, &expected).as_ref()
+        );
+    }
+
+    #[test]
+    fn hash_only_matches_keep_bytes() {
+        let mut keep_bytes = Transcript::with_mode(TranscriptMode::KeepBytes);
+        let mut hash_only = Transcript::with_mode(TranscriptMode::HashOnly);
+        for transcript in [&mut keep_bytes, &mut hash_only] {
+            transcript.update(b"client hello");
+            transcript.update(b"server hello");
+            transcript.start_hash(&digest::SHA256);
+            transcript.update(&[0x0b; 4096]); // certificate
+            transcript.update(b"finished");
+        }
+
+        assert_eq!(
+            hash_only.current_hash(&digest::SHA256),
+            keep_bytes.current_hash(&digest::SHA256)
+        );
+        assert_eq!(keep_bytes.transcript_bytes().unwrap().len(), 24 + 4096 + 8);
+        assert!(hash_only.transcript_bytes().is_none());
+        assert!(hash_only.messages.is_empty());
+    }
+
+    #[test]
+    fn flight_is_fed_message_by_message() {
+        let encrypted_extensions = [0x08, 0x00, 0x00, 0x02, 0x00, 0x00];
+        let finished = [0x14, 0x00, 0x00, 0x03, 0xaa, 0xbb, 0xcc];
+        let mut one_by_one = Transcript::with_mode(TranscriptMode::HashOnly);
+        one_by_one.start_hash(&digest::SHA256);
+        one_by_one.update(&encrypted_extensions);
+        one_by_one.update(&finished);
+
+        let flight = [encrypted_extensions.as_slice(), &finished].concat();
+        let mut from_flight = Transcript::with_mode(TranscriptMode::HashOnly);
+        from_flight.start_hash(&digest::SHA256);
+        from_flight.update_from_flight(&flight).unwrap();
+        assert_eq!(
+            from_flight.current_hash(&digest::SHA256),
+            one_by_one.current_hash(&digest::SHA256)
+        );
+
+        let mut transcript = Transcript::new();
+        assert!(transcript
+            .update_from_flight(&flight[..flight.len() - 1])
+            .is_err());
+        assert!(transcript.transcript_bytes().unwrap().is_empty());
+    }
+}
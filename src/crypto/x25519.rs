@@ -0,0 +1,218 @@
+//! X25519 (RFC 7748) for the key_share extension. ring only hands out X25519
+//! keys generated from its own sealed RNG, so the function is implemented here
+//! to let the client derive its key share from an injected source of
+//! randomness. The field arithmetic follows TweetNaCl: an element of GF(2^255 -
+//! 19) is sixteen signed 64-bit limbs of 16 bits each.
+
+/// Private keys, public keys, and shared secrets are all 32 bytes
+pub(crate) const KEY_LENGTH: usize = 32;
+
+/// The u-coordinate of the base point
+const BASE_POINT: [u8; KEY_LENGTH] = {
+    let mut point = [0; KEY_LENGTH];
+    point[0] = 9;
+    point
+};
+
+type FieldElement = [i64; 16];
+
+/// (486662 - 2) / 4
+const A24: FieldElement = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// Propagate the carries so that every limb is back to 16 bits. The carry out
+/// of the top limb wraps around as 2^256 = 38 (mod p).
+fn carry(o: &mut FieldElement) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swap p and q if the bit is set, without branching on it
+fn conditional_swap(p: &mut FieldElement, q: &mut FieldElement, bit: i64) {
+    let mask = !(bit - 1);
+    for i in 0..16 {
+        let t = mask & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn add(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    return o;
+}
+
+fn sub(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    return o;
+}
+
+fn mul(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = [0; 16];
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+
+    return o;
+}
+
+fn square(a: &FieldElement) -> FieldElement {
+    return mul(a, a);
+}
+
+/// a^(p - 2), which is the inverse of a by Fermat's little theorem
+fn invert(a: &FieldElement) -> FieldElement {
+    let mut c = *a;
+    for i in (0..=253).rev() {
+        c = square(&c);
+        if i != 2 && i != 4 {
+            c = mul(&c, a);
+        }
+    }
+    return c;
+}
+
+fn unpack(bytes: &[u8; KEY_LENGTH]) -> FieldElement {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = i64::from(bytes[2 * i]) + (i64::from(bytes[2 * i + 1]) << 8);
+    }
+    // The most significant bit of the u-coordinate is ignored
+    o[15] &= 0x7fff;
+    return o;
+}
+
+/// Fully reduce the element modulo p and serialize it little-endian
+fn pack(n: &FieldElement) -> [u8; KEY_LENGTH] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    for _ in 0..2 {
+        let mut m = [0i64; 16];
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let borrow = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        conditional_swap(&mut t, &mut m, 1 - borrow);
+    }
+
+    let mut o = [0; KEY_LENGTH];
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    return o;
+}
+
+/// Multiply the point with u-coordinate `u` by the clamped scalar, using the
+/// Montgomery ladder
+pub(crate) fn x25519(scalar: &[u8; KEY_LENGTH], u: &[u8; KEY_LENGTH]) -> [u8; KEY_LENGTH] {
+    let mut z = *scalar;
+    z[31] = (z[31] & 127) | 64;
+    z[0] &= 248;
+
+    let x = unpack(u);
+    let mut a: FieldElement = [0; 16];
+    let mut b = x;
+    let mut c: FieldElement = [0; 16];
+    let mut d: FieldElement = [0; 16];
+    a[0] = 1;
+    d[0] = 1;
+    for i in (0..=254).rev() {
+        let bit = i64::from((z[i >> 3] >> (i & 7)) & 1);
+        conditional_swap(&mut a, &mut b, bit);
+        conditional_swap(&mut c, &mut d, bit);
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        c = add(&b, &d);
+        b = sub(&b, &d);
+        d = square(&e);
+        let f = square(&a);
+        a = mul(&c, &a);
+        c = mul(&b, &e);
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        b = square(&a);
+        c = sub(&d, &f);
+        a = mul(&c, &A24);
+        a = add(&a, &d);
+        c = mul(&c, &a);
+        a = mul(&d, &f);
+        d = mul(&b, &x);
+        b = square(&e);
+        conditional_swap(&mut a, &mut b, bit);
+        conditional_swap(&mut c, &mut d, bit);
+    }
+
+    return pack(&mul(&a, &invert(&c)));
+}
+
+/// The public key that goes with the private key
+pub(crate) fn x25519_base(scalar: &[u8; KEY_LENGTH]) -> [u8; KEY_LENGTH] {
+    return x25519(scalar, &BASE_POINT);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(encoding: &str) -> [u8; KEY_LENGTH] {
+        return hex::decode(encoding).unwrap().try_into().unwrap();
+    }
+
+    /// RFC 7748, section 5.2
+    #[test]
+    fn rfc7748_scalar_multiplication() {
+        let scalar = key("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = key("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+
+        assert_eq!(
+            x25519(&scalar, &u),
+            key("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+        );
+    }
+
+    /// The client and server key shares of RFC 8448, section 3
+    #[test]
+    fn rfc8448_key_exchange() {
+        let client_private =
+            key("49af42ba7f7994852d713ef2784bcbcaa7911de26adc5642cb634540e7ea5005");
+        let server_public = key("c9828876112095fe66762bdbf7c672e156d6cc253b833df1dd69b1b04e751f0f");
+
+        assert_eq!(
+            x25519_base(&client_private),
+            key("99381de560e4bd43d23d8e435a7dbafeb3c06e51c13cae4d5413691e529aaf2c")
+        );
+        assert_eq!(
+            x25519(&client_private, &server_public),
+            key("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d")
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! The error type shared by the parsers and the I/O around them
+use crate::alert::{Alert, AlertDescription};
+use crate::constants::{ExtensionType, ProtocolVersion};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum TlsError {
+    /// The underlying reader or writer failed
+    Io(io::Error),
+
+    /// The bytes could not be decoded into the expected structure
+    DecodeError(&'static str),
+
+    /// A length field disagrees with the number of bytes that it covers
+    LengthMismatch {
+        field: &'static str,
+        declared: usize,
+        actual: usize,
+    },
+
+    /// The two bytes do not encode any known protocol version
+    InvalidProtocolVersion,
+
+    /// The peer settled on a protocol version that is too old to use
+    DeprecatedVersion(ProtocolVersion),
+
+    /// A message carries more than one extension of the same type
+    DuplicateExtension(ExtensionType),
+
+    /// A field holds a syntactically valid value that the protocol forbids in
+    /// this position; answered with an illegal_parameter alert
+    IllegalParameter(&'static str),
+
+    /// A message arrived that is not appropriate at this point
+    UnexpectedMessage(&'static str),
+
+    /// A handshake record arrived in the clear after the read key was
+    /// installed, when every handshake message must be protected
+    UnexpectedPlaintextRecord,
+
+    /// A record failed to decrypt (bad_record_mac)
+    BadRecordMac,
+
+    /// A record is longer than its type allows (record_overflow)
+    RecordOverflow,
+
+    /// A handshake message failed a cryptographic check, such as a Finished
+    /// whose verify_data is wrong (decrypt_error)
+    DecryptError(&'static str),
+
+    /// Sealing would have derived a nonce that was already used under the
+    /// same key, which is a bug on our side
+    NonceReuse(u64),
+
+    /// The server responded with an extension that the client never offered
+    UnsupportedExtension(ExtensionType),
+
+    /// A message lacks an extension that is mandatory in its context
+    MissingExtension(ExtensionType),
+
+    /// Application data was sent after close_notify
+    WriteAfterClose,
+
+    /// The reader ended in the middle of a record
+    UnexpectedEof,
+
+    /// The server's certificate chain is not trusted (bad_certificate)
+    BadCertificate(&'static str),
+
+    /// The write key has sealed as many records as it safely can, and the
+    /// config asks for an error rather than a KeyUpdate
+    RecordLimitReached,
+
+    /// A secret was asked of the key schedule before the stage that derives
+    /// it, or a stage was entered twice
+    WrongKeyScheduleState(&'static str),
+
+    /// The peer sent an alert that no other variant stands for
+    AlertReceived(Alert),
+
+    /// An invariant of our own code does not hold, which is a bug on our side
+    /// and never the peer's doing (internal_error)
+    Internal(&'static str),
+
+    /// A record failed to parse in this field, such as "protocol version";
+    /// the alert and the category are those of the error inside
+    RecordField {
+        field: &'static str,
+        error: Box<TlsError>,
+    },
+}
+
+/// Who an error is down to, for telling a broken peer from a bug of ours
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ErrorCategory {
+    /// The peer sent something that the protocol forbids, or aborted the
+    /// connection with an alert
+    PeerMisbehaved,
+
+    /// We broke an invariant of our own
+    Internal,
+
+    /// The transport failed or ended early
+    Transport,
+
+    /// The application asked for something that the connection cannot do
+    Usage,
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::DecodeError(reason) => write!(f, "decode error: {}", reason),
+            Self::LengthMismatch {
+                field,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "length mismatch in {}: declared {} bytes, found {}",
+                field, declared, actual
+            ),
+            Self::InvalidProtocolVersion => write!(f, "invalid protocol version"),
+            Self::DeprecatedVersion(version) => {
+                write!(f, "deprecated protocol version {:?}", version)
+            }
+            Self::DuplicateExtension(extension_type) => {
+                write!(f, "duplicate extension {:?}", extension_type)
+            }
+            Self::IllegalParameter(reason) => write!(f, "illegal parameter: {}", reason),
+            Self::UnexpectedMessage(reason) => write!(f, "unexpected message: {}", reason),
+            Self::UnexpectedPlaintextRecord => {
+                write!(f, "plaintext handshake record after the keys are installed")
+            }
+            Self::BadRecordMac => write!(f, "bad record MAC"),
+            Self::RecordOverflow => write!(f, "record overflow"),
+            Self::DecryptError(reason) => write!(f, "decrypt error: {}", reason),
+            Self::NonceReuse(sequence) => {
+                write!(f, "nonce reuse at sequence number {}", sequence)
+            }
+            Self::UnsupportedExtension(extension_type) => {
+                write!(f, "unsupported extension {:?}", extension_type)
+            }
+            Self::MissingExtension(extension_type) => {
+                write!(f, "missing extension {:?}", extension_type)
+            }
+            Self::WriteAfterClose => write!(f, "write after close_notify"),
+            Self::UnexpectedEof => write!(f, "unexpected end of stream inside a record"),
+            Self::BadCertificate(reason) => write!(f, "bad certificate: {}", reason),
+            Self::RecordLimitReached => write!(f, "too many records sealed under one key"),
+            Self::WrongKeyScheduleState(reason) => {
+                write!(f, "key schedule out of order: {}", reason)
+            }
+            Self::AlertReceived(alert) => {
+                write!(f, "received {:?} alert", alert.description)
+            }
+            Self::Internal(reason) => write!(f, "internal error: {}", reason),
+            Self::RecordField { field, error } => {
+                write!(f, "in the {} of a record: {}", field, error)
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl TlsError {
+    /// The alert to send the peer before closing the connection over this
+    /// error, if any. I/O errors, local misuse and alerts that the peer sent
+    /// itself are not answered with an alert.
+    pub(crate) fn to_alert(&self) -> Option<Alert> {
+        let description = match self {
+            Self::RecordField { error, .. } => return error.to_alert(),
+            Self::DecodeError(_) | Self::LengthMismatch { .. } | Self::InvalidProtocolVersion => {
+                AlertDescription::DecodeError
+            }
+            Self::DeprecatedVersion(_) => AlertDescription::ProtocolVersion,
+            Self::DuplicateExtension(_) | Self::IllegalParameter(_) => {
+                AlertDescription::IllegalParameter
+            }
+            Self::UnexpectedMessage(_) | Self::UnexpectedPlaintextRecord => {
+                AlertDescription::UnexpectedMessage
+            }
+            Self::BadRecordMac => AlertDescription::BadRecordMac,
+            Self::RecordOverflow => AlertDescription::RecordOverflow,
+            Self::DecryptError(_) => AlertDescription::DecryptError,
+            Self::NonceReuse(_) | Self::WrongKeyScheduleState(_) | Self::Internal(_) => {
+                AlertDescription::InternalError
+            }
+            Self::UnsupportedExtension(_) => AlertDescription::UnsupportedExtension,
+            Self::MissingExtension(_) => AlertDescription::MissingExtension,
+            Self::BadCertificate(_) => AlertDescription::BadCertificate,
+            Self::Io(_)
+            | Self::WriteAfterClose
+            | Self::UnexpectedEof
+            | Self::RecordLimitReached
+            | Self::AlertReceived(_) => return None,
+        };
+
+        return Some(Alert::new(description));
+    }
+
+    pub(crate) fn category(&self) -> ErrorCategory {
+        return match self {
+            Self::RecordField { error, .. } => error.category(),
+            Self::NonceReuse(_) | Self::WrongKeyScheduleState(_) | Self::Internal(_) => {
+                ErrorCategory::Internal
+            }
+            Self::Io(_) | Self::UnexpectedEof => ErrorCategory::Transport,
+            Self::WriteAfterClose | Self::RecordLimitReached => ErrorCategory::Usage,
+            Self::DecodeError(_)
+            | Self::LengthMismatch { .. }
+            | Self::InvalidProtocolVersion
+            | Self::DeprecatedVersion(_)
+            | Self::DuplicateExtension(_)
+            | Self::IllegalParameter(_)
+            | Self::UnexpectedMessage(_)
+            | Self::UnexpectedPlaintextRecord
+            | Self::BadRecordMac
+            | Self::RecordOverflow
+            | Self::DecryptError(_)
+            | Self::UnsupportedExtension(_)
+            | Self::MissingExtension(_)
+            | Self::BadCertificate(_)
+            | Self::AlertReceived(_) => ErrorCategory::PeerMisbehaved,
+        };
+    }
+}
+
+/// An alert from the peer, as the variant that it would have been sent for
+impl From<Alert> for TlsError {
+    fn from(value: Alert) -> Self {
+        return match value.description {
+            AlertDescription::DecodeError => Self::DecodeError("the peer sent decode_error"),
+            AlertDescription::IllegalParameter => {
+                Self::IllegalParameter("the peer sent illegal_parameter")
+            }
+            AlertDescription::UnexpectedMessage => {
+                Self::UnexpectedMessage("the peer sent unexpected_message")
+            }
+            AlertDescription::BadRecordMac => Self::BadRecordMac,
+            AlertDescription::RecordOverflow => Self::RecordOverflow,
+            AlertDescription::DecryptError => Self::DecryptError("the peer sent decrypt_error"),
+            AlertDescription::BadCertificate => {
+                Self::BadCertificate("the peer sent bad_certificate")
+            }
+            _ => Self::AlertReceived(value),
+        };
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::RecordField { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TlsError {
+    fn from(value: io::Error) -> Self {
+        return Self::Io(value);
+    }
+}
+
+/// So that TLS streams can implement std::io::Read and std::io::Write. I/O
+/// errors are passed through unchanged; protocol errors become InvalidData.
+impl From<TlsError> for io::Error {
+    fn from(value: TlsError) -> Self {
+        return match value {
+            TlsError::Io(err) => err,
+            TlsError::WriteAfterClose => io::Error::new(io::ErrorKind::BrokenPipe, value),
+            TlsError::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, value),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_io_error() {
+        let err = TlsError::from(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "socket closed",
+        ));
+
+        assert!(matches!(err, TlsError::Io(_)));
+        assert_eq!(format!("{}", err), "I/O error: socket closed");
+        assert_eq!(err.source().unwrap().to_string(), "socket closed");
+    }
+
+    #[test]
+    fn alert_round_trip() {
+        for description in [
+            AlertDescription::DecodeError,
+            AlertDescription::IllegalParameter,
+            AlertDescription::UnexpectedMessage,
+            AlertDescription::BadRecordMac,
+            AlertDescription::RecordOverflow,
+            AlertDescription::DecryptError,
+            AlertDescription::BadCertificate,
+        ] {
+            let alert = Alert::new(description);
+            assert_eq!(TlsError::from(alert).to_alert(), Some(alert));
+        }
+
+        // Nothing is sent back for an alert without a variant of its own
+        let err = TlsError::from(Alert::new(AlertDescription::HandshakeFailure));
+        assert!(matches!(err, TlsError::AlertReceived(_)));
+        assert_eq!(err.to_alert(), None);
+        assert_eq!(
+            TlsError::MissingExtension(ExtensionType::KeyShare).to_alert(),
+            Some(Alert::new(AlertDescription::MissingExtension))
+        );
+    }
+
+    #[test]
+    fn question_mark_converts_io_error() {
+        fn read_nothing() -> Result<(), TlsError> {
+            Err(io::Error::other("oops"))?;
+            return Ok(());
+        }
+
+        assert!(matches!(read_nothing(), Err(TlsError::Io(_))));
+    }
+}
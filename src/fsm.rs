@@ -1,4 +1,4 @@
-/// Traits for implementing finite state machine
+//! Traits for implementing finite state machine
 
 pub trait FiniteStateMachine
 where
@@ -6,10 +6,10 @@ where
 {
     type State;
 
-    fn transition(self: Self) -> Self {
+    fn transition(self) -> Self {
         return self;
     }
 
     /// Return true if the FSM has halted and cannot transition further
-    fn is_halt(self: &Self) -> bool;
+    fn is_halt(&self) -> bool;
 }
@@ -0,0 +1,2132 @@
+//! Handshake messages are carried in the fragment of Handshake records. This
+//! module holds the framing of handshake messages, the structures shared by
+//! them, starting with extensions, and the messages themselves.
+use crate::codec::{Codec, LengthPrefixed, ParseOutcome, Reader, U24};
+use crate::constants::{
+    CipherSuite, ExtensionType, HandshakeType, NamedGroup, ProtocolVersion, PskKeyExchangeMode,
+    SignatureScheme, RANDOM_LEN, SESSION_ID_MAX_LEN,
+};
+use crate::error::TlsError;
+use crate::fsm::FiniteStateMachine;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// msg_type (1 byte) and length (3 bytes)
+pub(crate) const HANDSHAKE_HEADER_LENGTH: usize = 4;
+
+/// A complete handshake message. The body is kept as bytes and decoded by the
+/// receiver according to the message type, so the exact encoding is still at
+/// hand for the transcript.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct HandshakeMessage {
+    pub(crate) msg_type: HandshakeType,
+    pub(crate) body: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl HandshakeMessage {
+    /// The length of to_bytes, without encoding the message
+    pub(crate) fn encoded_len(&self) -> usize {
+        return HANDSHAKE_HEADER_LENGTH + self.body.len();
+    }
+
+    /// msg_type (1) || length (3) || body, as HandshakeJoiner reads it back
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        buf.push(self.msg_type.into());
+        U24::try_from(self.body.len())
+            .expect("handshake message body is longer than 2^24 - 1 bytes")
+            .encode(&mut buf);
+        buf.extend_from_slice(&self.body);
+
+        return buf;
+    }
+}
+
+impl From<HandshakeMessage> for Vec<u8> {
+    fn from(value: HandshakeMessage) -> Self {
+        return value.to_bytes();
+    }
+}
+
+/// Handshake messages are not aligned with records: one message may be split
+/// across several records, and one record may carry several messages. The
+/// joiner collects the fragments and hands out whole messages.
+#[allow(dead_code)]
+pub(crate) struct HandshakeJoiner {
+    buffer: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl HandshakeJoiner {
+    pub(crate) fn new() -> Self {
+        return Self { buffer: vec![] };
+    }
+
+    /// Drop any partial message
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub(crate) fn push(&mut self, fragment: &[u8]) {
+        self.buffer.extend_from_slice(fragment);
+    }
+
+    /// Whether a partial message is waiting for more fragments
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.buffer.is_empty();
+    }
+
+    /// The read key is about to change. A message must not straddle a key
+    /// change (RFC 8446, section 5.1), so anything still buffered, whole or
+    /// not, is an unexpected_message.
+    pub(crate) fn end_epoch(&mut self) -> Result<(), TlsError> {
+        if !self.buffer.is_empty() {
+            return Err(TlsError::UnexpectedMessage(
+                "handshake message straddles a key change",
+            ));
+        }
+
+        return Ok(());
+    }
+
+    /// Remove the next complete message from the buffer, if there is one. A
+    /// message of an unknown type is an unexpected_message as soon as its
+    /// header is in, without waiting for its body.
+    pub(crate) fn next_message(&mut self) -> Result<Option<HandshakeMessage>, TlsError> {
+        if self.buffer.len() < HANDSHAKE_HEADER_LENGTH {
+            return Ok(None);
+        }
+        if !HandshakeType::from(self.buffer[0]).is_known() {
+            return Err(TlsError::UnexpectedMessage("unknown handshake type"));
+        }
+        let (length, _) = U24::decode(&self.buffer[1..HANDSHAKE_HEADER_LENGTH])?;
+        let message_length = HANDSHAKE_HEADER_LENGTH + usize::from(length);
+        if self.buffer.len() < message_length {
+            return Ok(None);
+        }
+
+        let message: Vec<u8> = self.buffer.drain(..message_length).collect();
+        return Ok(Some(HandshakeMessage {
+            msg_type: HandshakeType::from(message[0]),
+            body: message[HANDSHAKE_HEADER_LENGTH..].to_vec(),
+        }));
+    }
+}
+
+/// Decodes the handshake message in the fragment of a Handshake record, the
+/// way TLSPlaintextParser decodes the record around it. The fragment must
+/// hold exactly one message; messages that share a record or span several go
+/// through HandshakeJoiner instead.
+#[allow(dead_code)]
+pub(crate) enum HandshakeParser<'a> {
+    ExpectMsgType {
+        remainder: &'a [u8],
+    },
+    ExpectLength {
+        msg_type: HandshakeType,
+        remainder: &'a [u8],
+    },
+    ExpectBody {
+        msg_type: HandshakeType,
+        length: usize,
+        remainder: &'a [u8],
+    },
+    Finished {
+        msg_type: HandshakeType,
+        body: Vec<u8>,
+    },
+    /// The bytes seen so far are valid, but there are not enough of them to
+    /// finish the message
+    Incomplete,
+    Failed {
+        reason: TlsError,
+    },
+}
+
+#[allow(dead_code)]
+impl<'a> HandshakeParser<'a> {
+    pub(crate) fn start(fragment: &'a [u8]) -> Self {
+        return Self::ExpectMsgType {
+            remainder: fragment,
+        };
+    }
+
+    pub(crate) fn is_failed(&self) -> bool {
+        return matches!(self, Self::Failed { .. });
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        return matches!(self, Self::Finished { .. });
+    }
+
+    pub(crate) fn is_incomplete(&self) -> bool {
+        return matches!(self, Self::Incomplete);
+    }
+
+    pub(crate) fn failure_reason(&self) -> Option<&TlsError> {
+        return match self {
+            Self::Failed { reason } => Some(reason),
+            _ => None,
+        };
+    }
+
+    /// An unknown type fails at once, as it does in HandshakeJoiner
+    fn parse_msg_type(self) -> Self {
+        let remainder = match self {
+            Self::ExpectMsgType { remainder } => remainder,
+            _ => return Self::wrong_state(),
+        };
+        let (encoding, remainder) = match remainder.split_first() {
+            Some(split) => split,
+            None => return Self::Incomplete,
+        };
+        let msg_type = HandshakeType::from(*encoding);
+        if !msg_type.is_known() {
+            return Self::Failed {
+                reason: TlsError::UnexpectedMessage("unknown handshake type"),
+            };
+        }
+
+        return Self::ExpectLength {
+            msg_type,
+            remainder,
+        };
+    }
+
+    /// The length is a uint24, most significant byte first
+    fn parse_length(self) -> Self {
+        let (msg_type, remainder) = match self {
+            Self::ExpectLength {
+                msg_type,
+                remainder,
+            } => (msg_type, remainder),
+            _ => return Self::wrong_state(),
+        };
+        let (length, remainder) = match U24::decode(remainder) {
+            Ok((length, remainder)) => (usize::from(length), remainder),
+            Err(_) => return Self::Incomplete,
+        };
+
+        return Self::ExpectBody {
+            msg_type,
+            length,
+            remainder,
+        };
+    }
+
+    /// Fewer bytes than the length is Self::Incomplete, more is Self::Failed
+    fn parse_body(self) -> Self {
+        let (msg_type, length, remainder) = match self {
+            Self::ExpectBody {
+                msg_type,
+                length,
+                remainder,
+            } => (msg_type, length, remainder),
+            _ => return Self::wrong_state(),
+        };
+        if remainder.len() < length {
+            return Self::Incomplete;
+        }
+        if remainder.len() > length {
+            return Self::Failed {
+                reason: TlsError::LengthMismatch {
+                    field: "handshake message",
+                    declared: length,
+                    actual: remainder.len(),
+                },
+            };
+        }
+
+        return Self::Finished {
+            msg_type,
+            body: remainder.to_vec(),
+        };
+    }
+
+    fn wrong_state() -> Self {
+        return Self::Failed {
+            reason: TlsError::Internal("handshake parser in the wrong state"),
+        };
+    }
+}
+
+impl<'a> FiniteStateMachine for HandshakeParser<'a> {
+    type State = Self;
+
+    fn transition(self) -> Self {
+        match self {
+            Self::ExpectMsgType { .. } => self.parse_msg_type(),
+            Self::ExpectLength { .. } => self.parse_length(),
+            Self::ExpectBody { .. } => self.parse_body(),
+            Self::Failed { .. } => self,
+            Self::Incomplete => self,
+            Self::Finished { .. } => self,
+        }
+    }
+
+    fn is_halt(&self) -> bool {
+        return self.is_failed() || self.is_finished() || self.is_incomplete();
+    }
+}
+
+/// The body of Finished is the verify_data alone, as long as the hash of the
+/// cipher suite and without a length prefix of its own
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Finished {
+    pub(crate) verify_data: Vec<u8>,
+}
+
+/// The whole buffer is the verify_data, so decoding leaves nothing over
+impl Codec for Finished {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.verify_data);
+    }
+
+    fn decode(buf: &[u8]) -> Result<(Self, &[u8]), TlsError> {
+        return Ok((
+            Self {
+                verify_data: buf.to_vec(),
+            },
+            &[],
+        ));
+    }
+}
+
+impl From<Finished> for HandshakeMessage {
+    fn from(value: Finished) -> Self {
+        return HandshakeMessage {
+            msg_type: HandshakeType::Finished,
+            body: value.verify_data,
+        };
+    }
+}
+
+impl TryFrom<&HandshakeMessage> for Finished {
+    type Error = TlsError;
+
+    fn try_from(value: &HandshakeMessage) -> Result<Self, Self::Error> {
+        if value.msg_type != HandshakeType::Finished {
+            return Err(TlsError::UnexpectedMessage("expected finished"));
+        }
+        let (finished, _) = Finished::decode(&value.body)?;
+
+        return Ok(finished);
+    }
+}
+
+/// The server's proof that it holds the key of its certificate: the scheme,
+/// then the signature with a two-byte length prefix
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct CertificateVerify {
+    pub(crate) scheme: SignatureScheme,
+    pub(crate) signature: Vec<u8>,
+}
+
+impl From<CertificateVerify> for HandshakeMessage {
+    fn from(value: CertificateVerify) -> Self {
+        let mut body = u16::from(value.scheme).to_be_bytes().to_vec();
+        let marker = LengthPrefixed::begin_u16(&mut body);
+        body.extend_from_slice(&value.signature);
+        LengthPrefixed::end(&mut body, marker);
+
+        return HandshakeMessage {
+            msg_type: HandshakeType::CertificateVerify,
+            body,
+        };
+    }
+}
+
+/// The signature must fill the rest of the body
+impl TryFrom<&[u8]> for CertificateVerify {
+    type Error = TlsError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let truncated = || TlsError::DecodeError("truncated certificate verify");
+        let mut reader = Reader::new(value);
+        let scheme = SignatureScheme::from(reader.read_u16().ok_or_else(truncated)?);
+        let length = usize::from(reader.read_u16().ok_or_else(truncated)?);
+        if reader.remainder().len() != length {
+            return Err(TlsError::LengthMismatch {
+                field: "signature",
+                declared: length,
+                actual: reader.remainder().len(),
+            });
+        }
+
+        return Ok(Self {
+            scheme,
+            signature: reader.remainder().to_vec(),
+        });
+    }
+}
+
+/// Append the bytes with a one-byte length prefix
+fn push_u8_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+/// Append the bytes with a two-byte length prefix
+fn push_u16_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Each extension is serialized as `tag || length || content`, where the tag
+/// and the length are two bytes each. The content is borrowed when the
+/// extension is parsed out of a message, and owned when it is built locally.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Extension<'a> {
+    pub(crate) extension_type: ExtensionType,
+    pub(crate) extension_data: Cow<'a, [u8]>,
+}
+
+#[allow(dead_code)]
+impl Extension<'static> {
+    /// An extension of any type with any data, bypassing the typed builders,
+    /// e.g. to inject malformed or illegal extensions in negative tests
+    pub(crate) fn raw(type_code: u16, data: Vec<u8>) -> Self {
+        return Self {
+            extension_type: ExtensionType::from(type_code),
+            extension_data: Cow::Owned(data),
+        };
+    }
+}
+
+/// The wire format of a single extension, i.e. type, length, and data
+impl From<Extension<'_>> for Vec<u8> {
+    fn from(value: Extension<'_>) -> Self {
+        let mut buf = Vec::with_capacity(4 + value.extension_data.len());
+        buf.extend_from_slice(&u16::from(value.extension_type).to_be_bytes());
+        buf.extend_from_slice(&(value.extension_data.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&value.extension_data);
+
+        return buf;
+    }
+}
+
+/// Iterate over a block of concatenated extensions (the content of the
+/// extensions vector, without its own two-byte length) without allocating.
+/// Each extension's data must fit in what is left of the block, even if the
+/// buffer that the block was sliced from continues past it. After the first
+/// malformed extension, the iterator yields the error and then stops.
+#[allow(dead_code)]
+pub(crate) struct ExtensionIter<'a> {
+    remainder: &'a [u8],
+}
+
+#[allow(dead_code)]
+impl<'a> ExtensionIter<'a> {
+    pub(crate) fn new(block: &'a [u8]) -> Self {
+        return Self { remainder: block };
+    }
+}
+
+impl<'a> Iterator for ExtensionIter<'a> {
+    type Item = Result<Extension<'a>, TlsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remainder.is_empty() {
+            return None;
+        }
+        if self.remainder.len() < 4 {
+            self.remainder = &[];
+            return Some(Err(TlsError::DecodeError("truncated extension header")));
+        }
+
+        let extension_type =
+            ExtensionType::from(u16::from_be_bytes([self.remainder[0], self.remainder[1]]));
+        let length = usize::from(u16::from_be_bytes([self.remainder[2], self.remainder[3]]));
+        let remainder = &self.remainder[4..];
+        if remainder.len() < length {
+            self.remainder = &[];
+            return Some(Err(TlsError::LengthMismatch {
+                field: "extension_data",
+                declared: length,
+                actual: remainder.len(),
+            }));
+        }
+
+        self.remainder = &remainder[length..];
+        return Some(Ok(Extension {
+            extension_type,
+            extension_data: Cow::Borrowed(&remainder[..length]),
+        }));
+    }
+}
+
+/// Scan the block for the first extension of the given type. Extensions
+/// before it must be well-formed; the ones after it are not looked at.
+#[allow(dead_code)]
+pub(crate) fn find_extension(
+    block: &[u8],
+    extension_type: ExtensionType,
+) -> Result<Option<Extension<'_>>, TlsError> {
+    for extension in ExtensionIter::new(block) {
+        let extension = extension?;
+        if extension.extension_type == extension_type {
+            return Ok(Some(extension));
+        }
+    }
+
+    return Ok(None);
+}
+
+/// The data of every extension of the given type in the block, duplicates
+/// included, for diagnosing a peer that repeats an extension. The scan stops
+/// quietly at the first malformed extension.
+#[allow(dead_code)]
+pub(crate) fn find_all_extensions(block: &[u8], extension_type: ExtensionType) -> Vec<&[u8]> {
+    return ExtensionIter::new(block)
+        .map_while(Result::ok)
+        .filter(|extension| extension.extension_type == extension_type)
+        .filter_map(|extension| match extension.extension_data {
+            Cow::Borrowed(data) => Some(data),
+            Cow::Owned(_) => None,
+        })
+        .collect();
+}
+
+/// Decode every extension in the block. TLS forbids more than one extension
+/// of the same type in a single message (RFC 8446, section 4.2), so a repeated
+/// type fails the whole block.
+#[allow(dead_code)]
+pub(crate) fn parse_extensions(block: &[u8]) -> Result<Vec<Extension<'_>>, TlsError> {
+    let mut seen = HashSet::new();
+    let mut extensions = vec![];
+    for extension in ExtensionIter::new(block) {
+        let extension = extension?;
+        if !seen.insert(extension.extension_type) {
+            return Err(TlsError::DuplicateExtension(extension.extension_type));
+        }
+        extensions.push(extension);
+    }
+
+    return Ok(extensions);
+}
+
+/// Build the server_name extension, which holds a list with a single host name
+#[allow(dead_code)]
+pub(crate) fn server_name(host_name: &str) -> Extension<'static> {
+    let mut server_name = vec![0]; // name_type: host_name
+    push_u16_prefixed(&mut server_name, host_name.as_bytes());
+    let mut extension_data = vec![];
+    push_u16_prefixed(&mut extension_data, &server_name);
+
+    return Extension {
+        extension_type: ExtensionType::ServerName,
+        extension_data: Cow::Owned(extension_data),
+    };
+}
+
+/// Build the supported_versions extension of a ClientHello, which lists the
+/// versions in order of preference
+#[allow(dead_code)]
+pub(crate) fn supported_versions(versions: &[ProtocolVersion]) -> Extension<'static> {
+    let mut list = vec![];
+    for version in versions {
+        let encoding: [u8; 2] = (*version).try_into().unwrap();
+        list.extend_from_slice(&encoding);
+    }
+    let mut extension_data = vec![];
+    push_u8_prefixed(&mut extension_data, &list);
+
+    return Extension {
+        extension_type: ExtensionType::SupportedVersions,
+        extension_data: Cow::Owned(extension_data),
+    };
+}
+
+/// Build the supported_versions extension of a ServerHello, which holds only
+/// the selected version
+#[allow(dead_code)]
+pub(crate) fn selected_version(version: ProtocolVersion) -> Extension<'static> {
+    let encoding: [u8; 2] = version.try_into().unwrap();
+
+    return Extension {
+        extension_type: ExtensionType::SupportedVersions,
+        extension_data: Cow::Owned(encoding.to_vec()),
+    };
+}
+
+#[allow(dead_code)]
+pub(crate) fn supported_groups(groups: &[NamedGroup]) -> Extension<'static> {
+    let list: Vec<u8> = groups
+        .iter()
+        .flat_map(|group| u16::from(*group).to_be_bytes())
+        .collect();
+    let mut extension_data = vec![];
+    push_u16_prefixed(&mut extension_data, &list);
+
+    return Extension {
+        extension_type: ExtensionType::SupportedGroups,
+        extension_data: Cow::Owned(extension_data),
+    };
+}
+
+#[allow(dead_code)]
+pub(crate) fn signature_algorithms(schemes: &[SignatureScheme]) -> Extension<'static> {
+    let list: Vec<u8> = schemes
+        .iter()
+        .flat_map(|scheme| u16::from(*scheme).to_be_bytes())
+        .collect();
+    let mut extension_data = vec![];
+    push_u16_prefixed(&mut extension_data, &list);
+
+    return Extension {
+        extension_type: ExtensionType::SignatureAlgorithms,
+        extension_data: Cow::Owned(extension_data),
+    };
+}
+
+/// One key share: a group and the public key generated for it
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct KeyShareEntry {
+    pub(crate) group: NamedGroup,
+    pub(crate) key_exchange: Vec<u8>,
+}
+
+/// group (2) || key_exchange length (2) || key_exchange
+impl Codec for KeyShareEntry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&u16::from(self.group).to_be_bytes());
+        push_u16_prefixed(buf, &self.key_exchange);
+    }
+
+    fn decode(buf: &[u8]) -> Result<(Self, &[u8]), TlsError> {
+        if buf.len() < 4 {
+            return Err(TlsError::DecodeError("truncated key share"));
+        }
+        let group = NamedGroup::from(u16::from_be_bytes([buf[0], buf[1]]));
+        let length = usize::from(u16::from_be_bytes([buf[2], buf[3]]));
+        if buf.len() - 4 < length {
+            return Err(TlsError::LengthMismatch {
+                field: "key_exchange",
+                declared: length,
+                actual: buf.len() - 4,
+            });
+        }
+        let (key_exchange, rest) = buf[4..].split_at(length);
+
+        return Ok((
+            Self {
+                group,
+                key_exchange: key_exchange.to_vec(),
+            },
+            rest,
+        ));
+    }
+}
+
+/// The client_shares of a ClientHello: the entries behind a two-byte length
+impl Codec for Vec<KeyShareEntry> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut list = vec![];
+        for entry in self {
+            entry.encode(&mut list);
+        }
+        push_u16_prefixed(buf, &list);
+    }
+
+    fn decode(buf: &[u8]) -> Result<(Self, &[u8]), TlsError> {
+        if buf.len() < 2 {
+            return Err(TlsError::DecodeError("truncated client_shares"));
+        }
+        let length = usize::from(u16::from_be_bytes([buf[0], buf[1]]));
+        if buf.len() - 2 < length {
+            return Err(TlsError::LengthMismatch {
+                field: "client_shares",
+                declared: length,
+                actual: buf.len() - 2,
+            });
+        }
+        let (mut list, rest) = buf[2..].split_at(length);
+        let mut entries = vec![];
+        while !list.is_empty() {
+            let (entry, remainder) = KeyShareEntry::decode(list)?;
+            entries.push(entry);
+            list = remainder;
+        }
+
+        return Ok((entries, rest));
+    }
+}
+
+/// The key_share extension, which lists one entry per group that the client
+/// has generated a key for, and holds the single entry answering one of them
+/// in a ServerHello
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum KeyShare {
+    ClientHello(Vec<KeyShareEntry>),
+    ServerHello(KeyShareEntry),
+}
+
+#[allow(dead_code)]
+impl KeyShare {
+    /// Decode the extension data of a ClientHello's key_share into its entries
+    pub(crate) fn parse_client_hello(
+        extension_data: &[u8],
+    ) -> Result<Vec<KeyShareEntry>, TlsError> {
+        let (entries, rest) = Vec::<KeyShareEntry>::decode(extension_data)?;
+        if !rest.is_empty() {
+            return Err(TlsError::LengthMismatch {
+                field: "client_shares",
+                declared: extension_data.len() - 2 - rest.len(),
+                actual: extension_data.len() - 2,
+            });
+        }
+
+        return Ok(entries);
+    }
+
+    /// Decode the extension data of a ServerHello's key_share into its entry
+    pub(crate) fn parse_server_hello(extension_data: &[u8]) -> Result<KeyShareEntry, TlsError> {
+        let (entry, rest) = KeyShareEntry::decode(extension_data)?;
+        if !rest.is_empty() {
+            return Err(TlsError::LengthMismatch {
+                field: "key_exchange",
+                declared: entry.key_exchange.len(),
+                actual: extension_data.len() - 4,
+            });
+        }
+
+        return Ok(entry);
+    }
+}
+
+impl From<KeyShare> for Extension<'static> {
+    fn from(value: KeyShare) -> Self {
+        let mut extension_data = vec![];
+        match value {
+            KeyShare::ClientHello(entries) => entries.encode(&mut extension_data),
+            KeyShare::ServerHello(entry) => entry.encode(&mut extension_data),
+        }
+
+        return Extension {
+            extension_type: ExtensionType::KeyShare,
+            extension_data: Cow::Owned(extension_data),
+        };
+    }
+}
+
+/// Build the key_share extension of a ClientHello, with one entry per group
+/// that the client has generated a key for
+#[allow(dead_code)]
+pub(crate) fn key_share(entries: &[(NamedGroup, Vec<u8>)]) -> Extension<'static> {
+    let entries = entries
+        .iter()
+        .map(|(group, key_exchange)| KeyShareEntry {
+            group: *group,
+            key_exchange: key_exchange.clone(),
+        })
+        .collect();
+
+    return KeyShare::ClientHello(entries).into();
+}
+
+/// Build the key_share extension of a ServerHello, which holds the single
+/// entry answering one of the client's key shares
+#[allow(dead_code)]
+pub(crate) fn server_key_share(group: NamedGroup, key_exchange: &[u8]) -> Extension<'static> {
+    return KeyShare::ServerHello(KeyShareEntry {
+        group,
+        key_exchange: key_exchange.to_vec(),
+    })
+    .into();
+}
+
+/// Build the ALPN extension from the protocol names in order of preference
+#[allow(dead_code)]
+pub(crate) fn application_layer_protocol_negotiation(protocols: &[Vec<u8>]) -> Extension<'static> {
+    let mut list = vec![];
+    for protocol in protocols {
+        push_u8_prefixed(&mut list, protocol);
+    }
+    let mut extension_data = vec![];
+    push_u16_prefixed(&mut extension_data, &list);
+
+    return Extension {
+        extension_type: ExtensionType::ApplicationLayerProtocolNegotiation,
+        extension_data: Cow::Owned(extension_data),
+    };
+}
+
+/// Build the psk_key_exchange_modes extension. The modes are a list with a
+/// one-byte length prefix that must hold at least one mode.
+#[allow(dead_code)]
+pub(crate) fn psk_key_exchange_modes(
+    modes: &[PskKeyExchangeMode],
+) -> Result<Extension<'static>, TlsError> {
+    if modes.is_empty() || modes.len() > 255 {
+        return Err(TlsError::DecodeError(
+            "invalid number of psk key exchange modes",
+        ));
+    }
+    let mut extension_data = vec![modes.len() as u8];
+    extension_data.extend(modes.iter().map(|mode| u8::from(*mode)));
+
+    return Ok(Extension {
+        extension_type: ExtensionType::PskKeyExchangeModes,
+        extension_data: Cow::Owned(extension_data),
+    });
+}
+
+/// Decode the data of a psk_key_exchange_modes extension
+#[allow(dead_code)]
+pub(crate) fn parse_psk_key_exchange_modes(
+    extension_data: &[u8],
+) -> Result<Vec<PskKeyExchangeMode>, TlsError> {
+    let (length, modes) = match extension_data.split_first() {
+        Some((length, modes)) => (usize::from(*length), modes),
+        None => return Err(TlsError::DecodeError("truncated psk key exchange modes")),
+    };
+    if length != modes.len() {
+        return Err(TlsError::LengthMismatch {
+            field: "ke_modes",
+            declared: length,
+            actual: modes.len(),
+        });
+    }
+    if modes.is_empty() {
+        return Err(TlsError::DecodeError("empty psk key exchange modes"));
+    }
+
+    return Ok(modes
+        .iter()
+        .map(|mode| PskKeyExchangeMode::from(*mode))
+        .collect());
+}
+
+/// Serialize a ClientHello. The legacy fields are fixed by TLS 1.3: the legacy
+/// version is TLS 1.2 and the only compression method is "null".
+#[allow(dead_code)]
+pub(crate) fn build_client_hello(
+    random: &[u8; RANDOM_LEN],
+    legacy_session_id: &[u8],
+    cipher_suites: &[CipherSuite],
+    extensions: Vec<Extension<'_>>,
+) -> HandshakeMessage {
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(random);
+    push_u8_prefixed(&mut body, legacy_session_id);
+    let suites: Vec<u8> = cipher_suites
+        .iter()
+        .flat_map(|suite| u16::from(*suite).to_be_bytes())
+        .collect();
+    push_u16_prefixed(&mut body, &suites);
+    push_u8_prefixed(&mut body, &[0]);
+    let block: Vec<u8> = extensions.into_iter().flat_map(Vec::<u8>::from).collect();
+    push_u16_prefixed(&mut body, &block);
+
+    return HandshakeMessage {
+        msg_type: HandshakeType::ClientHello,
+        body,
+    };
+}
+
+/// The same bytes as build_client_hello followed by HandshakeMessage::to_bytes,
+/// handshake header included, but written in a single pass: each length field
+/// is reserved first and filled in once what it covers is written, instead of
+/// every extension and every vector being built in a buffer of its own and
+/// copied into the next one out
+#[allow(dead_code)]
+pub(crate) fn encode_client_hello(
+    random: &[u8; RANDOM_LEN],
+    legacy_session_id: &[u8],
+    cipher_suites: &[CipherSuite],
+    extensions: &[Extension<'_>],
+) -> Vec<u8> {
+    let mut buf = vec![u8::from(HandshakeType::ClientHello)];
+    let body = LengthPrefixed::begin_u24(&mut buf);
+    buf.extend_from_slice(&[0x03, 0x03]);
+    buf.extend_from_slice(random);
+    let session_id = LengthPrefixed::begin_u8(&mut buf);
+    buf.extend_from_slice(legacy_session_id);
+    LengthPrefixed::end(&mut buf, session_id);
+    let suites = LengthPrefixed::begin_u16(&mut buf);
+    for suite in cipher_suites {
+        buf.extend_from_slice(&u16::from(*suite).to_be_bytes());
+    }
+    LengthPrefixed::end(&mut buf, suites);
+    buf.extend_from_slice(&[1, 0]); // compression methods: "null" only
+    let block = LengthPrefixed::begin_u16(&mut buf);
+    for extension in extensions {
+        buf.extend_from_slice(&u16::from(extension.extension_type).to_be_bytes());
+        let data = LengthPrefixed::begin_u16(&mut buf);
+        buf.extend_from_slice(&extension.extension_data);
+        LengthPrefixed::end(&mut buf, data);
+    }
+    LengthPrefixed::end(&mut buf, block);
+    LengthPrefixed::end(&mut buf, body);
+
+    return buf;
+}
+
+/// The extensions of a ClientHello body, for tools that only care about the
+/// likes of SNI and ALPN and not about the fields before them
+#[allow(dead_code)]
+pub(crate) fn parse_client_hello_extensions(body: &[u8]) -> Result<Vec<Extension<'_>>, TlsError> {
+    let mut reader = Reader::new(body);
+    let length = skip_client_hello_fields(&mut reader)
+        .ok_or(TlsError::DecodeError("truncated client hello"))?;
+    let block = reader.remainder();
+    if block.len() != length {
+        return Err(TlsError::LengthMismatch {
+            field: "extensions",
+            declared: length,
+            actual: block.len(),
+        });
+    }
+
+    return parse_extensions(block);
+}
+
+/// Skip the fields of a ClientHello before the extensions block and read its
+/// length, leaving the reader at the block
+fn skip_client_hello_fields(reader: &mut Reader) -> Option<usize> {
+    // legacy version (2) || random
+    reader.take(2 + RANDOM_LEN)?;
+    let session_id_length = reader.read_u8()?;
+    reader.take(usize::from(session_id_length))?;
+    let cipher_suites_length = reader.read_u16()?;
+    reader.take(usize::from(cipher_suites_length))?;
+    let compression_methods_length = reader.read_u8()?;
+    reader.take(usize::from(compression_methods_length))?;
+
+    return reader.read_u16().map(usize::from);
+}
+
+/// The body of a ClientHello message, for the server side of the handshake
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ClientHello<'a> {
+    pub(crate) legacy_version: ProtocolVersion,
+    pub(crate) random: [u8; RANDOM_LEN],
+    pub(crate) legacy_session_id: &'a [u8],
+    pub(crate) cipher_suites: Vec<CipherSuite>,
+    pub(crate) legacy_compression_methods: &'a [u8],
+    pub(crate) extensions: Vec<Extension<'a>>,
+}
+
+/// Every length must cover exactly its field. A TLS 1.3 ClientHello always
+/// carries supported_versions (RFC 8446, section 4.2.1), so one without it is
+/// refused; which versions it lists is for the server to decide.
+impl<'a> TryFrom<&'a [u8]> for ClientHello<'a> {
+    type Error = TlsError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let truncated = || TlsError::DecodeError("truncated client hello");
+        let mut reader = Reader::new(value);
+        let legacy_version = ProtocolVersion::from_wire(reader.take(2).ok_or_else(truncated)?)?;
+        let random = reader.take(RANDOM_LEN).ok_or_else(truncated)?;
+        let session_id_length = usize::from(reader.read_u8().ok_or_else(truncated)?);
+        if session_id_length > SESSION_ID_MAX_LEN {
+            return Err(TlsError::DecodeError("legacy_session_id is too long"));
+        }
+        let legacy_session_id = reader.take(session_id_length).ok_or_else(truncated)?;
+        let cipher_suites_length = usize::from(reader.read_u16().ok_or_else(truncated)?);
+        if cipher_suites_length == 0 || cipher_suites_length % 2 != 0 {
+            return Err(TlsError::DecodeError("invalid cipher_suites length"));
+        }
+        let cipher_suites = reader
+            .take(cipher_suites_length)
+            .ok_or_else(truncated)?
+            .chunks(2)
+            .map(|suite| CipherSuite::from(u16::from_be_bytes([suite[0], suite[1]])))
+            .collect();
+        let compression_methods_length = usize::from(reader.read_u8().ok_or_else(truncated)?);
+        if compression_methods_length == 0 {
+            return Err(TlsError::DecodeError("no compression methods"));
+        }
+        let legacy_compression_methods = reader
+            .take(compression_methods_length)
+            .ok_or_else(truncated)?;
+        let extensions_length = usize::from(reader.read_u16().ok_or_else(truncated)?);
+        let block = reader.remainder();
+        if block.len() != extensions_length {
+            return Err(TlsError::LengthMismatch {
+                field: "extensions",
+                declared: extensions_length,
+                actual: block.len(),
+            });
+        }
+        let extensions = parse_extensions(block)?;
+        if !extensions
+            .iter()
+            .any(|extension| extension.extension_type == ExtensionType::SupportedVersions)
+        {
+            return Err(TlsError::MissingExtension(ExtensionType::SupportedVersions));
+        }
+
+        return Ok(Self {
+            legacy_version,
+            random: random.try_into().unwrap(),
+            legacy_session_id,
+            cipher_suites,
+            legacy_compression_methods,
+            extensions,
+        });
+    }
+}
+
+/// The body of the ClientHello message, without the handshake header, field
+/// by field as it was parsed or built; nothing is filled in or checked
+impl From<ClientHello<'_>> for Vec<u8> {
+    fn from(value: ClientHello<'_>) -> Self {
+        let legacy_version: [u8; 2] = value.legacy_version.try_into().unwrap();
+        let mut buf = legacy_version.to_vec();
+        buf.extend_from_slice(&value.random);
+        let session_id = LengthPrefixed::begin_u8(&mut buf);
+        buf.extend_from_slice(value.legacy_session_id);
+        LengthPrefixed::end(&mut buf, session_id);
+        let suites = LengthPrefixed::begin_u16(&mut buf);
+        for suite in &value.cipher_suites {
+            buf.extend_from_slice(&u16::from(*suite).to_be_bytes());
+        }
+        LengthPrefixed::end(&mut buf, suites);
+        let compression_methods = LengthPrefixed::begin_u8(&mut buf);
+        buf.extend_from_slice(value.legacy_compression_methods);
+        LengthPrefixed::end(&mut buf, compression_methods);
+        let block = LengthPrefixed::begin_u16(&mut buf);
+        for extension in value.extensions {
+            buf.extend_from_slice(&Vec::from(extension));
+        }
+        LengthPrefixed::end(&mut buf, block);
+
+        return buf;
+    }
+}
+
+/// The body of EncryptedExtensions is a single extensions vector
+#[allow(dead_code)]
+pub(crate) fn parse_encrypted_extensions(body: &[u8]) -> Result<Vec<Extension<'_>>, TlsError> {
+    if body.len() < 2 {
+        return Err(TlsError::DecodeError("truncated encrypted extensions"));
+    }
+    let length = usize::from(u16::from_be_bytes([body[0], body[1]]));
+    if body.len() - 2 != length {
+        return Err(TlsError::LengthMismatch {
+            field: "extensions",
+            declared: length,
+            actual: body.len() - 2,
+        });
+    }
+
+    return parse_extensions(&body[2..]);
+}
+
+/// The protocol that the server picked in the ALPN extension of
+/// EncryptedExtensions, whose list must hold exactly one name (RFC 7301,
+/// section 3.1)
+#[allow(dead_code)]
+pub(crate) fn parse_alpn_selection(extension_data: &[u8]) -> Result<&[u8], TlsError> {
+    // list length (2) || name length (1) || name
+    if extension_data.len() < 3 {
+        return Err(TlsError::DecodeError("truncated ALPN extension"));
+    }
+    let length = usize::from(u16::from_be_bytes([extension_data[0], extension_data[1]]));
+    if extension_data.len() - 2 != length {
+        return Err(TlsError::LengthMismatch {
+            field: "protocol_name_list",
+            declared: length,
+            actual: extension_data.len() - 2,
+        });
+    }
+    let protocol = &extension_data[3..];
+    if protocol.is_empty() || protocol.len() != usize::from(extension_data[2]) {
+        return Err(TlsError::DecodeError(
+            "ALPN selection must be exactly one protocol name",
+        ));
+    }
+
+    return Ok(protocol);
+}
+
+/// The random of a ServerHello that is actually a HelloRetryRequest: the
+/// SHA-256 of "HelloRetryRequest" (RFC 8446, section 4.1.3)
+#[allow(dead_code)]
+pub(crate) const HELLO_RETRY_REQUEST_RANDOM: [u8; RANDOM_LEN] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// The body of a ServerHello message, i.e. without the handshake type and the
+/// three-byte handshake length
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ServerHello<'a> {
+    pub(crate) legacy_version: ProtocolVersion,
+    pub(crate) random: [u8; RANDOM_LEN],
+    pub(crate) legacy_session_id_echo: &'a [u8],
+    pub(crate) cipher_suite: CipherSuite,
+    pub(crate) legacy_compression_method: u8,
+    pub(crate) extensions: Vec<Extension<'a>>,
+}
+
+#[allow(dead_code)]
+impl<'a> ServerHello<'a> {
+    pub(crate) fn is_hello_retry_request(&self) -> bool {
+        return self.random == HELLO_RETRY_REQUEST_RANDOM;
+    }
+
+    /// The version that the server settled on: the one in supported_versions
+    /// if it is there, and the legacy version otherwise, as a pre-TLS 1.3
+    /// server would have it. TLS 1.0 and 1.1 are refused outright, and so is
+    /// a selected version that is not known, draft versions included unless
+    /// the draft-versions feature is on.
+    pub(crate) fn negotiated_version(&self) -> Result<ProtocolVersion, TlsError> {
+        let version = match self
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::SupportedVersions)
+        {
+            Some(extension) if extension.extension_data.len() == 2 => {
+                ProtocolVersion::try_from(extension.extension_data.as_ref())?
+            }
+            Some(_) => return Err(TlsError::DecodeError("invalid selected version")),
+            None => self.legacy_version,
+        };
+        if version.is_deprecated() {
+            return Err(TlsError::DeprecatedVersion(version));
+        }
+
+        return Ok(version);
+    }
+
+    /// The index of the PSK identity that the server accepted, out of those in
+    /// the client's pre_shared_key extension, or None for a full handshake
+    pub(crate) fn selected_psk_identity(&self) -> Result<Option<u16>, TlsError> {
+        return match self
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::PreSharedKey)
+        {
+            Some(extension) if extension.extension_data.len() == 2 => {
+                Ok(Some(u16::from_be_bytes([
+                    extension.extension_data[0],
+                    extension.extension_data[1],
+                ])))
+            }
+            Some(_) => Err(TlsError::DecodeError("invalid selected_identity")),
+            None => Ok(None),
+        };
+    }
+
+    /// TLS 1.3 only allows supported_versions, key_share, and pre_shared_key
+    /// in a ServerHello; a HelloRetryRequest trades pre_shared_key for cookie
+    fn is_allowed_extension(&self, extension_type: ExtensionType) -> bool {
+        return match extension_type {
+            ExtensionType::SupportedVersions | ExtensionType::KeyShare => true,
+            ExtensionType::PreSharedKey => !self.is_hello_retry_request(),
+            ExtensionType::Cookie => self.is_hello_retry_request(),
+            _ => false,
+        };
+    }
+}
+
+/// A ServerHello body that may still be arriving. It is Incomplete while
+/// the bytes up to the end of the extensions are not all there, and an Error
+/// as soon as a field is out of bounds or bytes follow the extensions.
+#[allow(dead_code)]
+pub(crate) fn parse_server_hello(value: &[u8]) -> ParseOutcome<ServerHello<'_>> {
+    let mut reader = Reader::new(value);
+    let (version, random) = match (reader.take(2), reader.take(RANDOM_LEN)) {
+        (Some(version), Some(random)) => (version, random),
+        _ => return ParseOutcome::Incomplete,
+    };
+    let legacy_version = match ProtocolVersion::from_wire(version) {
+        Ok(legacy_version) => legacy_version,
+        Err(err) => return ParseOutcome::Error(err),
+    };
+    let session_id_length = match reader.read_u8() {
+        Some(session_id_length) => usize::from(session_id_length),
+        None => return ParseOutcome::Incomplete,
+    };
+    if session_id_length > SESSION_ID_MAX_LEN {
+        return ParseOutcome::Error(TlsError::DecodeError("legacy_session_id_echo is too long"));
+    }
+    let fields = (
+        reader.take(session_id_length),
+        reader.read_u16(),
+        reader.read_u8(),
+        reader.read_u16(),
+    );
+    let (legacy_session_id_echo, cipher_suite, legacy_compression_method, extensions_length) =
+        match fields {
+            (Some(session_id), Some(cipher_suite), Some(compression), Some(length)) => {
+                (session_id, cipher_suite, compression, usize::from(length))
+            }
+            _ => return ParseOutcome::Incomplete,
+        };
+    let block = match reader.take(extensions_length) {
+        Some(block) => block,
+        None => return ParseOutcome::Incomplete,
+    };
+    if !reader.remainder().is_empty() {
+        return ParseOutcome::Error(TlsError::LengthMismatch {
+            field: "extensions",
+            declared: extensions_length,
+            actual: extensions_length + reader.remainder().len(),
+        });
+    }
+
+    let extensions = match parse_extensions(block) {
+        Ok(extensions) => extensions,
+        Err(err) => return ParseOutcome::Error(err),
+    };
+    let server_hello = ServerHello {
+        legacy_version,
+        random: random.try_into().unwrap(),
+        legacy_session_id_echo,
+        cipher_suite: CipherSuite::from(cipher_suite),
+        legacy_compression_method,
+        extensions,
+    };
+    for extension in server_hello.extensions.iter() {
+        if !server_hello.is_allowed_extension(extension.extension_type) {
+            return ParseOutcome::Error(TlsError::IllegalParameter(
+                "extension not allowed in server hello",
+            ));
+        }
+    }
+
+    return ParseOutcome::Complete(server_hello);
+}
+
+impl<'a> TryFrom<&'a [u8]> for ServerHello<'a> {
+    type Error = TlsError;
+
+    /// The whole body is at hand, so a short one is an error. An extensions
+    /// block that declares more bytes than the body has left is reported as
+    /// such, rather than as a truncated body.
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        return match parse_server_hello(value) {
+            ParseOutcome::Complete(server_hello) => Ok(server_hello),
+            ParseOutcome::Incomplete => {
+                let mut reader = Reader::new(value);
+                match declared_extensions_length(&mut reader) {
+                    Some(declared) => Err(TlsError::LengthMismatch {
+                        field: "extensions",
+                        declared,
+                        actual: reader.remainder().len(),
+                    }),
+                    None => Err(TlsError::DecodeError("truncated server hello")),
+                }
+            }
+            ParseOutcome::Error(err) => Err(err),
+        };
+    }
+}
+
+/// Skip the fields of a ServerHello before the extensions block and read its
+/// length, leaving the reader at the block
+fn declared_extensions_length(reader: &mut Reader) -> Option<usize> {
+    // version (2) || random || session id length (1)
+    reader.take(2 + RANDOM_LEN)?;
+    let session_id_length = reader.read_u8()?;
+    // session id || cipher suite (2) || compression (1)
+    reader.take(usize::from(session_id_length) + 3)?;
+
+    return reader.read_u16().map(usize::from);
+}
+
+/// One certificate of the chain in a Certificate message, as DER, with the
+/// extensions that apply to it alone, such as a stapled OCSP response in
+/// status_request
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct CertificateEntry {
+    pub(crate) cert_data: Vec<u8>,
+    pub(crate) extensions: Vec<Extension<'static>>,
+}
+
+/// Decode the body of a server's Certificate message: an empty
+/// certificate_request_context, then the list of entries with the
+/// end-entity certificate first
+#[allow(dead_code)]
+pub(crate) fn parse_certificate(body: &[u8]) -> Result<Vec<CertificateEntry>, TlsError> {
+    // context length (1) || certificate_list length (3)
+    if body.len() < 4 {
+        return Err(TlsError::DecodeError("truncated certificate"));
+    }
+    if body[0] != 0 {
+        return Err(TlsError::DecodeError(
+            "certificate_request_context must be empty",
+        ));
+    }
+    let (length, mut remainder) = U24::decode(&body[1..])?;
+    let length = usize::from(length);
+    if remainder.len() != length {
+        return Err(TlsError::LengthMismatch {
+            field: "certificate_list",
+            declared: length,
+            actual: remainder.len(),
+        });
+    }
+
+    let mut entries = vec![];
+    while !remainder.is_empty() {
+        // cert_data length (3) || cert_data || extensions length (2) || extensions
+        let (cert_length, rest) = U24::decode(remainder)
+            .map_err(|_| TlsError::DecodeError("truncated certificate entry"))?;
+        let cert_length = usize::from(cert_length);
+        if rest.len() < cert_length + 2 {
+            return Err(TlsError::DecodeError("truncated certificate entry"));
+        }
+        let (cert_data, rest) = rest.split_at(cert_length);
+        let extensions_length = usize::from(u16::from_be_bytes([rest[0], rest[1]]));
+        if rest.len() - 2 < extensions_length {
+            return Err(TlsError::DecodeError("truncated certificate entry"));
+        }
+        let (block, rest) = rest[2..].split_at(extensions_length);
+        let extensions = parse_extensions(block)?
+            .into_iter()
+            .map(|extension| Extension {
+                extension_type: extension.extension_type,
+                extension_data: Cow::Owned(extension.extension_data.into_owned()),
+            })
+            .collect();
+        entries.push(CertificateEntry {
+            cert_data: cert_data.to_vec(),
+            extensions,
+        });
+        remainder = rest;
+    }
+
+    return Ok(entries);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::constants::{ContentType, RECORD_HEADER_LEN};
+    use crate::record_layer::{RecordLayer, TLSPlaintext};
+
+    /// The extensions of the ServerHello in tls-capture.log, followed by an
+    /// extension type that the crate does not know about
+    const THREE_EXTENSIONS: [u8; 50] = [
+        0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, // supported_versions
+        0x00, 0x33, 0x00, 0x24, 0x00, 0x1d, 0x00, 0x20, // key_share
+        0x37, 0x81, 0x3b, 0xa1, 0xf6, 0x9d, 0xe9, 0xa8, 0x82, 0xe8, 0x43, 0xd4, 0x32, 0x24, 0x9d,
+        0xd5, 0x01, 0x34, 0xfc, 0x9a, 0x5a, 0x49, 0x2f, 0xee, 0x15, 0xb4, 0xf1, 0x59, 0xdb, 0x0e,
+        0x90, 0x07, //
+        0xfe, 0x0d, 0x00, 0x00, // unknown, empty
+    ];
+
+    #[test]
+    fn iterate_three_extensions() {
+        let types: Vec<ExtensionType> = ExtensionIter::new(&THREE_EXTENSIONS)
+            .map(|extension| extension.unwrap().extension_type)
+            .collect();
+
+        assert_eq!(
+            types,
+            vec![
+                ExtensionType::SupportedVersions,
+                ExtensionType::KeyShare,
+                ExtensionType::Unknown(0xfe0d),
+            ]
+        );
+    }
+
+    #[test]
+    fn extension_data_cannot_extend_past_block() {
+        // supported_versions claims 4 bytes, but the block ends after 2; the
+        // bytes after the block belong to something else
+        let buffer = [0x00, 0x2b, 0x00, 0x04, 0x03, 0x04, 0xaa, 0xbb];
+        let mut extensions = ExtensionIter::new(&buffer[..6]);
+
+        assert!(matches!(
+            extensions.next(),
+            Some(Err(TlsError::LengthMismatch {
+                field: "extension_data",
+                declared: 4,
+                actual: 2,
+            }))
+        ));
+        assert!(extensions.next().is_none());
+        assert!(parse_extensions(&buffer[..6]).is_err());
+    }
+
+    #[test]
+    fn keep_status_request_data_opaque() {
+        let block = [
+            0x00, 0x05, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, // status_request
+            0x00, 0x12, 0x00, 0x00, // signed_certificate_timestamp
+        ];
+        let extensions = parse_extensions(&block).unwrap();
+
+        assert_eq!(extensions[0].extension_type, ExtensionType::StatusRequest);
+        assert_eq!(
+            extensions[0].extension_data.as_ref(),
+            [0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            extensions[1].extension_type,
+            ExtensionType::SignedCertificateTimestamp
+        );
+        assert!(extensions[1].extension_data.is_empty());
+    }
+
+    #[test]
+    fn encode_client_key_share_with_two_entries() {
+        let extension: Vec<u8> = Extension::from(KeyShare::ClientHello(vec![
+            KeyShareEntry {
+                group: NamedGroup::X25519,
+                key_exchange: vec![0xaa; 2],
+            },
+            KeyShareEntry {
+                group: NamedGroup::Secp256r1,
+                key_exchange: vec![0xbb; 3],
+            },
+        ]))
+        .into();
+
+        assert_eq!(
+            extension,
+            [
+                0x00, 0x33, 0x00, 0x0f, // key_share, 15 bytes
+                0x00, 0x0d, // client_shares, 13 bytes
+                0x00, 0x1d, 0x00, 0x02, 0xaa, 0xaa, // x25519
+                0x00, 0x17, 0x00, 0x03, 0xbb, 0xbb, 0xbb, // secp256r1
+            ]
+        );
+        let entries = KeyShare::parse_client_hello(&extension[4..]).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].group, NamedGroup::Secp256r1);
+    }
+
+    #[test]
+    fn parse_server_key_share() {
+        let key_share = find_extension(&THREE_EXTENSIONS, ExtensionType::KeyShare)
+            .unwrap()
+            .unwrap();
+
+        let entry = KeyShare::parse_server_hello(&key_share.extension_data).unwrap();
+        assert_eq!(entry.group, NamedGroup::X25519);
+        assert_eq!(entry.key_exchange.len(), 32);
+        assert!(matches!(
+            KeyShare::parse_server_hello(&key_share.extension_data[..35]),
+            Err(TlsError::LengthMismatch {
+                field: "key_exchange",
+                declared: 32,
+                actual: 31,
+            })
+        ));
+    }
+
+    #[test]
+    fn find_key_share() {
+        let key_share = find_extension(&THREE_EXTENSIONS, ExtensionType::KeyShare)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(key_share.extension_data.len(), 0x24);
+        assert_eq!(key_share.extension_data[0..4], [0x00, 0x1d, 0x00, 0x20]);
+    }
+
+    #[test]
+    fn find_missing_extension() {
+        let server_name = find_extension(&THREE_EXTENSIONS, ExtensionType::ServerName).unwrap();
+
+        assert!(server_name.is_none());
+    }
+
+    #[test]
+    fn iterate_truncated_extension() {
+        // Claims four bytes of data but only carries two
+        let mut iter = ExtensionIter::new(&[0x00, 0x2b, 0x00, 0x04, 0x03, 0x04]);
+
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn parse_distinct_extensions() {
+        let extensions = parse_extensions(&THREE_EXTENSIONS).unwrap();
+
+        assert_eq!(extensions.len(), 3);
+    }
+
+    #[test]
+    fn reject_duplicate_supported_versions() {
+        let block = [
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, // supported_versions
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x03, // supported_versions, again
+        ];
+
+        assert!(matches!(
+            parse_extensions(&block),
+            Err(TlsError::DuplicateExtension(
+                ExtensionType::SupportedVersions
+            ))
+        ));
+    }
+
+    #[test]
+    fn find_every_duplicate() {
+        let block = [
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, // supported_versions
+            0xfe, 0x0d, 0x00, 0x00, // unknown, empty
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x03, // supported_versions, again
+        ];
+
+        assert_eq!(
+            find_all_extensions(&block, ExtensionType::SupportedVersions),
+            [[0x03, 0x04].as_slice(), [0x03, 0x03].as_slice()]
+        );
+        assert_eq!(
+            find_all_extensions(&block, ExtensionType::Unknown(0xfe0d)),
+            [[].as_slice()]
+        );
+        assert!(find_all_extensions(&block, ExtensionType::KeyShare).is_empty());
+        assert_eq!(
+            find_all_extensions(&block[..14], ExtensionType::SupportedVersions).len(),
+            1
+        );
+    }
+
+    /// The ServerHello of the RFC 8448 simple 1-RTT handshake, without the
+    /// handshake header
+    const RFC8448_SERVER_HELLO: [u8; 86] = [
+        0x03, 0x03, 0xa6, 0xaf, 0x06, 0xa4, 0x12, 0x18, 0x60, 0xdc, 0x5e, 0x6e, 0x60, 0x24, 0x9c,
+        0xd3, 0x4c, 0x95, 0x93, 0x0c, 0x8a, 0xc5, 0xcb, 0x14, 0x34, 0xda, 0xc1, 0x55, 0x77, 0x2e,
+        0xd3, 0xe2, 0x69, 0x28, 0x00, 0x13, 0x01, 0x00, 0x00, 0x2e, 0x00, 0x33, 0x00, 0x24, 0x00,
+        0x1d, 0x00, 0x20, 0xc9, 0x82, 0x88, 0x76, 0x11, 0x20, 0x95, 0xfe, 0x66, 0x76, 0x2b, 0xdb,
+        0xf7, 0xc6, 0x72, 0xe1, 0x56, 0xd6, 0xcc, 0x25, 0x3b, 0x83, 0x3d, 0xf1, 0xdd, 0x69, 0xb1,
+        0xb0, 0x4e, 0x75, 0x1f, 0x0f, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04,
+    ];
+
+    #[test]
+    fn parse_clean_server_hello() {
+        let server_hello = ServerHello::try_from(RFC8448_SERVER_HELLO.as_slice()).unwrap();
+
+        assert_eq!(server_hello.legacy_version, ProtocolVersion::TLSv1_2);
+        assert_eq!(server_hello.random[0..2], [0xa6, 0xaf]);
+        assert!(server_hello.legacy_session_id_echo.is_empty());
+        assert_eq!(server_hello.cipher_suite, CipherSuite::Aes128GcmSha256);
+        assert_eq!(server_hello.extensions.len(), 2);
+        assert!(!server_hello.is_hello_retry_request());
+    }
+
+    #[test]
+    fn reject_long_session_id_echo() {
+        // A 40-byte echo that is really there, so that only the bound fails
+        let mut encoding = RFC8448_SERVER_HELLO[..34].to_vec();
+        encoding.push(40);
+        encoding.extend_from_slice(&[0xaa; 40]);
+        encoding.extend_from_slice(&RFC8448_SERVER_HELLO[35..]);
+
+        assert!(matches!(
+            ServerHello::try_from(encoding.as_slice()),
+            Err(TlsError::DecodeError("legacy_session_id_echo is too long"))
+        ));
+    }
+
+    #[test]
+    fn feed_server_hello_one_byte_at_a_time() {
+        for end in 0..RFC8448_SERVER_HELLO.len() {
+            assert!(
+                matches!(
+                    parse_server_hello(&RFC8448_SERVER_HELLO[..end]),
+                    ParseOutcome::Incomplete
+                ),
+                "{} bytes",
+                end
+            );
+        }
+        let ParseOutcome::Complete(server_hello) = parse_server_hello(&RFC8448_SERVER_HELLO) else {
+            panic!("the whole server hello did not parse");
+        };
+        assert_eq!(server_hello.cipher_suite, CipherSuite::Aes128GcmSha256);
+
+        let mut trailing = RFC8448_SERVER_HELLO.to_vec();
+        trailing.push(0);
+        assert!(matches!(
+            parse_server_hello(&trailing),
+            ParseOutcome::Error(TlsError::LengthMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn serialize_raw_extension() {
+        let extension = Extension::raw(0xfe0d, vec![0xde, 0xad]);
+        assert_eq!(extension.extension_type, ExtensionType::Unknown(0xfe0d));
+
+        let encoding: Vec<u8> = extension.into();
+        assert_eq!(encoding, [0xfe, 0x0d, 0x00, 0x02, 0xde, 0xad]);
+    }
+
+    #[test]
+    fn reject_raw_early_data_in_server_hello() {
+        let extension: Vec<u8> = Extension::raw(42, vec![]).into();
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding.extend_from_slice(&extension);
+        encoding[39] += extension.len() as u8;
+
+        assert!(matches!(
+            ServerHello::try_from(encoding.as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    #[test]
+    fn refuse_to_settle_on_tls_1_1() {
+        let server_hello = ServerHello::try_from(RFC8448_SERVER_HELLO.as_slice()).unwrap();
+        assert_eq!(
+            server_hello.negotiated_version().unwrap(),
+            ProtocolVersion::TLSv1_3
+        );
+
+        // Swap the selected version in the trailing supported_versions
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding[85] = 0x02;
+        let server_hello = ServerHello::try_from(encoding.as_slice()).unwrap();
+        assert!(matches!(
+            server_hello.negotiated_version(),
+            Err(TlsError::DeprecatedVersion(ProtocolVersion::TLSv1_1))
+        ));
+    }
+
+    /// RFC8448_SERVER_HELLO with the selected version swapped for draft 28
+    fn draft_server_hello() -> Vec<u8> {
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding[84..86].copy_from_slice(&[0x7f, 0x1c]);
+        return encoding;
+    }
+
+    #[cfg(feature = "draft-versions")]
+    #[test]
+    fn accept_draft_version() {
+        let encoding = draft_server_hello();
+        let server_hello = ServerHello::try_from(encoding.as_slice()).unwrap();
+        assert_eq!(
+            server_hello.negotiated_version().unwrap(),
+            ProtocolVersion::DraftVersion(0x7f1c)
+        );
+    }
+
+    #[cfg(not(feature = "draft-versions"))]
+    #[test]
+    fn reject_draft_version() {
+        let encoding = draft_server_hello();
+        let server_hello = ServerHello::try_from(encoding.as_slice()).unwrap();
+        assert!(matches!(
+            server_hello.negotiated_version(),
+            Err(TlsError::InvalidProtocolVersion)
+        ));
+    }
+
+    #[test]
+    fn client_hello_round_trip() {
+        let client_hello = build_client_hello(
+            &[0x5a; RANDOM_LEN],
+            &[0x01; 32],
+            &[CipherSuite::Aes128GcmSha256],
+            vec![
+                supported_versions(&[ProtocolVersion::TLSv1_3]),
+                key_share(&[(NamedGroup::X25519, vec![0x09; 32])]),
+            ],
+        );
+        let encoding = client_hello.to_bytes();
+        assert_eq!(
+            encoding[..HANDSHAKE_HEADER_LENGTH],
+            [0x01, 0x00, 0x00, client_hello.body.len() as u8]
+        );
+
+        let mut joiner = HandshakeJoiner::new();
+        joiner.push(&encoding);
+        assert_eq!(joiner.next_message().unwrap(), Some(client_hello));
+        assert!(joiner.is_empty());
+    }
+
+    /// RFC 8448, section 3: the body of the ClientHello, for the server
+    /// "server"
+    const RFC8448_CLIENT_HELLO_BODY: &str = concat!(
+        "0303cb34ecb1e78163ba1c38c6dacb196a6dffa21a8d9912ec18a2ef6283024dece70000",
+        "06130113031302010000910000000b0009000006736572766572ff01000100000a00140012001d00",
+        "17001800190100010101020103010400230000003300260024001d002099381de560e4bd43d23d8e",
+        "435a7dbafeb3c06e51c13cae4d5413691e529aaf2c002b0003020304000d0020001e040305030603",
+        "020308040805080604010501060102010402050206020202002d00020101001c00024001",
+    );
+
+    #[test]
+    fn server_name_from_client_hello_extensions() {
+        let body = hex::decode(RFC8448_CLIENT_HELLO_BODY).unwrap();
+        let extensions = parse_client_hello_extensions(&body).unwrap();
+        assert_eq!(extensions.len(), 9);
+        let server_name = extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::ServerName)
+            .unwrap();
+        // list length (2) || name_type (1) || name length (2) || name
+        assert_eq!(server_name.extension_data[5..], *b"server");
+
+        assert!(matches!(
+            parse_client_hello_extensions(&body[..40]),
+            Err(TlsError::DecodeError("truncated client hello"))
+        ));
+        assert!(matches!(
+            parse_client_hello_extensions(&body[..body.len() - 1]),
+            Err(TlsError::LengthMismatch { .. })
+        ));
+    }
+
+    /// Many extensions, as a browser would send
+    fn many_extensions() -> Vec<Extension<'static>> {
+        let mut extensions = vec![
+            server_name("example.com"),
+            supported_versions(&[ProtocolVersion::TLSv1_3]),
+            supported_groups(&[NamedGroup::X25519, NamedGroup::Secp256r1]),
+            key_share(&[(NamedGroup::X25519, vec![0x09; 32])]),
+            application_layer_protocol_negotiation(&[b"h2".to_vec(), b"http/1.1".to_vec()]),
+        ];
+        extensions.extend((0..32).map(|i| Extension::raw(0xff00 + i, vec![i as u8; 48])));
+        return extensions;
+    }
+
+    #[test]
+    fn single_pass_client_hello_matches_naive() {
+        for (session_id, extensions) in [(vec![], vec![]), (vec![0x01; 32], many_extensions())] {
+            let suites = [CipherSuite::Aes128GcmSha256, CipherSuite::Aes256GcmSha384];
+            let naive = build_client_hello(
+                &[0x5a; RANDOM_LEN],
+                &session_id,
+                &suites,
+                extensions.clone(),
+            );
+            assert_eq!(
+                encode_client_hello(&[0x5a; RANDOM_LEN], &session_id, &suites, &extensions),
+                naive.to_bytes()
+            );
+        }
+    }
+
+    /// cargo test --release bench_client_hello_encoders -- --ignored --nocapture
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_client_hello_encoders() {
+        const ROUNDS: u32 = 100_000;
+        let extensions = many_extensions();
+        let suites = [CipherSuite::Aes128GcmSha256];
+
+        // build_client_hello takes the extensions by value, so the naive
+        // encoder also pays for cloning them
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            let message = build_client_hello(&[0x5a; RANDOM_LEN], &[], &suites, extensions.clone());
+            std::hint::black_box(message.to_bytes());
+        }
+        let naive = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            std::hint::black_box(encode_client_hello(
+                &[0x5a; RANDOM_LEN],
+                &[],
+                &suites,
+                &extensions,
+            ));
+        }
+        let single_pass = start.elapsed();
+
+        println!(
+            "naive: {:?} per ClientHello, single pass: {:?} per ClientHello",
+            naive / ROUNDS,
+            single_pass / ROUNDS
+        );
+    }
+
+    /// The ClientHello record that curl 7.88.1 with OpenSSL 3.0 sends to
+    /// example.com
+    const CURL_CLIENT_HELLO: &[u8] = include_bytes!("../testdata/curl/client-hello.bin");
+
+    #[test]
+    fn encode_minimal_client_hello() {
+        let client_hello = ClientHello {
+            legacy_version: ProtocolVersion::TLSv1_2,
+            random: [0xaa; RANDOM_LEN],
+            legacy_session_id: &[],
+            cipher_suites: vec![CipherSuite::Aes128GcmSha256],
+            legacy_compression_methods: &[0x00],
+            extensions: vec![supported_versions(&[ProtocolVersion::TLSv1_3])],
+        };
+        let body = Vec::from(client_hello.clone());
+
+        let expected = [
+            &[0x03, 0x03][..], // legacy_version
+            &[0xaa; RANDOM_LEN],
+            &[0x00],                                                 // legacy_session_id
+            &[0x00, 0x02, 0x13, 0x01],                               // cipher_suites
+            &[0x01, 0x00],                                           // legacy_compression_methods
+            &[0x00, 0x07, 0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04], // supported_versions
+        ]
+        .concat();
+        assert_eq!(body, expected);
+        assert_eq!(
+            ClientHello::try_from(body.as_slice()).unwrap(),
+            client_hello
+        );
+
+        // Wrapped in a handshake message, as the fragment of a handshake record
+        let message = Vec::from(HandshakeMessage {
+            msg_type: HandshakeType::ClientHello,
+            body: body.clone(),
+        });
+        let record = RecordLayer::new()
+            .write_records(ContentType::Handshake, &message)
+            .unwrap();
+        let record = TLSPlaintext::try_from(record.as_slice()).unwrap();
+        assert_eq!(record.content_type(), ContentType::Handshake);
+        assert_eq!(record.fragment()[..4], [0x01, 0x00, 0x00, body.len() as u8]);
+        assert_eq!(record.fragment()[4..], body);
+    }
+
+    #[test]
+    fn parse_curl_client_hello() {
+        let body = &CURL_CLIENT_HELLO[RECORD_HEADER_LEN + HANDSHAKE_HEADER_LENGTH..];
+        let client_hello = ClientHello::try_from(body).unwrap();
+        assert_eq!(client_hello.legacy_version, ProtocolVersion::TLSv1_2);
+        assert_eq!(client_hello.legacy_session_id.len(), 32);
+        assert_eq!(client_hello.cipher_suites.len(), 31);
+        assert_eq!(
+            client_hello.cipher_suites[..3],
+            [
+                CipherSuite::Aes256GcmSha384,
+                CipherSuite::Chacha20Poly1305Sha256,
+                CipherSuite::Aes128GcmSha256,
+            ]
+        );
+        assert_eq!(client_hello.legacy_compression_methods, [0]);
+        let sni = client_hello
+            .extensions
+            .iter()
+            .find(|extension| extension.extension_type == ExtensionType::ServerName)
+            .unwrap();
+        assert_eq!(sni.extension_data[5..], *b"example.com");
+
+        assert!(matches!(
+            ClientHello::try_from(&body[..body.len() - 1]),
+            Err(TlsError::LengthMismatch { .. })
+        ));
+        let without_supported_versions = build_client_hello(
+            &[0x5a; RANDOM_LEN],
+            &[],
+            &[CipherSuite::Aes128GcmSha256],
+            vec![server_name("example.com")],
+        );
+        assert!(matches!(
+            ClientHello::try_from(without_supported_versions.body.as_slice()),
+            Err(TlsError::MissingExtension(ExtensionType::SupportedVersions))
+        ));
+    }
+
+    #[test]
+    fn finished_round_trip() {
+        let finished = Finished {
+            verify_data: vec![0xab; 32],
+        };
+        let encoding = HandshakeMessage::from(finished.clone()).to_bytes();
+        assert_eq!(encoding[..HANDSHAKE_HEADER_LENGTH], [20, 0x00, 0x00, 32]);
+        assert_eq!(encoding.len(), HANDSHAKE_HEADER_LENGTH + 32);
+
+        let mut joiner = HandshakeJoiner::new();
+        joiner.push(&encoding);
+        let message = joiner.next_message().unwrap().unwrap();
+        assert_eq!(Finished::try_from(&message).unwrap(), finished);
+    }
+
+    #[test]
+    fn certificate_verify_round_trip() {
+        let certificate_verify = CertificateVerify {
+            scheme: SignatureScheme::Ed25519,
+            signature: vec![0xab; 64],
+        };
+        let message = HandshakeMessage::from(certificate_verify.clone());
+        assert_eq!(message.body[..4], [0x08, 0x07, 0x00, 0x40]);
+        assert_eq!(
+            CertificateVerify::try_from(message.body.as_slice()).unwrap(),
+            certificate_verify
+        );
+
+        assert!(matches!(
+            CertificateVerify::try_from(&message.body[..message.body.len() - 1]),
+            Err(TlsError::LengthMismatch { .. })
+        ));
+        assert!(CertificateVerify::try_from(&[0x08, 0x07, 0x00][..]).is_err());
+    }
+
+    #[test]
+    fn parse_certificate_chain() {
+        let body = [
+            0x00, // certificate_request_context
+            0x00, 0x00, 0x0d, // certificate_list
+            0x00, 0x00, 0x02, 0x30, 0x00, 0x00, 0x00, // end-entity
+            0x00, 0x00, 0x01, 0x30, 0x00, 0x00, // intermediate
+        ];
+        let entries = parse_certificate(&body).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].cert_data, [0x30, 0x00]);
+        assert_eq!(entries[1].cert_data, [0x30]);
+        assert!(entries[1].extensions.is_empty());
+
+        assert!(parse_certificate(&body[..body.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn parse_stapled_ocsp_response() {
+        let body = [
+            0x00, // certificate_request_context
+            0x00, 0x00, 0x11, // certificate_list
+            0x00, 0x00, 0x02, 0x30, 0x00, // end-entity
+            0x00, 0x0a, // extensions
+            0x00, 0x05, 0x00, 0x06, // status_request
+            0x01, 0x00, 0x00, 0x02, 0xab, 0xcd, // ocsp, with a 2-byte response
+        ];
+        let entries = parse_certificate(&body).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].extensions.len(), 1);
+        assert_eq!(
+            entries[0].extensions[0].extension_type,
+            ExtensionType::StatusRequest
+        );
+        assert_eq!(
+            *entries[0].extensions[0].extension_data,
+            [0x01, 0x00, 0x00, 0x02, 0xab, 0xcd]
+        );
+    }
+
+    #[test]
+    fn reject_server_name_in_server_hello() {
+        // Append an empty server_name extension and fix up the block length
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        encoding[39] += 4;
+
+        assert!(matches!(
+            ServerHello::try_from(encoding.as_slice()),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    #[test]
+    fn parse_selected_psk_identity() {
+        assert_eq!(
+            ServerHello::try_from(RFC8448_SERVER_HELLO.as_slice())
+                .unwrap()
+                .selected_psk_identity()
+                .unwrap(),
+            None
+        );
+
+        // Append pre_shared_key selecting the first identity
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding.extend_from_slice(&[0x00, 0x29, 0x00, 0x02, 0x00, 0x00]);
+        encoding[39] += 6;
+        let server_hello = ServerHello::try_from(encoding.as_slice()).unwrap();
+        assert_eq!(server_hello.selected_psk_identity().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn report_wrong_extensions_length() {
+        let mut encoding = RFC8448_SERVER_HELLO.to_vec();
+        encoding[39] = 0x30;
+
+        // More extensions are declared than are there, which may just be bytes
+        // yet to arrive
+        assert!(matches!(
+            parse_server_hello(&encoding),
+            ParseOutcome::Incomplete
+        ));
+        assert!(matches!(
+            ServerHello::try_from(encoding.as_slice()),
+            Err(TlsError::LengthMismatch {
+                field: "extensions",
+                declared: 0x30,
+                actual: 0x2e,
+            })
+        ));
+        assert!(matches!(
+            ServerHello::try_from(&encoding[..39]),
+            Err(TlsError::DecodeError("truncated server hello"))
+        ));
+
+        encoding[39] = 0x2c;
+        assert!(matches!(
+            ServerHello::try_from(encoding.as_slice()),
+            Err(TlsError::LengthMismatch {
+                field: "extensions",
+                declared: 0x2c,
+                actual: 0x2e,
+            })
+        ));
+    }
+
+    #[test]
+    fn encode_psk_dhe_ke() {
+        let extension = psk_key_exchange_modes(&[PskKeyExchangeMode::PskDheKe]).unwrap();
+        let encoding: Vec<u8> = extension.into();
+
+        assert_eq!(encoding, [0x00, 0x2d, 0x00, 0x02, 0x01, 0x01]);
+        assert_eq!(
+            parse_psk_key_exchange_modes(&encoding[4..]).unwrap(),
+            vec![PskKeyExchangeMode::PskDheKe]
+        );
+    }
+
+    #[test]
+    fn reject_empty_psk_key_exchange_modes() {
+        assert!(psk_key_exchange_modes(&[]).is_err());
+        assert!(parse_psk_key_exchange_modes(&[0x00]).is_err());
+        assert!(parse_psk_key_exchange_modes(&[]).is_err());
+    }
+
+    #[test]
+    fn join_messages_across_fragments() {
+        let first: Vec<u8> = HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body: vec![0x00, 0x00],
+        }
+        .into();
+        let second: Vec<u8> = HandshakeMessage {
+            msg_type: HandshakeType::Finished,
+            body: vec![0xaa; 32],
+        }
+        .into();
+        let flight = [first, second].concat();
+
+        let mut joiner = HandshakeJoiner::new();
+        joiner.push(&flight[..10]);
+        let message = joiner.next_message().unwrap().unwrap();
+        assert_eq!(message.msg_type, HandshakeType::EncryptedExtensions);
+        assert!(joiner.next_message().unwrap().is_none());
+        joiner.push(&flight[10..]);
+        assert_eq!(joiner.next_message().unwrap().unwrap().body, vec![0xaa; 32]);
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn reject_unknown_handshake_type() {
+        let mut joiner = HandshakeJoiner::new();
+        // Only the header of a type 99 message, whose body never arrives
+        joiner.push(&[99, 0x00, 0x01, 0x00]);
+
+        assert!(matches!(
+            joiner.next_message(),
+            Err(TlsError::UnexpectedMessage("unknown handshake type"))
+        ));
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        let messages = [
+            build_client_hello(
+                &[0x11; RANDOM_LEN],
+                &[0x22; 32],
+                &[CipherSuite::Aes128GcmSha256],
+                vec![supported_versions(&[ProtocolVersion::TLSv1_3])],
+            ),
+            HandshakeMessage {
+                msg_type: HandshakeType::EncryptedExtensions,
+                body: vec![0x00, 0x00],
+            },
+            HandshakeMessage {
+                msg_type: HandshakeType::Certificate,
+                body: vec![0xab; 0x10000],
+            },
+            HandshakeMessage::from(Finished {
+                verify_data: vec![0x33; 32],
+            }),
+            HandshakeMessage {
+                msg_type: HandshakeType::EndOfEarlyData,
+                body: vec![],
+            },
+        ];
+
+        for message in messages {
+            assert_eq!(message.encoded_len(), message.to_bytes().len());
+        }
+    }
+
+    /// Run a HandshakeParser over the bytes until it halts
+    fn parse_handshake(bytes: &[u8]) -> HandshakeParser<'_> {
+        let mut parser = HandshakeParser::start(bytes);
+        while !parser.is_halt() {
+            parser = parser.transition();
+        }
+
+        return parser;
+    }
+
+    #[test]
+    fn parse_handshake_with_three_byte_length() {
+        // 0x010000 is 65536, which a two-byte length could not hold
+        let mut fragment = vec![11, 0x01, 0x00, 0x00];
+        fragment.extend_from_slice(&[0xab; 0x10000]);
+
+        match parse_handshake(&fragment) {
+            HandshakeParser::Finished { msg_type, body } => {
+                assert_eq!(msg_type, HandshakeType::Certificate);
+                assert_eq!(body.len(), 0x10000);
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse_handshake(&fragment[..fragment.len() - 1]).is_incomplete());
+    }
+
+    #[test]
+    fn parse_handshake_message() {
+        match parse_handshake(&[20, 0x00, 0x00, 0x02, 0xaa, 0xbb]) {
+            HandshakeParser::Finished { msg_type, body } => {
+                assert_eq!(msg_type, HandshakeType::Finished);
+                assert_eq!(body, vec![0xaa, 0xbb]);
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse_handshake(&[]).is_incomplete());
+        assert!(parse_handshake(&[20, 0x00, 0x00]).is_incomplete());
+        assert!(matches!(
+            parse_handshake(&[99, 0x00, 0x00, 0x00]).failure_reason(),
+            Some(TlsError::UnexpectedMessage("unknown handshake type"))
+        ));
+        assert!(matches!(
+            parse_handshake(&[20, 0x00, 0x00, 0x01, 0xaa, 0xbb]).failure_reason(),
+            Some(TlsError::LengthMismatch {
+                declared: 1,
+                actual: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn build_minimal_client_hello() {
+        let message = build_client_hello(
+            &[0x11; 32],
+            &[],
+            &[CipherSuite::Aes128GcmSha256],
+            vec![supported_versions(&[ProtocolVersion::TLSv1_3])],
+        );
+        let encoding: Vec<u8> = message.into();
+
+        assert_eq!(encoding[0..4], [0x01, 0x00, 0x00, 0x32]);
+        assert_eq!(encoding[4..6], [0x03, 0x03]);
+        assert_eq!(
+            encoding[38..],
+            [
+                0x00, // no session id
+                0x00, 0x02, 0x13, 0x01, // cipher suites
+                0x01, 0x00, // compression methods
+                0x00, 0x07, 0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04, // extensions
+            ]
+        );
+    }
+}
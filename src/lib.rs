@@ -1,3 +1,20 @@
+#![allow(clippy::needless_return)]
+mod alert;
+pub mod cli;
+#[cfg(feature = "record-capture")]
+mod capture;
+mod clock;
+mod codec;
+mod config;
+mod conn;
 mod record_layer;
 mod constants;
+mod crypto;
+mod error;
 mod fsm;
+mod handshake;
+mod observer;
+pub mod prelude;
+#[cfg(feature = "buffer-pool")]
+mod pool;
+mod verify;
@@ -1,35 +1,41 @@
 //! Let's start with capturing inputs and outputs of a TLS stream
 //! This is copied from the example of rustls/rustls
+#![allow(clippy::needless_return)]
 use rustls::{OwnedTrustAnchor, RootCertStore};
 use std::io::{Read, Write, stdout};
 use std::net::TcpStream;
 use std::sync::Arc;
 
-struct LoggedTcpStream<T> {
+/// Logs the hex of every byte that goes through the socket; writeln! goes
+/// through write_all, so a short write to the log is never lost
+struct LoggedTcpStream<T, S = TcpStream> {
     writer: T,
-    socket: TcpStream,
+    socket: S,
 }
 
-impl<T: Write> LoggedTcpStream<T> {
-    fn new(writer: T, socket: TcpStream) -> Self {
+impl<T: Write, S> LoggedTcpStream<T, S> {
+    fn new(writer: T, socket: S) -> Self {
         return Self { writer, socket };
     }
 }
 
-impl<T: Write> Read for LoggedTcpStream<T> {
+impl<T: Write, S: Read> Read for LoggedTcpStream<T, S> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let nbytes = self.socket.read(buf)?;
-        let hexstr = hex::encode(&buf);
+        // Only the bytes just read; the rest of buf is whatever it held before
+        let hexstr = hex::encode(&buf[..nbytes]);
         writeln!(self.writer, "Received: {}", hexstr)?;
         return Ok(nbytes);
     }
 }
 
-impl<T: Write> Write for LoggedTcpStream<T> {
+impl<T: Write, S: Write> Write for LoggedTcpStream<T, S> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let hexstr = hex::encode(&buf);
+        let nbytes = self.socket.write(buf)?;
+        // Only the bytes the socket took; write_all sends the rest again
+        let hexstr = hex::encode(&buf[..nbytes]);
         writeln!(self.writer, "Sent: {}", hexstr)?;
-        return self.socket.write(&buf);
+        return Ok(nbytes);
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -39,8 +45,33 @@ impl<T: Write> Write for LoggedTcpStream<T> {
 
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["parse", hexstring] => print_or_exit(tls_core::cli::parse(hexstring)),
+        ["client-hello", hostname] => print_or_exit(tls_core::cli::client_hello(hostname)),
+        ["client-hello", hostname, "--connect"] => {
+            print_or_exit(tls_core::cli::send_client_hello(hostname))
+        }
+        _ => fetch(),
+    }
+}
+
+/// Print the output of a subcommand, or its error and exit with failure
+fn print_or_exit(output: Result<String, String>) {
+    match output {
+        Ok(output) => println!("{}", output),
+        Err(reason) => {
+            eprintln!("{}", reason);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The original demo: fetch www.rust-lang.org with rustls and log the bytes
+fn fetch() {
     let mut root_store = RootCertStore::empty();
-    root_store.add_server_trust_anchors(
+    root_store.add_trust_anchors(
         webpki_roots::TLS_SERVER_ROOTS
             .0
             .iter()
@@ -90,3 +121,60 @@ fn main() {
     tls.read_to_end(&mut plaintext).unwrap();
     // stdout().write_all(&plaintext).unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Hands out at most three bytes per read and takes at most three per
+    /// write, like a socket whose data moves in small segments
+    struct ShortSegments(Vec<u8>);
+
+    impl Read for ShortSegments {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let nbytes = buf.len().min(3).min(self.0.len());
+            buf[..nbytes].copy_from_slice(&self.0[..nbytes]);
+            self.0.drain(..nbytes);
+            return Ok(nbytes);
+        }
+    }
+
+    impl Write for ShortSegments {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            return Ok(buf.len().min(3));
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn tag_each_direction() {
+        let mut sock = LoggedTcpStream::new(vec![], ShortSegments(vec![0x15, 0x03]));
+        sock.write_all(&[0x16, 0x03, 0x01]).unwrap();
+        assert_eq!(sock.read(&mut [0; 4]).unwrap(), 2);
+        // A short write logs what the socket took, and write_all the rest
+        sock.write_all(&[0x16, 0x03, 0x03, 0x00, 0x01]).unwrap();
+
+        let log = String::from_utf8(sock.writer).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            ["Sent: 160301", "Received: 1503", "Sent: 160303", "Sent: 0001"]
+        );
+    }
+
+    #[test]
+    fn log_only_the_bytes_read() {
+        let mut sock = LoggedTcpStream::new(vec![], ShortSegments(vec![0x16, 0x03, 0x03, 0x00]));
+        let mut buf = [0xee; 8];
+        assert_eq!(sock.read(&mut buf).unwrap(), 3);
+        assert_eq!(sock.read(&mut buf).unwrap(), 1);
+
+        assert_eq!(
+            String::from_utf8(sock.writer).unwrap(),
+            "Received: 160303\nReceived: 00\n"
+        );
+    }
+}
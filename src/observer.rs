@@ -0,0 +1,29 @@
+//! Hooks for watching a handshake from the outside, e.g. to log it, so that
+//! the connection itself never has to write to stderr
+use crate::conn::HandshakeState;
+use crate::handshake::HandshakeMessage;
+
+/// Which traffic keys were just derived
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum KeyEpoch {
+    Handshake,
+    Application,
+}
+
+/// Every callback does nothing by default, so an observer only implements the
+/// events it cares about
+pub(crate) trait HandshakeObserver {
+    fn on_message_sent(&mut self, _message: &HandshakeMessage) {}
+
+    fn on_message_received(&mut self, _message: &HandshakeMessage) {}
+
+    fn on_state_change(&mut self, _old: HandshakeState, _new: HandshakeState) {}
+
+    fn on_keys_derived(&mut self, _epoch: KeyEpoch) {}
+}
+
+/// The observer of a connection that nobody is watching
+pub(crate) struct NoopObserver;
+
+impl HandshakeObserver for NoopObserver {}
@@ -0,0 +1,148 @@
+//! A pool of scratch buffers for the record layer, so that sealing and
+//! parsing records under load reuses a few buffers instead of allocating one
+//! for every record. A buffer taken from the pool goes back to it when it is
+//! dropped.
+use crate::crypto;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Idle buffers beyond this many are freed rather than kept
+const MAX_IDLE_BUFFERS: usize = 16;
+
+/// How the buffers taken from a pool were come by
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) struct PoolStats {
+    /// Taken when no idle buffer was left, so that a new one was allocated
+    pub(crate) allocated: u64,
+
+    /// Taken from the idle buffers
+    pub(crate) reused: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    idle: Vec<Vec<u8>>,
+    stats: PoolStats,
+}
+
+/// A handle on a pool of buffers. Clones share the same pool, so that a pool
+/// can serve several connections.
+#[derive(Clone, Default)]
+pub(crate) struct BufferPool {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[allow(dead_code)]
+impl BufferPool {
+    pub(crate) fn new() -> Self {
+        return Self::default();
+    }
+
+    /// An empty buffer, which keeps the capacity it had when it was returned
+    pub(crate) fn take(&self) -> PooledBuffer {
+        let mut shared = self.shared.lock().unwrap();
+        let buf = match shared.idle.pop() {
+            Some(buf) => {
+                shared.stats.reused += 1;
+                buf
+            }
+            None => {
+                shared.stats.allocated += 1;
+                vec![]
+            }
+        };
+
+        return PooledBuffer {
+            buf,
+            pool: self.clone(),
+        };
+    }
+
+    /// Keep the buffer for a later take, unless the pool is full. The pool
+    /// may serve other connections, so whatever plaintext the buffer held is
+    /// zeroed, spare capacity included, before anyone else sees it.
+    pub(crate) fn recycle(&self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        buf.resize(buf.capacity(), 0);
+        crypto::zeroize(&mut buf);
+        buf.clear();
+        let mut shared = self.shared.lock().unwrap();
+        if shared.idle.len() < MAX_IDLE_BUFFERS {
+            shared.idle.push(buf);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PoolStats {
+        return self.shared.lock().unwrap().stats;
+    }
+}
+
+/// A buffer on loan from a pool
+pub(crate) struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        return &self.buf;
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        return &mut self.buf;
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.recycle(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recycled_buffer_is_zeroed() {
+        let pool = BufferPool::new();
+        let mut buf = pool.take();
+        buf.extend_from_slice(b"decrypted plaintext");
+        buf.truncate(3);
+        drop(buf);
+
+        let mut buf = pool.take();
+        let capacity = buf.capacity();
+        // Look at the spare capacity, where clear() alone would have left the
+        // bytes. Sound because recycle wrote every byte up to the capacity.
+        unsafe { buf.set_len(capacity) };
+        assert!(buf.iter().all(|byte| *byte == 0));
+        buf.clear();
+    }
+
+    #[test]
+    fn dropped_buffer_is_reused() {
+        let pool = BufferPool::new();
+        let mut buf = pool.take();
+        buf.extend_from_slice(b"scratch");
+        let capacity = buf.capacity();
+        drop(buf);
+
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                allocated: 1,
+                reused: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! The types that most users of the crate need, for a single
+//! `use tls_core::prelude::*`. The list is curated rather than a glob over
+//! the modules, and grows as more of the crate becomes public.
+//!
+//! ```
+//! use tls_core::prelude::*;
+//!
+//! // A fatal handshake_failure alert, followed by the start of the next record
+//! let bytes = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28, 0x16];
+//! let (record, consumed) = TLSPlaintext::parse_with_consumed(&bytes).unwrap();
+//! assert_eq!(consumed, 7);
+//! assert_eq!(record.content_type(), ContentType::Alert);
+//! assert_eq!(record.declared_length(), 2);
+//!
+//! let alert = Alert::try_from(record.fragment()).unwrap();
+//! assert_eq!(alert.level, AlertLevel::Fatal);
+//! assert_eq!(alert.description, AlertDescription::HandshakeFailure);
+//! ```
+pub use crate::alert::{Alert, AlertDescription, AlertLevel};
+pub use crate::constants::{ContentType, ExtensionType, HandshakeType, ProtocolVersion};
+pub use crate::error::TlsError;
+pub use crate::record_layer::{ParseError, TLSPlaintext};
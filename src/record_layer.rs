@@ -1,15 +1,47 @@
 //! TLS Records are the top layer abstraction that are serialized first before
 //! being sent into the TCP stream
-use crate::constants::{ContentType, ProtocolVersion};
+use crate::alert::Alert;
+#[cfg(feature = "record-capture")]
+use crate::capture::RecordCapture;
+use crate::codec::{decode_u16, encode_u16, ParseOutcome};
+use crate::constants::{ContentType, HandshakeType, ProtocolVersion, RECORD_HEADER_LEN};
+use crate::crypto::aead::{TrafficKey, TAG_LENGTH};
+use crate::error::TlsError;
 use crate::fsm::FiniteStateMachine;
+use crate::handshake::{HandshakeJoiner, HandshakeMessage};
+#[cfg(feature = "buffer-pool")]
+use crate::pool::BufferPool;
+use std::io::Read;
+#[cfg(feature = "metrics")]
+use std::time::{Duration, Instant};
 
-const TLS_PLAINTEXT_MAX_LENGTH: u16 = 0b0100000000000000;
+pub(crate) const TLS_PLAINTEXT_MAX_LENGTH: u16 = 0b0100000000000000;
+
+/// A TLSCiphertext may exceed the plaintext limit by the content type byte,
+/// the padding, and the authentication tag, up to 256 bytes in total
+const TLS_CIPHERTEXT_MAX_LENGTH: u16 = TLS_PLAINTEXT_MAX_LENGTH + 256;
+
+/// The shortest body of a protected record: a full tag, sealing at least the
+/// content type byte. TLS 1.3 never truncates tags.
+const TLS_CIPHERTEXT_MIN_LENGTH: u16 = TAG_LENGTH as u16 + 1;
+
+/// How many bytes to ask the reader for at a time; large enough for one
+/// maximally sized record
+const READ_CHUNK_SIZE: usize = RECORD_HEADER_LEN + TLS_CIPHERTEXT_MAX_LENGTH as usize;
+
+/// The inbound buffer holds at most a partial record and one chunk once it is
+/// compacted, and gives back any capacity beyond that
+const BUFFER_CAPACITY_LIMIT: usize = 2 * READ_CHUNK_SIZE;
 
 /// Record is the top layer abstraction that is serialized into the TCP stream
 #[allow(dead_code)]
 enum Record<T> {
     TLSPlaintext(TLSPlaintext<T>),
     TLSCiphertext(TLSCiphertext<T>),
+
+    /// A record that is only passed along, such as by a proxy, and is
+    /// serialized back to exactly the bytes it came from
+    Opaque(RawRecord),
 }
 
 /// Where the payload types can be converted into byte arrays, the record
@@ -22,6 +54,7 @@ where
         match value {
             Record::TLSPlaintext(pt) => pt.into(),
             Record::TLSCiphertext(ct) => ct.into(),
+            Record::Opaque(raw_record) => raw_record.to_bytes(),
         }
     }
 }
@@ -29,7 +62,7 @@ where
 /// TLS Plaintext is sent for negotiating cryptographic parameters, including
 /// ClientHello, HelloRetryRequest, and ServerHello
 #[allow(dead_code)]
-struct TLSPlaintext<Payload> {
+pub struct TLSPlaintext<Payload> {
     content_type: ContentType,
     legacy_record_version: ProtocolVersion,
     length: u16,
@@ -40,12 +73,66 @@ struct TLSPlaintext<Payload> {
     fragment: Payload,
 }
 
+#[allow(dead_code)]
+impl<T: AsRef<[u8]>> TLSPlaintext<T> {
+    /// The content type in the header, which for a protected record is
+    /// always application data
+    pub fn content_type(&self) -> ContentType {
+        return self.content_type;
+    }
+
+    /// The length field, which is only correct if the record was built
+    /// consistently; see validate
+    pub fn declared_length(&self) -> u16 {
+        return self.length;
+    }
+
+    /// Check that the length field matches the fragment and stays within the
+    /// limit that the parser holds inbound records of this type to
+    pub fn validate(&self) -> Result<(), TlsError> {
+        let actual = self.fragment.as_ref().len();
+        if usize::from(self.length) != actual {
+            return Err(TlsError::LengthMismatch {
+                field: "length",
+                declared: usize::from(self.length),
+                actual,
+            });
+        }
+        if decode_length(&self.content_type, self.length).is_none() {
+            return Err(TlsError::DecodeError("record length exceeds the limit"));
+        }
+
+        return Ok(());
+    }
+
+    /// The bytes that the record carries
+    pub fn fragment(&self) -> &[u8] {
+        return self.fragment.as_ref();
+    }
+}
+
+#[allow(dead_code)]
+impl TLSPlaintext<Vec<u8>> {
+    /// Parse the record at the start of the buffer, together with how many
+    /// bytes it occupies (header and fragment), so that a caller with its own
+    /// buffer knows how far to advance it
+    pub fn parse_with_consumed(buf: &[u8]) -> Result<(Self, usize), TlsError> {
+        return match parse_plaintext_prefix(buf) {
+            ParseOutcome::Complete((tls_plaintext, rest)) => {
+                Ok((tls_plaintext, buf.len() - rest.len()))
+            }
+            ParseOutcome::Incomplete => Err(TlsError::DecodeError("truncated TLS record")),
+            ParseOutcome::Error(err) => Err(err),
+        };
+    }
+}
+
 impl<T: Into<Vec<u8>>> From<TLSPlaintext<T>> for Vec<u8> {
     fn from(value: TLSPlaintext<T>) -> Self {
         let mut buf = vec![];
         let content_type: u8 = value.content_type.try_into().unwrap();
         let record_version: [u8; 2] = value.legacy_record_version.try_into().unwrap();
-        let length = value.length.to_be_bytes();
+        let length = encode_u16(value.length);
         let fragment: Vec<u8> = value.fragment.into();
 
         buf.push(content_type);
@@ -57,8 +144,111 @@ impl<T: Into<Vec<u8>>> From<TLSPlaintext<T>> for Vec<u8> {
     }
 }
 
+/// The decoding that mirrors the encoding into Vec<u8>: the bytes must hold
+/// exactly one record, and running out of them fails with what is missing
+impl<'a> TryFrom<&'a [u8]> for TLSPlaintext<Vec<u8>> {
+    type Error = ParseError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let mut parser = TLSPlaintextParser::start(value);
+        while !parser.is_halt() {
+            parser = parser.end_of_input().transition();
+        }
+
+        return match parser {
+            TLSPlaintextParser::Finished { tls_plaintext, .. } => Ok(tls_plaintext),
+            TLSPlaintextParser::Failed { reason } => Err(reason),
+            _ => Err(ParseError::WrongState),
+        };
+    }
+}
+
+impl<T> From<TLSPlaintext<T>> for Record<T> {
+    fn from(value: TLSPlaintext<T>) -> Self {
+        return Self::TLSPlaintext(value);
+    }
+}
+
+impl<T> From<TLSCiphertext<T>> for Record<T> {
+    fn from(value: TLSCiphertext<T>) -> Self {
+        return Self::TLSCiphertext(value);
+    }
+}
+
+impl<T> From<RawRecord> for Record<T> {
+    fn from(value: RawRecord) -> Self {
+        return Self::Opaque(value);
+    }
+}
+
+impl<T> TryFrom<Record<T>> for TLSPlaintext<T> {
+    type Error = TlsError;
+
+    fn try_from(value: Record<T>) -> Result<Self, Self::Error> {
+        return match value {
+            Record::TLSPlaintext(pt) => Ok(pt),
+            Record::TLSCiphertext(_) => {
+                Err(TlsError::UnexpectedMessage("record is a TLSCiphertext"))
+            }
+            Record::Opaque(_) => Err(TlsError::UnexpectedMessage("record is opaque")),
+        };
+    }
+}
+
+impl<T> TryFrom<Record<T>> for TLSCiphertext<T> {
+    type Error = TlsError;
+
+    fn try_from(value: Record<T>) -> Result<Self, Self::Error> {
+        return match value {
+            Record::TLSCiphertext(ct) => Ok(ct),
+            Record::TLSPlaintext(_) => Err(TlsError::UnexpectedMessage("record is a TLSPlaintext")),
+            Record::Opaque(_) => Err(TlsError::UnexpectedMessage("record is opaque")),
+        };
+    }
+}
+
+/// The fragment of a TLSPlaintext interpreted according to its content type
+#[allow(dead_code)]
+#[derive(Debug, Eq, PartialEq)]
+enum Fragment {
+    Handshake(Vec<u8>),
+    Alert(Alert),
+    ApplicationData(Vec<u8>),
+    ChangeCipherSpec,
+}
+
+/// A typed fragment can be serialized back into the bytes it was classified
+/// from, so TLSPlaintext<Fragment> can be sent just like TLSPlaintext<Vec<u8>>
+impl From<Fragment> for Vec<u8> {
+    fn from(value: Fragment) -> Self {
+        match value {
+            Fragment::Handshake(bytes) => bytes,
+            Fragment::Alert(alert) => alert.into(),
+            Fragment::ApplicationData(bytes) => bytes,
+            Fragment::ChangeCipherSpec => vec![0x01],
+        }
+    }
+}
+
+/// Interpret the fragment of a plaintext record according to its content
+/// type. Alerts are decoded into their level and description. The
+/// ChangeCipherSpec fragment is always the single byte 0x01.
+#[allow(dead_code)]
+fn classify(pt: TLSPlaintext<Vec<u8>>) -> Result<Fragment, TlsError> {
+    return match pt.content_type {
+        ContentType::Handshake => Ok(Fragment::Handshake(pt.fragment)),
+        ContentType::Alert => Ok(Fragment::Alert(Alert::try_from(pt.fragment.as_slice())?)),
+        ContentType::ApplicationData => Ok(Fragment::ApplicationData(pt.fragment)),
+        ContentType::ChangeCipherSpec => match pt.fragment.as_slice() {
+            [0x01] => Ok(Fragment::ChangeCipherSpec),
+            _ => Err(TlsError::DecodeError("invalid change_cipher_spec encoding")),
+        },
+        ContentType::Invalid => Err(TlsError::DecodeError("invalid content type")),
+    };
+}
+
 #[allow(dead_code)]
-struct TLSCiphertext<Payload> {
+pub(crate) struct TLSCiphertext<Payload> {
     /// Always set to ContentType::ApplicationData
     opaque_type: ContentType,
 
@@ -77,7 +267,7 @@ impl<T: Into<Vec<u8>>> From<TLSCiphertext<T>> for Vec<u8> {
 
         let record_version: [u8; 2] = value.legacy_record_version.try_into().unwrap();
         buf.extend_from_slice(&record_version);
-        buf.extend_from_slice(&value.length.to_be_bytes());
+        buf.extend_from_slice(&encode_u16(value.length));
         buf.extend_from_slice(&value.encrypted_record.into());
 
         return buf;
@@ -85,7 +275,409 @@ impl<T: Into<Vec<u8>>> From<TLSCiphertext<T>> for Vec<u8> {
 }
 
 #[allow(dead_code)]
-enum TLSPlaintextParser<'a> {
+impl TLSCiphertext<Vec<u8>> {
+    /// The length field of a record sealing an inner plaintext of
+    /// plaintext_len bytes (the content and the content type) with this much
+    /// padding under an AEAD with this tag length. A record longer than
+    /// 2^14 + 256 bytes is a record_overflow.
+    pub(crate) fn compute_length(
+        plaintext_len: usize,
+        padding: usize,
+        tag_len: usize,
+    ) -> Result<u16, TlsError> {
+        let length = plaintext_len + padding + tag_len;
+        if length > usize::from(TLS_CIPHERTEXT_MAX_LENGTH) {
+            return Err(TlsError::RecordOverflow);
+        }
+
+        return Ok(length as u16);
+    }
+}
+
+/// A protected record parses as a TLSPlaintext of type application data
+impl<T> TryFrom<TLSPlaintext<T>> for TLSCiphertext<T> {
+    type Error = TlsError;
+
+    fn try_from(value: TLSPlaintext<T>) -> Result<Self, Self::Error> {
+        if value.content_type != ContentType::ApplicationData {
+            return Err(TlsError::UnexpectedMessage(
+                "protected record is not application data",
+            ));
+        }
+        if value.length < TLS_CIPHERTEXT_MIN_LENGTH {
+            return Err(TlsError::DecodeError(
+                "protected record is too short for the tag and the content type",
+            ));
+        }
+        return Ok(Self {
+            opaque_type: value.content_type,
+            legacy_record_version: value.legacy_record_version,
+            length: value.length,
+            encrypted_record: value.fragment,
+        });
+    }
+}
+
+/// A record kept exactly as it appeared on the wire, for passing records
+/// along without understanding them. Only the length in the header is
+/// interpreted, to find where the body ends; the content type and the version
+/// may hold anything.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct RawRecord {
+    pub(crate) header_bytes: [u8; RECORD_HEADER_LEN],
+    pub(crate) body: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl RawRecord {
+    /// Split the first record off the bytes, returning it with the bytes
+    /// after it
+    pub(crate) fn parse(bytes: &[u8]) -> Result<(Self, &[u8]), TlsError> {
+        if bytes.len() < RECORD_HEADER_LEN {
+            return Err(TlsError::DecodeError("truncated record header"));
+        }
+        let (header, rest) = bytes.split_at(RECORD_HEADER_LEN);
+        let length = usize::from(decode_u16(&header[3..])?.0);
+        if rest.len() < length {
+            return Err(TlsError::DecodeError("truncated record body"));
+        }
+        let (body, rest) = rest.split_at(length);
+        let raw_record = Self {
+            header_bytes: header.try_into().unwrap(),
+            body: body.to_vec(),
+        };
+
+        return Ok((raw_record, rest));
+    }
+
+    /// The exact bytes that the record was parsed from
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        return [self.header_bytes.as_slice(), &self.body].concat();
+    }
+}
+
+/// How many zero bytes to append to the inner plaintext before sealing, to
+/// hide the length of the content from an observer
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum PaddingPolicy {
+    None,
+
+    /// Always append this many bytes
+    Fixed(usize),
+
+    /// Round the inner plaintext (content and content type) up to a multiple
+    /// of this many bytes
+    PadToBlock(usize),
+}
+
+impl PaddingPolicy {
+    /// The padding for an inner plaintext of the given unpadded length. The
+    /// padded length never exceeds the limit of 2^14 + 1 bytes.
+    fn padding_length(&self, unpadded_length: usize) -> usize {
+        let padding = match *self {
+            Self::None => 0,
+            Self::Fixed(length) => length,
+            Self::PadToBlock(0) => 0,
+            Self::PadToBlock(block) => (block - unpadded_length % block) % block,
+        };
+        let limit = TLS_PLAINTEXT_MAX_LENGTH as usize + 1;
+
+        return padding.min(limit.saturating_sub(unpadded_length));
+    }
+}
+
+/// TLSInnerPlaintext is the content, followed by the real content type, followed
+/// by optional zero padding. It is what gets encrypted into a TLSCiphertext.
+/// The buffer is cleared before the inner plaintext is written to it.
+fn encode_inner_plaintext(
+    content_type: ContentType,
+    content: &[u8],
+    padding: &PaddingPolicy,
+    inner: &mut Vec<u8>,
+) {
+    let padding_length = padding.padding_length(content.len() + 1);
+    inner.clear();
+    inner.reserve(content.len() + 1 + padding_length + TAG_LENGTH);
+    inner.extend_from_slice(content);
+    inner.push(content_type.try_into().unwrap());
+    inner.resize(inner.len() + padding_length, 0);
+}
+
+/// The real content type is the last non-zero byte; everything after it is
+/// padding. An inner plaintext of only zeros has no content type at all, but
+/// the content itself may well be empty.
+fn decode_inner_plaintext(inner: &[u8]) -> Result<(ContentType, &[u8]), TlsError> {
+    let type_position = match inner.iter().rposition(|byte| *byte != 0) {
+        Some(position) => position,
+        None => {
+            return Err(TlsError::UnexpectedMessage(
+                "inner plaintext without content type",
+            ))
+        }
+    };
+    let content_type = ContentType::try_from(inner[type_position])
+        .map_err(|_| TlsError::UnexpectedMessage("invalid inner content type"))?;
+
+    return Ok((content_type, &inner[..type_position]));
+}
+
+/// The additional data of the AEAD is the record header of the ciphertext
+fn ciphertext_additional_data(length: u16) -> [u8; RECORD_HEADER_LEN] {
+    let length = encode_u16(length);
+    return [0x17, 0x03, 0x03, length[0], length[1]];
+}
+
+/// Encrypt one record's worth of content under the given key. Splitting larger
+/// content into records is up to the caller.
+#[allow(dead_code)]
+pub(crate) fn seal_record(
+    key: &mut TrafficKey,
+    sequence: u64,
+    content_type: ContentType,
+    content: &[u8],
+    padding: &PaddingPolicy,
+) -> Result<TLSCiphertext<Vec<u8>>, TlsError> {
+    let mut in_out = vec![];
+    let length = seal_record_into(key, sequence, content_type, content, padding, &mut in_out)?;
+
+    return Ok(TLSCiphertext {
+        opaque_type: ContentType::ApplicationData,
+        legacy_record_version: ProtocolVersion::TLSv1_2,
+        length,
+        encrypted_record: in_out,
+    });
+}
+
+/// Like seal_record, but into a buffer that the caller provides, so that the
+/// buffer can be reused across records. Returns the length of the record.
+fn seal_record_into(
+    key: &mut TrafficKey,
+    sequence: u64,
+    content_type: ContentType,
+    content: &[u8],
+    padding: &PaddingPolicy,
+    in_out: &mut Vec<u8>,
+) -> Result<u16, TlsError> {
+    if content.len() > TLS_PLAINTEXT_MAX_LENGTH as usize {
+        return Err(TlsError::DecodeError("record content too large to seal"));
+    }
+    let length = TLSCiphertext::compute_length(
+        content.len() + 1,
+        padding.padding_length(content.len() + 1),
+        TAG_LENGTH,
+    )?;
+    encode_inner_plaintext(content_type, content, padding, in_out);
+    key.seal(sequence, &ciphertext_additional_data(length), in_out)?;
+
+    return Ok(length);
+}
+
+/// Decrypt a record and recover its real content type. A record too short to
+/// hold the tag and the content type is a decode_error, and never reaches the
+/// AEAD.
+#[allow(dead_code)]
+fn open_record(
+    key: &TrafficKey,
+    sequence: u64,
+    ciphertext: TLSCiphertext<Vec<u8>>,
+) -> Result<(ContentType, Vec<u8>), TlsError> {
+    let mut in_out = ciphertext.encrypted_record;
+    let (content_type, content) =
+        open_record_in_place(key, sequence, ciphertext.length, &mut in_out)?;
+
+    return Ok((content_type, content.to_vec()));
+}
+
+/// Like open_record, but decrypting in the caller's buffer, which still holds
+/// the ciphertext afterwards in case the caller wants to reuse it
+fn open_record_in_place<'b>(
+    key: &TrafficKey,
+    sequence: u64,
+    length: u16,
+    in_out: &'b mut [u8],
+) -> Result<(ContentType, &'b [u8]), TlsError> {
+    if in_out.len() < usize::from(TLS_CIPHERTEXT_MIN_LENGTH) {
+        return Err(TlsError::DecodeError(
+            "encrypted record is too short for the tag and the content type",
+        ));
+    }
+    let inner = key.open(sequence, &ciphertext_additional_data(length), in_out)?;
+
+    return decode_inner_plaintext(inner);
+}
+
+/// The inbound side of the protected part of the handshake: records go in
+/// under the handshake read key, whole handshake messages come out, however
+/// the messages are split across records
+#[allow(dead_code)]
+pub(crate) struct EncryptedHandshakeStream {
+    key: TrafficKey,
+    sequence: u64,
+    joiner: HandshakeJoiner,
+}
+
+#[allow(dead_code)]
+impl EncryptedHandshakeStream {
+    pub(crate) fn new(key: TrafficKey) -> Self {
+        return Self {
+            key,
+            sequence: 0,
+            joiner: HandshakeJoiner::new(),
+        };
+    }
+
+    /// Open the next record and buffer its handshake fragment. An alert from
+    /// the peer fails as its error, and any other content is unexpected.
+    pub(crate) fn push_record(&mut self, record: TLSCiphertext<Vec<u8>>) -> Result<(), TlsError> {
+        let (content_type, content) = open_record(&self.key, self.sequence, record)?;
+        self.sequence += 1;
+
+        return match content_type {
+            ContentType::Handshake => {
+                self.joiner.push(&content);
+                Ok(())
+            }
+            ContentType::Alert => Err(TlsError::from(Alert::try_from(content.as_slice())?)),
+            _ => Err(TlsError::UnexpectedMessage(
+                "handshake stream carries a record other than handshake",
+            )),
+        };
+    }
+
+    /// The next whole message, or None until more records are pushed
+    pub(crate) fn next_message(&mut self) -> Result<Option<HandshakeMessage>, TlsError> {
+        return self.joiner.next_message();
+    }
+
+    /// Whether nothing is left over from the records pushed so far
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.joiner.is_empty();
+    }
+}
+
+/// Where a TLSPlaintextParser is, without the data that its state carries
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Phase {
+    ContentType,
+    ProtocolVersion,
+    Length,
+    Content,
+    Finished,
+    Incomplete,
+    Failed,
+}
+
+impl Phase {
+    /// The field of the record that the phase parses, if any
+    fn field(&self) -> Option<&'static str> {
+        return match self {
+            Self::ContentType => Some("content type"),
+            Self::ProtocolVersion => Some("protocol version"),
+            Self::Length => Some("length"),
+            Self::Content => Some("content"),
+            Self::Finished | Self::Incomplete | Self::Failed => None,
+        };
+    }
+}
+
+/// Why a TLSPlaintextParser or a TLSCiphertextParser failed
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParseError {
+    /// The input ended before the content type. Like the next two, this is
+    /// only a failure for a caller that knows that no more input is coming
+    /// (see TLSPlaintextParser::end_of_input); otherwise it is Incomplete.
+    MissingContentType,
+    MissingProtocolVersion,
+    InsufficientLengthBytes,
+    InvalidContentType(u8),
+    InvalidProtocolVersion([u8; 2]),
+
+    /// The length exceeds what a record of its content type may carry
+    LengthOverflow(u16),
+
+    /// The length of a protected record leaves no room for the tag and the
+    /// content type
+    LengthUnderflow(u16),
+
+    /// The content is shorter than the length after the end of the input, or
+    /// longer than it under the strict parse_content_exact
+    ContentLengthMismatch {
+        expected: u16,
+        actual: usize,
+    },
+
+    /// A per-state method was called in a state that it does not handle,
+    /// which only a wrong dispatch on our side can do
+    WrongState,
+}
+
+impl ParseError {
+    /// The phase that the parser failed in, which is None only for a failure
+    /// of our own
+    fn phase(&self) -> Option<Phase> {
+        return match self {
+            Self::MissingContentType | Self::InvalidContentType(_) => Some(Phase::ContentType),
+            Self::MissingProtocolVersion | Self::InvalidProtocolVersion(_) => {
+                Some(Phase::ProtocolVersion)
+            }
+            Self::InsufficientLengthBytes | Self::LengthOverflow(_) | Self::LengthUnderflow(_) => {
+                Some(Phase::Length)
+            }
+            Self::ContentLengthMismatch { .. } => Some(Phase::Content),
+            Self::WrongState => None,
+        };
+    }
+}
+
+/// The error of the reason, wrapped in TlsError::RecordField with the field
+/// of the phase that the parser failed in
+impl From<ParseError> for TlsError {
+    fn from(value: ParseError) -> Self {
+        let error = match value {
+            ParseError::MissingContentType
+            | ParseError::MissingProtocolVersion
+            | ParseError::InsufficientLengthBytes => {
+                TlsError::DecodeError("truncated record header")
+            }
+            // 0x00 is the content type invalid, which RecordHeader::parse
+            // also answers with unexpected_message
+            ParseError::InvalidContentType(0x00) => {
+                TlsError::UnexpectedMessage("record of content type invalid")
+            }
+            ParseError::InvalidContentType(_) => TlsError::DecodeError("invalid content type"),
+            ParseError::InvalidProtocolVersion(_) => TlsError::InvalidProtocolVersion,
+            ParseError::LengthOverflow(_) => TlsError::RecordOverflow,
+            ParseError::LengthUnderflow(_) => TlsError::DecodeError(
+                "protected record is too short for the tag and the content type",
+            ),
+            ParseError::ContentLengthMismatch { expected, actual } => TlsError::LengthMismatch {
+                field: "record",
+                declared: usize::from(expected),
+                actual,
+            },
+            ParseError::WrongState => {
+                TlsError::Internal("record parser method called in the wrong state")
+            }
+        };
+
+        return match value.phase().and_then(|phase| phase.field()) {
+            Some(field) => TlsError::RecordField {
+                field,
+                error: Box::new(error),
+            },
+            None => error,
+        };
+    }
+}
+
+/// The fragment of the finished record is converted from the input bytes into
+/// F: Vec<u8> copies it, while &[u8] borrows it from the input without copying
+#[allow(dead_code)]
+enum TLSPlaintextParser<'a, F = Vec<u8>> {
     ExpectContentType {
         remainder: &'a [u8],
     },
@@ -104,85 +696,209 @@ enum TLSPlaintextParser<'a> {
         length: u16,
         remainder: &'a [u8],
     },
+    /// The record is complete. Whatever follows it in the input is kept in
+    /// rest, which is always empty after the strict parse_content_exact.
     Finished {
-        tls_plaintext: TLSPlaintext<Vec<u8>>,
+        tls_plaintext: TLSPlaintext<F>,
+        rest: &'a [u8],
+    },
+    /// The bytes seen so far are valid, but there are not enough of them to
+    /// finish the record; the caller should read more and try again
+    Incomplete,
+    Failed {
+        reason: ParseError,
     },
-    Failed,
 }
 
 #[allow(dead_code)]
-impl<'a> TLSPlaintextParser<'a> {
+impl<'a, F: From<&'a [u8]>> TLSPlaintextParser<'a, F> {
     /// The finite state machine always start with "ExpectContentType"
     fn start(remainder: &'a [u8]) -> Self {
         return Self::ExpectContentType { remainder };
     }
 
     fn is_failed(&self) -> bool {
-        match self {
-            Self::Failed => true,
-            _ => false,
-        }
+        return matches!(self, Self::Failed { .. });
     }
 
     fn is_finished(&self) -> bool {
-        match self {
-            Self::Finished { tls_plaintext: _ } => true,
-            _ => false,
+        return matches!(self, Self::Finished { .. });
+    }
+
+    fn is_incomplete(&self) -> bool {
+        return matches!(self, Self::Incomplete);
+    }
+
+    fn phase(&self) -> Phase {
+        return match self {
+            Self::ExpectContentType { .. } => Phase::ContentType,
+            Self::ExpectProtocolVersion { .. } => Phase::ProtocolVersion,
+            Self::ExpectLength { .. } => Phase::Length,
+            Self::ExpectContent { .. } => Phase::Content,
+            Self::Finished { .. } => Phase::Finished,
+            Self::Incomplete => Phase::Incomplete,
+            Self::Failed { .. } => Phase::Failed,
+        };
+    }
+
+    /// The input that the parser has not consumed yet: the bytes after the
+    /// record once it is Finished. Incomplete and Failed keep no input.
+    fn remaining(&self) -> &'a [u8] {
+        return match self {
+            Self::ExpectContentType { remainder }
+            | Self::ExpectProtocolVersion { remainder, .. }
+            | Self::ExpectLength { remainder, .. }
+            | Self::ExpectContent { remainder, .. } => remainder,
+            Self::Finished { rest, .. } => rest,
+            Self::Incomplete | Self::Failed { .. } => &[],
+        };
+    }
+
+    /// Which field the parser waits on and how many bytes it has for it, such
+    /// as "waiting for 1 more length byte (have 1)", for debugging a parse
+    /// that does not make progress
+    fn describe_state(&self) -> String {
+        let (needed, remainder) = match self {
+            Self::ExpectContentType { remainder } => (1, remainder),
+            Self::ExpectProtocolVersion { remainder, .. } => (2, remainder),
+            Self::ExpectLength { remainder, .. } => (2, remainder),
+            Self::ExpectContent {
+                length, remainder, ..
+            } => (usize::from(*length), remainder),
+            Self::Finished { rest, .. } => {
+                return format!("finished, with {} bytes left over", rest.len())
+            }
+            Self::Incomplete => return "incomplete, waiting for more input".to_string(),
+            Self::Failed { reason } => return format!("failed: {:?}", reason),
+        };
+        // Every state that is waiting on a field has a phase for it
+        let field = self.phase().field().unwrap_or_default();
+        let missing = needed.saturating_sub(remainder.len());
+        if missing == 0 {
+            return format!("ready to parse the {} (have {})", field, remainder.len());
+        }
+
+        return format!(
+            "waiting for {} more {} byte{} (have {})",
+            missing,
+            field,
+            if missing == 1 { "" } else { "s" },
+            remainder.len()
+        );
+    }
+
+    /// Why the parser stopped, if it is Self::Failed
+    fn failure_reason(&self) -> Option<&ParseError> {
+        return match self {
+            Self::Failed { reason } => Some(reason),
+            _ => None,
+        };
+    }
+
+    /// Advance the parser by one state in place, for callers that would rather
+    /// hold a `&mut` than reassign the parser after every transition. Landing
+    /// in Self::Failed is reported as the error for its reason.
+    fn step(&mut self) -> Result<(), TlsError> {
+        let state = std::mem::replace(self, Self::Incomplete);
+        *self = state.transition();
+        if let Self::Failed { reason } = self {
+            return Err(TlsError::from(*reason));
         }
+
+        return Ok(());
+    }
+
+    /// Tell the parser that the input is all there is, so that a state still
+    /// waiting for bytes fails with what is missing instead of going on to
+    /// Self::Incomplete. A state that has its bytes is left as it is.
+    fn end_of_input(self) -> Self {
+        let reason = match &self {
+            Self::ExpectContentType { remainder: [] } => ParseError::MissingContentType,
+            Self::ExpectProtocolVersion { remainder, .. } if remainder.len() < 2 => {
+                ParseError::MissingProtocolVersion
+            }
+            Self::ExpectLength { remainder, .. } if remainder.len() < 2 => {
+                ParseError::InsufficientLengthBytes
+            }
+            Self::ExpectContent {
+                length, remainder, ..
+            } if remainder.len() < usize::from(*length) => ParseError::ContentLengthMismatch {
+                expected: *length,
+                actual: remainder.len(),
+            },
+            _ => return self,
+        };
+
+        return Self::Failed { reason };
+    }
+
+    fn wrong_state() -> Self {
+        return Self::Failed {
+            reason: ParseError::WrongState,
+        };
     }
 
     /// Attempt to extract the content_type encoding from the remainder of the
     /// received bytes. If there is a valid content_type encoding, return
-    /// Self::ExpectProtocolVersion, otherwise return Self::Failed
+    /// Self::ExpectProtocolVersion; if there are no bytes, return
+    /// Self::Incomplete, otherwise return Self::Failed
     fn parse_content_type(self) -> Self {
         let remainder = match self {
             Self::ExpectContentType { remainder } => remainder,
-            _ => unreachable!(),
+            _ => return Self::wrong_state(),
         };
-        if remainder.len() < 1 {
-            // TODO: Failed because content_type encoding is missing
-            return Self::Failed;
+        if remainder.is_empty() {
+            return Self::Incomplete;
         }
         // Unwrap is ok because there is guaranteed to be at least one byte
-        let encoding = remainder.get(0).unwrap();
-        return match ContentType::try_from(encoding.clone()) {
+        let encoding = remainder.first().unwrap();
+        return match ContentType::try_from(*encoding) {
+            // Decodes, but never appears on the wire
+            Ok(ContentType::Invalid) | Err(_) => Self::Failed {
+                reason: ParseError::InvalidContentType(*encoding),
+            },
             Ok(content_type) => Self::ExpectProtocolVersion {
                 content_type,
                 remainder: &remainder[1..],
             },
-            Err(_) => {
-                // TODO: failed because is encoding is invalid
-                Self::Failed
-            }
         };
     }
 
     /// Attempt to extract the protocol version encoding from the remainder of
-    /// the received bytes. If there is a valid protocol_version encoding,
-    /// return Self::ExpectLength, else return Self.Failed
+    /// the received bytes. If there are fewer than two bytes, return
+    /// Self::Incomplete, else return Self::ExpectLength. Unrecognized versions
+    /// are preserved as ProtocolVersion::Unknown; rejecting them is left to
+    /// the caller (see RecordLayer::strict_versions).
     fn parse_protocol_version(self) -> Self {
         let (content_type, remainder) = match self {
             Self::ExpectProtocolVersion {
                 content_type,
                 remainder,
             } => (content_type, remainder),
-            _ => unreachable!(),
+            _ => return Self::wrong_state(),
         };
 
-        return match ProtocolVersion::try_from(remainder) {
+        if remainder.len() < 2 {
+            return Self::Incomplete;
+        }
+
+        return match ProtocolVersion::from_wire(remainder) {
             Ok(protocol_version) => Self::ExpectLength {
                 content_type,
                 protocol_version,
                 remainder: remainder.get(2..).unwrap(),
             },
-            Err(_) => Self::Failed,
+            Err(_) => Self::Failed {
+                reason: ParseError::InvalidProtocolVersion([remainder[0], remainder[1]]),
+            },
         };
     }
 
     /// Attempt to extract the length encoding (big endian, aka network endian,
     /// aka lower memory address encodes more significant digit) from the
     /// remaining bytes. If there is a valid length, return
-    /// Self::ExpectContent, else return Self::Failed
+    /// Self::ExpectContent; if there are fewer than two bytes, return
+    /// Self::Incomplete, else return Self::Failed
     fn parse_length(self) -> Self {
         let (content_type, protocol_version, remainder) = match self {
             Self::ExpectLength {
@@ -190,33 +906,54 @@ impl<'a> TLSPlaintextParser<'a> {
                 protocol_version,
                 remainder,
             } => (content_type, protocol_version, remainder),
-            _ => unreachable!(),
+            _ => return Self::wrong_state(),
         };
 
-        if remainder.len() < 2 {
-            // TODO: Failed due to insufficient bytes
-            return Self::Failed;
-        }
-
-        let mut length_encoding: [u8; 2] = [0; 2];
-        // Unwrapping is okay because length is guaranteed
-        length_encoding.copy_from_slice(remainder.get(0..2).unwrap());
-        let length = u16::from_be_bytes(length_encoding);
-        if length > TLS_PLAINTEXT_MAX_LENGTH {
-            // TODO: Failed due to length overflow
-            return Self::Failed;
-        }
+        let (length, remainder) = match decode_u16(remainder) {
+            Ok(decoded) => decoded,
+            Err(_) => return Self::Incomplete,
+        };
+        let length = match decode_length(&content_type, length) {
+            Some(length) => length,
+            None => {
+                return Self::Failed {
+                    reason: ParseError::LengthOverflow(length),
+                }
+            }
+        };
 
         return Self::ExpectContent {
             content_type,
             protocol_version,
             length,
-            remainder: remainder.get(2..).unwrap(),
+            remainder,
         };
     }
 
-    /// Attempt to parse the content according to the previously parsed length
-    fn parse_content(self) -> Self {
+    /// Attempt to parse the content according to the previously parsed length.
+    /// Fewer bytes than the length is Self::Incomplete, more bytes than the
+    /// length is Self::Failed. This is the strict parse for input that holds
+    /// exactly one record.
+    fn parse_content_exact(self) -> Self {
+        return match self.parse_content_prefix() {
+            Self::Finished {
+                tls_plaintext,
+                rest,
+            } if !rest.is_empty() => Self::Failed {
+                reason: ParseError::ContentLengthMismatch {
+                    expected: tls_plaintext.length,
+                    actual: usize::from(tls_plaintext.length) + rest.len(),
+                },
+            },
+            parser => parser,
+        };
+    }
+
+    /// Attempt to parse the content according to the previously parsed length,
+    /// consuming exactly that many bytes. Fewer bytes than the length is
+    /// Self::Incomplete; any bytes after the content are left in the rest for
+    /// the next record.
+    fn parse_content_prefix(self) -> Self {
         let (content_type, legacy_record_version, length, remainder) = match self {
             Self::ExpectContent {
                 content_type,
@@ -224,46 +961,931 @@ impl<'a> TLSPlaintextParser<'a> {
                 length,
                 remainder,
             } => (content_type, protocol_version, length, remainder),
-            _ => unreachable!(),
+            _ => return Self::wrong_state(),
         };
 
-        if remainder.len() != usize::from(length) {
-            return Self::Failed;
+        if remainder.len() < usize::from(length) {
+            return Self::Incomplete;
         }
-        let fragment: Vec<u8> = remainder.into();
+        let (fragment, rest) = remainder.split_at(usize::from(length));
         let tls_plaintext = TLSPlaintext {
             content_type,
             legacy_record_version,
             length,
-            fragment,
+            fragment: F::from(fragment),
         };
 
-        return Self::Finished { tls_plaintext };
+        return Self::Finished {
+            tls_plaintext,
+            rest,
+        };
     }
 }
 
-impl<'a> FiniteStateMachine for TLSPlaintextParser<'a> {
+impl<'a, F: From<&'a [u8]>> FiniteStateMachine for TLSPlaintextParser<'a, F> {
     type State = Self;
 
-    fn transition(self: Self) -> Self {
+    fn transition(self) -> Self {
         match self {
             Self::ExpectContentType { .. } => self.parse_content_type(),
             Self::ExpectProtocolVersion { .. } => self.parse_protocol_version(),
             Self::ExpectLength { .. } => self.parse_length(),
-            Self::ExpectContent { .. } => self.parse_content(),
-            Self::Failed => self,
+            Self::ExpectContent { .. } => self.parse_content_exact(),
+            Self::Failed { .. } => self,
+            Self::Incomplete => self,
             Self::Finished { .. } => self,
         }
     }
 
-    fn is_halt(self: &Self) -> bool {
-        return self.is_failed() || self.is_finished();
+    fn is_halt(&self) -> bool {
+        return self.is_failed() || self.is_finished() || self.is_incomplete();
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// The counterpart of TLSPlaintextParser for protected records, which always
+/// claim to be application data under TLS 1.2 (RFC 8446, section 5.2). The
+/// input must hold exactly one record.
+#[allow(dead_code)]
+enum TLSCiphertextParser<'a> {
+    ExpectOpaqueType {
+        remainder: &'a [u8],
+    },
+    ExpectLegacyVersion {
+        remainder: &'a [u8],
+    },
+    ExpectLength {
+        remainder: &'a [u8],
+    },
+    ExpectEncryptedRecord {
+        length: u16,
+        remainder: &'a [u8],
+    },
+    Finished {
+        ciphertext: TLSCiphertext<Vec<u8>>,
+    },
+    /// The bytes seen so far are valid, but there are not enough of them to
+    /// finish the record
+    Incomplete,
+    Failed {
+        reason: ParseError,
+    },
+}
+
+#[allow(dead_code)]
+impl<'a> TLSCiphertextParser<'a> {
+    fn start(remainder: &'a [u8]) -> Self {
+        return Self::ExpectOpaqueType { remainder };
+    }
+
+    fn is_failed(&self) -> bool {
+        return matches!(self, Self::Failed { .. });
+    }
+
+    fn is_finished(&self) -> bool {
+        return matches!(self, Self::Finished { .. });
+    }
+
+    fn is_incomplete(&self) -> bool {
+        return matches!(self, Self::Incomplete);
+    }
+
+    fn failure_reason(&self) -> Option<&ParseError> {
+        return match self {
+            Self::Failed { reason } => Some(reason),
+            _ => None,
+        };
+    }
+
+    /// Anything but application data is not a protected record
+    fn parse_opaque_type(self) -> Self {
+        let remainder = match self {
+            Self::ExpectOpaqueType { remainder } => remainder,
+            _ => return Self::wrong_state(),
+        };
+        let (encoding, remainder) = match remainder.split_first() {
+            Some(split) => split,
+            None => return Self::Incomplete,
+        };
+
+        return match ContentType::try_from(*encoding) {
+            Ok(ContentType::ApplicationData) => Self::ExpectLegacyVersion { remainder },
+            _ => Self::Failed {
+                reason: ParseError::InvalidContentType(*encoding),
+            },
+        };
+    }
+
+    /// Anything but TLS 1.2 is not a protected TLS 1.3 record
+    fn parse_legacy_version(self) -> Self {
+        let remainder = match self {
+            Self::ExpectLegacyVersion { remainder } => remainder,
+            _ => return Self::wrong_state(),
+        };
+        if remainder.len() < 2 {
+            return Self::Incomplete;
+        }
+        let (encoding, remainder) = remainder.split_at(2);
+
+        return match ProtocolVersion::from_wire(encoding) {
+            Ok(ProtocolVersion::TLSv1_2) => Self::ExpectLength { remainder },
+            _ => Self::Failed {
+                reason: ParseError::InvalidProtocolVersion([encoding[0], encoding[1]]),
+            },
+        };
+    }
+
+    /// The length may exceed the plaintext limit by the ciphertext expansion,
+    /// and must leave room for the tag and the content type
+    fn parse_length(self) -> Self {
+        let remainder = match self {
+            Self::ExpectLength { remainder } => remainder,
+            _ => return Self::wrong_state(),
+        };
+        let (length, remainder) = match decode_u16(remainder) {
+            Ok(decoded) => decoded,
+            Err(_) => return Self::Incomplete,
+        };
+        if length > TLS_CIPHERTEXT_MAX_LENGTH {
+            return Self::Failed {
+                reason: ParseError::LengthOverflow(length),
+            };
+        }
+        if length < TLS_CIPHERTEXT_MIN_LENGTH {
+            return Self::Failed {
+                reason: ParseError::LengthUnderflow(length),
+            };
+        }
+
+        return Self::ExpectEncryptedRecord { length, remainder };
+    }
+
+    /// Fewer bytes than the length is Self::Incomplete, more is Self::Failed
+    fn parse_encrypted_record(self) -> Self {
+        let (length, remainder) = match self {
+            Self::ExpectEncryptedRecord { length, remainder } => (length, remainder),
+            _ => return Self::wrong_state(),
+        };
+        if remainder.len() < usize::from(length) {
+            return Self::Incomplete;
+        }
+        if remainder.len() > usize::from(length) {
+            return Self::Failed {
+                reason: ParseError::ContentLengthMismatch {
+                    expected: length,
+                    actual: remainder.len(),
+                },
+            };
+        }
+        let ciphertext = TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length,
+            encrypted_record: remainder.to_vec(),
+        };
+
+        return Self::Finished { ciphertext };
+    }
+
+    fn wrong_state() -> Self {
+        return Self::Failed {
+            reason: ParseError::WrongState,
+        };
+    }
+}
+
+impl<'a> FiniteStateMachine for TLSCiphertextParser<'a> {
+    type State = Self;
+
+    fn transition(self) -> Self {
+        match self {
+            Self::ExpectOpaqueType { .. } => self.parse_opaque_type(),
+            Self::ExpectLegacyVersion { .. } => self.parse_legacy_version(),
+            Self::ExpectLength { .. } => self.parse_length(),
+            Self::ExpectEncryptedRecord { .. } => self.parse_encrypted_record(),
+            Self::Failed { .. } => self,
+            Self::Incomplete => self,
+            Self::Finished { .. } => self,
+        }
+    }
+
+    fn is_halt(&self) -> bool {
+        return self.is_failed() || self.is_finished() || self.is_incomplete();
+    }
+}
+
+/// Check the length field of a record header, returning None if the length
+/// exceeds what the record may carry. Protected records all look like
+/// application data on the wire, and are allowed the ciphertext limit.
+fn decode_length(content_type: &ContentType, length: u16) -> Option<u16> {
+    let max_length = match content_type {
+        ContentType::ApplicationData => TLS_CIPHERTEXT_MAX_LENGTH,
+        _ => TLS_PLAINTEXT_MAX_LENGTH,
+    };
+    if length > max_length {
+        return None;
+    }
+
+    return Some(length);
+}
+
+/// Whether the bytes could be the start of a TLS stream, such as for a server
+/// that takes both plain HTTP and TLS on one port: a record header with a
+/// content type that appears on the wire, a 0x03XX version up to TLS 1.3, and
+/// a non-zero length within the limit. Fewer bytes than a header never look
+/// like TLS.
+#[allow(dead_code)]
+pub(crate) fn sniff_tls(first_bytes: &[u8]) -> bool {
+    let (content_type, version, length) = match first_bytes {
+        [content_type, major, minor, length_high, length_low, ..] => (
+            *content_type,
+            [*major, *minor],
+            u16::from_be_bytes([*length_high, *length_low]),
+        ),
+        _ => return false,
+    };
+    let content_type = match ContentType::try_from(content_type) {
+        Ok(ContentType::Invalid) | Err(_) => return false,
+        Ok(content_type) => content_type,
+    };
+
+    return version[0] == 0x03
+        && version[1] <= 0x04
+        && length > 0
+        && decode_length(&content_type, length).is_some();
+}
+
+/// The fixed five bytes that start every record. Parsing the header alone is
+/// enough to know how many more bytes to wait for before the record can be
+/// parsed in full.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct RecordHeader {
+    pub(crate) content_type: ContentType,
+    pub(crate) version: ProtocolVersion,
+    pub(crate) length: u16,
+}
+
+#[allow(dead_code)]
+impl RecordHeader {
+    fn parse(header: &[u8; RECORD_HEADER_LEN]) -> Result<Self, TlsError> {
+        let content_type = ContentType::try_from(header[0])
+            .map_err(|_| TlsError::DecodeError("invalid content type"))?;
+        if content_type == ContentType::Invalid {
+            return Err(TlsError::UnexpectedMessage(
+                "record of content type invalid",
+            ));
+        }
+        let version = ProtocolVersion::from_wire(&header[1..3])?;
+        let length = decode_length(&content_type, decode_u16(&header[3..])?.0)
+            .ok_or(TlsError::RecordOverflow)?;
+
+        return Ok(Self {
+            content_type,
+            version,
+            length,
+        });
+    }
+
+    /// The length of the whole record, header included
+    fn record_length(&self) -> usize {
+        return RECORD_HEADER_LEN + usize::from(self.length);
+    }
+}
+
+/// Check that the bytes start with a valid record header and that the whole
+/// body follows it, returning the length of the record, header included. The
+/// fragment is neither copied nor interpreted, which is all that a middlebox
+/// needs before forwarding the record.
+#[allow(dead_code)]
+pub(crate) fn validate_record_framing(buf: &[u8]) -> Result<usize, TlsError> {
+    let header = buf
+        .first_chunk::<RECORD_HEADER_LEN>()
+        .ok_or(TlsError::DecodeError("truncated record header"))?;
+    let record_length = RecordHeader::parse(header)?.record_length();
+    if buf.len() < record_length {
+        return Err(TlsError::DecodeError("truncated record body"));
+    }
+
+    return Ok(record_length);
+}
+
+/// The plaintext records of a flight of handshake messages. Messages share a
+/// record as long as they fit, and a message that does not fit is split
+/// across records.
+#[allow(dead_code)]
+pub(crate) fn serialize_flight(messages: &[HandshakeMessage]) -> Vec<TLSPlaintext<Vec<u8>>> {
+    let mut encoding = vec![];
+    for message in messages {
+        encoding.extend_from_slice(&message.to_bytes());
+    }
+
+    return encoding
+        .chunks(TLS_PLAINTEXT_MAX_LENGTH as usize)
+        .map(|fragment| TLSPlaintext {
+            content_type: ContentType::Handshake,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: fragment.len() as u16,
+            fragment: fragment.to_vec(),
+        })
+        .collect();
+}
+
+/// Drive a TLSPlaintextParser over the bytes of exactly one record
+#[allow(dead_code)]
+fn parse_plaintext(bytes: &[u8]) -> ParseOutcome<TLSPlaintext<Vec<u8>>> {
+    let mut parser = TLSPlaintextParser::start(bytes);
+    while !parser.is_halt() {
+        parser = parser.transition();
+    }
+
+    return match parser {
+        TLSPlaintextParser::Finished { tls_plaintext, .. } => ParseOutcome::Complete(tls_plaintext),
+        TLSPlaintextParser::Incomplete => ParseOutcome::Incomplete,
+        TLSPlaintextParser::Failed { reason } => ParseOutcome::Error(reason.into()),
+        _ => ParseOutcome::Error(TlsError::Internal("TLSPlaintextParser halted mid-record")),
+    };
+}
+
+/// A one-line summary of a record for humans: its type, legacy version, and
+/// length, followed by the type of the first handshake message or the alert
+/// that it carries
+pub(crate) fn describe_record(record: &TLSPlaintext<Vec<u8>>) -> String {
+    let mut description = format!(
+        "{:?} record, legacy version {:?}, {} bytes",
+        record.content_type, record.legacy_record_version, record.length
+    );
+    match record.content_type {
+        ContentType::Handshake if !record.fragment.is_empty() => {
+            let msg_type = HandshakeType::from(record.fragment[0]);
+            description.push_str(&format!(": {:?}", msg_type));
+        }
+        ContentType::Alert => {
+            if let Ok(alert) = Alert::try_from(record.fragment.as_slice()) {
+                description.push_str(&format!(": {:?} {:?}", alert.level, alert.description));
+            }
+        }
+        _ => {}
+    }
+
+    return description;
+}
+
+/// Parse the bytes as exactly one record and describe it
+pub(crate) fn inspect_record(bytes: &[u8]) -> Result<String, TlsError> {
+    return match parse_plaintext(bytes) {
+        ParseOutcome::Complete(record) => Ok(describe_record(&record)),
+        ParseOutcome::Incomplete => Err(TlsError::DecodeError("incomplete TLS record")),
+        ParseOutcome::Error(err) => Err(err),
+    };
+}
+
+/// Drive a TLSPlaintextParser over the start of a stream of records, returning
+/// the first record and the bytes after it
+#[allow(dead_code)]
+fn parse_plaintext_prefix(bytes: &[u8]) -> ParseOutcome<(TLSPlaintext<Vec<u8>>, &[u8])> {
+    let mut parser = TLSPlaintextParser::start(bytes);
+    while !parser.is_halt() {
+        parser = match parser {
+            TLSPlaintextParser::ExpectContent { .. } => parser.parse_content_prefix(),
+            parser => parser.transition(),
+        };
+    }
+
+    return match parser {
+        TLSPlaintextParser::Finished {
+            tls_plaintext,
+            rest,
+        } => ParseOutcome::Complete((tls_plaintext, rest)),
+        TLSPlaintextParser::Incomplete => ParseOutcome::Incomplete,
+        TLSPlaintextParser::Failed { reason } => ParseOutcome::Error(reason.into()),
+        _ => ParseOutcome::Error(TlsError::Internal("TLSPlaintextParser halted mid-record")),
+    };
+}
+
+/// Parse every record in the bytes, such as a whole flight that a server sent
+/// in one write, feeding the rest after each record back into the parser. The
+/// bytes must end at a record boundary: a partial record at the end fails with
+/// what it is missing.
+#[allow(dead_code)]
+fn parse_all(mut bytes: &[u8]) -> Result<Vec<TLSPlaintext<Vec<u8>>>, ParseError> {
+    let mut records = vec![];
+    while !bytes.is_empty() {
+        let mut parser = TLSPlaintextParser::start(bytes);
+        while !parser.is_halt() {
+            parser = match parser.end_of_input() {
+                parser @ TLSPlaintextParser::ExpectContent { .. } => parser.parse_content_prefix(),
+                parser => parser.transition(),
+            };
+        }
+        match parser {
+            TLSPlaintextParser::Finished {
+                tls_plaintext,
+                rest,
+            } => {
+                records.push(tls_plaintext);
+                bytes = rest;
+            }
+            TLSPlaintextParser::Failed { reason } => return Err(reason),
+            _ => return Err(ParseError::WrongState),
+        }
+    }
+
+    return Ok(records);
+}
+
+/// A snapshot of the counters of a record layer
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) struct Metrics {
+    /// Inbound records taken out of the buffer, protected or not
+    pub(crate) records_parsed: u64,
+
+    /// The length of every inbound record that was opened, tag included
+    pub(crate) bytes_decrypted: u64,
+
+    pub(crate) open_time: Duration,
+    pub(crate) seal_time: Duration,
+}
+
+/// Which keys protect the records in one direction: none until ServerHello,
+/// then the handshake keys, then the application keys. Each epoch starts
+/// its sequence number over at zero (RFC 8446, section 5.3).
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Epoch {
+    Plaintext,
+    Handshake,
+    Application,
+}
+
+/// The read key that a KeyUpdate replaced, kept for the records that the peer
+/// sealed under it but that arrive after the records under its successor
+struct RetiredKey {
+    key: TrafficKey,
+    sequence: u64,
+
+    /// How many more records it may open
+    remaining: u64,
+}
+
+/// The glue between a socket and the parser. Bytes read from the socket are
+/// buffered until they form complete records, so the caller does not need to
+/// care that a single read may return several records, or only part of one.
+///
+/// Once keys are installed, the record layer also protects outbound records
+/// and unprotects inbound ones, keeping one epoch and one sequence number per
+/// direction.
+#[allow(dead_code)]
+pub(crate) struct RecordLayer {
+    /// Inbound bytes; the records before consumed have been taken already and
+    /// are dropped when the buffer is compacted
+    buffer: Vec<u8>,
+    consumed: usize,
+
+    /// When set, records whose legacy_record_version is not a known version
+    /// are rejected instead of being passed along as ProtocolVersion::Unknown
+    strict_versions: bool,
+
+    read_epoch: Epoch,
+    read_key: Option<TrafficKey>,
+    read_sequence: u64,
+    write_epoch: Epoch,
+    write_key: Option<TrafficKey>,
+    write_sequence: u64,
+
+    /// After a KeyUpdate, up to this many records that fail under the new
+    /// read key are tried under the old one. Zero keeps no old key.
+    key_update_window: u64,
+    retired_read_key: Option<RetiredKey>,
+
+    /// Applied to every sealed record
+    padding: PaddingPolicy,
+
+    #[cfg(feature = "record-capture")]
+    capture: Option<RecordCapture>,
+
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+
+    /// Where the scratch buffers of sealing and parsing come from
+    #[cfg(feature = "buffer-pool")]
+    pool: BufferPool,
+}
+
+#[allow(dead_code)]
+impl RecordLayer {
+    pub(crate) fn new() -> Self {
+        return Self {
+            buffer: vec![],
+            consumed: 0,
+            strict_versions: false,
+            read_epoch: Epoch::Plaintext,
+            read_key: None,
+            read_sequence: 0,
+            write_epoch: Epoch::Plaintext,
+            write_key: None,
+            write_sequence: 0,
+            key_update_window: 0,
+            retired_read_key: None,
+            padding: PaddingPolicy::None,
+            #[cfg(feature = "record-capture")]
+            capture: None,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            #[cfg(feature = "buffer-pool")]
+            pool: BufferPool::new(),
+        };
+    }
+
+    /// Drop the keys, the sequence numbers, and anything buffered, as for a
+    /// new connection. The padding policy, the capture, the metrics, and the
+    /// buffer pool stay.
+    pub(crate) fn reset(&mut self) {
+        self.buffer.clear();
+        self.consumed = 0;
+        self.read_epoch = Epoch::Plaintext;
+        self.read_key = None;
+        self.read_sequence = 0;
+        self.write_epoch = Epoch::Plaintext;
+        self.write_key = None;
+        self.write_sequence = 0;
+        self.retired_read_key = None;
+    }
+
+    /// Enter the epoch of the key, which protects every inbound record from
+    /// now on; the sequence number restarts at zero with each new key. A key
+    /// that replaces an application key is a KeyUpdate, after which the old
+    /// key is kept if there is a window for it.
+    pub(crate) fn set_read_key(&mut self, epoch: Epoch, key: TrafficKey) {
+        let is_key_update = self.read_epoch == Epoch::Application && epoch == Epoch::Application;
+        let old_key = self.read_key.replace(key);
+        self.retired_read_key = match old_key {
+            Some(old_key) if is_key_update && self.key_update_window > 0 => Some(RetiredKey {
+                key: old_key,
+                sequence: self.read_sequence,
+                remaining: self.key_update_window,
+            }),
+            _ => None,
+        };
+        self.read_epoch = epoch;
+        self.read_sequence = 0;
+    }
+
+    /// Tolerate up to this many records under the old read key after each
+    /// KeyUpdate, for a peer whose records are reordered around it
+    pub(crate) fn set_key_update_window(&mut self, records: u64) {
+        self.key_update_window = records;
+    }
+
+    /// Enter the epoch of the key, which protects every outbound record from
+    /// now on; the sequence number restarts at zero with each new key
+    pub(crate) fn set_write_key(&mut self, epoch: Epoch, key: TrafficKey) {
+        self.write_epoch = epoch;
+        self.write_key = Some(key);
+        self.write_sequence = 0;
+    }
+
+    /// The epoch of the next record to be opened
+    pub(crate) fn read_epoch(&self) -> Epoch {
+        return self.read_epoch;
+    }
+
+    /// The epoch of the next record to be sealed
+    pub(crate) fn write_epoch(&self) -> Epoch {
+        return self.write_epoch;
+    }
+
+    /// How many records the current write key has sealed
+    pub(crate) fn write_sequence(&self) -> u64 {
+        return self.write_sequence;
+    }
+
+    pub(crate) fn set_padding_policy(&mut self, padding: PaddingPolicy) {
+        self.padding = padding;
+    }
+
+    /// Write every record to the capture from now on, whether it is written
+    /// by write_records or taken by take_records
+    #[cfg(feature = "record-capture")]
+    pub(crate) fn set_capture(&mut self, capture: RecordCapture) {
+        self.capture = Some(capture);
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> Metrics {
+        return self.metrics;
+    }
+
+    /// Draw scratch buffers from the pool, which may be shared with other
+    /// record layers, instead of from a pool of this record layer's own
+    #[cfg(feature = "buffer-pool")]
+    pub(crate) fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.pool = pool;
+    }
+
+    #[cfg(feature = "buffer-pool")]
+    pub(crate) fn buffer_pool(&self) -> &BufferPool {
+        return &self.pool;
+    }
+
+    /// Split the content into as many records as needed and serialize them,
+    /// sealing each one if a write key is installed
+    pub(crate) fn write_records(
+        &mut self,
+        content_type: ContentType,
+        content: &[u8],
+    ) -> Result<Vec<u8>, TlsError> {
+        let mut buf = vec![];
+        for fragment in content.chunks(TLS_PLAINTEXT_MAX_LENGTH as usize) {
+            #[cfg(feature = "record-capture")]
+            let start_of_record = buf.len();
+            match self.write_key.as_mut() {
+                Some(key) => {
+                    #[cfg(feature = "metrics")]
+                    let start = Instant::now();
+                    #[cfg(feature = "buffer-pool")]
+                    let mut in_out = self.pool.take();
+                    #[cfg(not(feature = "buffer-pool"))]
+                    let mut in_out = vec![];
+                    let length = seal_record_into(
+                        key,
+                        self.write_sequence,
+                        content_type,
+                        fragment,
+                        &self.padding,
+                        &mut in_out,
+                    )?;
+                    #[cfg(feature = "metrics")]
+                    {
+                        self.metrics.seal_time += start.elapsed();
+                    }
+                    self.write_sequence += 1;
+                    buf.extend_from_slice(&ciphertext_additional_data(length));
+                    buf.extend_from_slice(&in_out);
+                }
+                None => buf.extend_from_slice(&Vec::from(TLSPlaintext {
+                    content_type,
+                    legacy_record_version: ProtocolVersion::TLSv1_2,
+                    length: fragment.len() as u16,
+                    fragment,
+                })),
+            };
+            #[cfg(feature = "record-capture")]
+            if let Some(capture) = self.capture.as_mut() {
+                capture.write_record(&buf[start_of_record..])?;
+            }
+        }
+
+        return Ok(buf);
+    }
+
+    /// Recover the real content type and the content of an inbound record,
+    /// opening it first if a read key is installed. Only application data
+    /// records are protected; anything else passes through as-is. Before
+    /// there is a read key, a record whose type may not travel unprotected is
+    /// an unexpected_message.
+    pub(crate) fn unprotect(
+        &mut self,
+        record: TLSPlaintext<Vec<u8>>,
+    ) -> Result<(ContentType, Vec<u8>), TlsError> {
+        match (self.read_key.is_some(), &record.content_type) {
+            (true, ContentType::ApplicationData) => {}
+            (false, content_type) if !content_type.allowed_plaintext() => {
+                return Err(TlsError::UnexpectedMessage(
+                    "content type not allowed in plaintext",
+                ));
+            }
+            _ => return Ok((record.content_type, record.fragment)),
+        };
+
+        return self.open_owned(TLSCiphertext::try_from(record)?);
+    }
+
+    /// Open a protected record under the read key, like unprotect does for
+    /// the application data records that it is given
+    pub(crate) fn open(
+        &mut self,
+        ciphertext: &TLSCiphertext<Vec<u8>>,
+    ) -> Result<(ContentType, Vec<u8>), TlsError> {
+        return self.open_owned(TLSCiphertext {
+            opaque_type: ciphertext.opaque_type,
+            legacy_record_version: ciphertext.legacy_record_version,
+            length: ciphertext.length,
+            encrypted_record: ciphertext.encrypted_record.clone(),
+        });
+    }
+
+    fn open_owned(
+        &mut self,
+        ciphertext: TLSCiphertext<Vec<u8>>,
+    ) -> Result<(ContentType, Vec<u8>), TlsError> {
+        let key = self.read_key.as_ref().ok_or(TlsError::UnexpectedMessage(
+            "protected record without a read key",
+        ))?;
+        // Opening may scramble the buffer when it fails, so the record is
+        // copied first if there is an old key to try next
+        let fallback = match &self.retired_read_key {
+            Some(retired) if retired.remaining > 0 => {
+                Some((ciphertext.length, ciphertext.encrypted_record.clone()))
+            }
+            _ => None,
+        };
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        #[cfg(feature = "metrics")]
+        let length = u64::from(ciphertext.length);
+        #[cfg(not(feature = "buffer-pool"))]
+        let result = open_record(key, self.read_sequence, ciphertext);
+        #[cfg(feature = "buffer-pool")]
+        let result = {
+            // The content is copied out, so the ciphertext's buffer can go
+            // back to the pool
+            let mut in_out = ciphertext.encrypted_record;
+            let result =
+                open_record_in_place(key, self.read_sequence, ciphertext.length, &mut in_out)
+                    .map(|(content_type, content)| (content_type, content.to_vec()));
+            self.pool.recycle(in_out);
+            result
+        };
+        let opened = match (result, fallback) {
+            (Ok(opened), _) => {
+                self.read_sequence += 1;
+                opened
+            }
+            (Err(TlsError::BadRecordMac), Some((length, mut in_out))) => {
+                let retired = self.retired_read_key.as_mut().unwrap();
+                let (content_type, content) =
+                    open_record_in_place(&retired.key, retired.sequence, length, &mut in_out)?;
+                retired.sequence += 1;
+                retired.remaining -= 1;
+                (content_type, content.to_vec())
+            }
+            (Err(err), _) => return Err(err),
+        };
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.open_time += start.elapsed();
+            self.metrics.bytes_decrypted += length;
+        }
+
+        return Ok(opened);
+    }
+
+    /// Read once from the reader, then return every complete record that is
+    /// now buffered. A partial record at the end of the buffer is kept for the
+    /// next call.
+    pub(crate) fn read_from(
+        &mut self,
+        r: &mut impl Read,
+    ) -> Result<Vec<TLSPlaintext<Vec<u8>>>, TlsError> {
+        self.read_chunk(r)?;
+
+        return self.take_records();
+    }
+
+    /// Read once from the reader into the buffer, returning the number of
+    /// bytes read; zero means the reader has reached its end
+    pub(crate) fn read_chunk(&mut self, r: &mut impl Read) -> Result<usize, TlsError> {
+        self.compact();
+        let length = self.buffer.len();
+        self.buffer.reserve_exact(READ_CHUNK_SIZE);
+        self.buffer.resize(length + READ_CHUNK_SIZE, 0);
+        let nbytes = match r.read(&mut self.buffer[length..]) {
+            Ok(nbytes) => nbytes,
+            Err(err) => {
+                self.buffer.truncate(length);
+                return Err(err.into());
+            }
+        };
+        self.buffer.truncate(length + nbytes);
+
+        return Ok(nbytes);
+    }
+
+    /// Drop the records that have been taken from the front of the buffer,
+    /// and shrink the buffer if an earlier burst grew it past the limit
+    fn compact(&mut self) {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+        if self.buffer.capacity() > BUFFER_CAPACITY_LIMIT {
+            self.buffer.shrink_to(BUFFER_CAPACITY_LIMIT);
+        }
+    }
+
+    /// Remove every complete record from the buffer, leaving a partial record
+    /// at the end of the buffer in place
+    pub(crate) fn take_records(&mut self) -> Result<Vec<TLSPlaintext<Vec<u8>>>, TlsError> {
+        let mut records = vec![];
+        while let Some(record) = self.take_record()? {
+            records.push(record);
+        }
+        self.compact();
+
+        return Ok(records);
+    }
+
+    /// Read until a whole record is buffered, then return that one record.
+    /// The reader ending at a record boundary is a clean close and yields
+    /// None; ending in the middle of a record is UnexpectedEof.
+    pub(crate) fn read_one_record(
+        &mut self,
+        r: &mut impl Read,
+    ) -> Result<Option<TLSPlaintext<Vec<u8>>>, TlsError> {
+        loop {
+            if let Some(record) = self.take_record()? {
+                return Ok(Some(record));
+            }
+            if self.read_chunk(r)? == 0 {
+                if self.buffer.len() == self.consumed {
+                    return Ok(None);
+                }
+                return Err(TlsError::UnexpectedEof);
+            }
+        }
+    }
+
+    /// Remove the first record from the buffer if it is complete
+    fn take_record(&mut self) -> Result<Option<TLSPlaintext<Vec<u8>>>, TlsError> {
+        let record_length = match self.complete_record_length(&self.buffer[self.consumed..])? {
+            Some(record_length) => record_length,
+            None => return Ok(None),
+        };
+        #[cfg(feature = "buffer-pool")]
+        let mut record_bytes = self.pool.take();
+        #[cfg(not(feature = "buffer-pool"))]
+        let mut record_bytes = vec![];
+        record_bytes.extend_from_slice(&self.buffer[self.consumed..self.consumed + record_length]);
+        self.consumed += record_length;
+        #[cfg(feature = "record-capture")]
+        if let Some(capture) = self.capture.as_mut() {
+            capture.write_record(&record_bytes)?;
+        }
+        return match parse_plaintext(&record_bytes) {
+            ParseOutcome::Complete(tls_plaintext) => {
+                #[cfg(feature = "metrics")]
+                {
+                    self.metrics.records_parsed += 1;
+                }
+                Ok(Some(tls_plaintext))
+            }
+            ParseOutcome::Incomplete => Err(TlsError::DecodeError("truncated TLS record")),
+            ParseOutcome::Error(err) => Err(err),
+        };
+    }
+
+    /// The header of the next record, once its five bytes are buffered,
+    /// without taking anything off the buffer. A header that does not parse
+    /// is None too, and is reported by the read that takes the record.
+    pub(crate) fn peek_next_header(&self) -> Option<RecordHeader> {
+        let header = self.buffer[self.consumed..].get(..RECORD_HEADER_LEN)?;
+        // Unwrapping is okay because length is guaranteed
+        return RecordHeader::parse(header.try_into().unwrap()).ok();
+    }
+
+    /// If the bytes start with a complete record, return the total length of
+    /// that record (header included), otherwise return None. An invalid header
+    /// is reported right away instead of waiting for a body that will never
+    /// parse.
+    fn complete_record_length(&self, bytes: &[u8]) -> Result<Option<usize>, TlsError> {
+        if bytes.len() < RECORD_HEADER_LEN {
+            return Ok(None);
+        }
+        // Unwrapping is okay because length is guaranteed
+        let header = RecordHeader::parse(bytes[..RECORD_HEADER_LEN].try_into().unwrap())?;
+        if self.strict_versions && !header.version.is_known() {
+            return Err(TlsError::InvalidProtocolVersion);
+        }
+        if bytes.len() < header.record_length() {
+            return Ok(None);
+        }
+
+        return Ok(Some(header.record_length()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alert::{AlertDescription, AlertLevel};
+    use crate::constants::CipherSuite;
+    use std::io::{self, Cursor};
+
+    /// The parser that copies the fragment, which is what most tests want
+    type Parser<'a> = TLSPlaintextParser<'a, Vec<u8>>;
+
+    /// A reader that returns at most `chunk_size` bytes per read, so that a
+    /// record can be split across reads the way a real socket might
+    struct ChunkedReader {
+        inner: Cursor<Vec<u8>>,
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let limit = buf.len().min(self.chunk_size);
+            return self.inner.read(&mut buf[..limit]);
+        }
+    }
 
     #[test]
     fn test_blind_serialization() {
@@ -282,9 +1904,9 @@ mod test {
 
     #[test]
     fn test_parse_content_type() {
-        let start = TLSPlaintextParser::start(&[0x16, 1, 2, 3, 4]);
+        let start = Parser::start(&[0x16, 1, 2, 3, 4]);
         match start.parse_content_type() {
-            TLSPlaintextParser::ExpectProtocolVersion {
+            Parser::ExpectProtocolVersion {
                 content_type,
                 remainder,
             } => {
@@ -297,25 +1919,54 @@ mod test {
 
     #[test]
     fn missing_content_type() {
-        let start = TLSPlaintextParser::start(&[]);
-        assert!(start.parse_content_type().is_failed());
+        let start = Parser::start(&[]);
+        assert!(start.parse_content_type().is_incomplete());
+    }
+
+    #[test]
+    fn reject_invalid_content_type() {
+        assert!(Parser::start(&[0x00, 0x03, 0x03, 0x00, 0x01, 0xaa])
+            .parse_content_type()
+            .is_failed());
+
+        let mut reader = Cursor::new(vec![0x00, 0x03, 0x03, 0x00, 0x01, 0xaa]);
+        assert!(matches!(
+            RecordLayer::new().read_from(&mut reader),
+            Err(TlsError::UnexpectedMessage(_))
+        ));
+
+        // The FSM entry points answer the same way
+        let reason = match TLSPlaintext::try_from(&[0x00, 0x03, 0x03, 0x00, 0x01, 0xaa][..]) {
+            Err(reason) => reason,
+            Ok(_) => unreachable!(),
+        };
+        assert_eq!(reason, ParseError::InvalidContentType(0x00));
+        assert_eq!(
+            TlsError::from(reason).to_alert(),
+            Some(Alert::new(AlertDescription::UnexpectedMessage))
+        );
+        // unlike a byte that is no content type at all
+        assert_eq!(
+            TlsError::from(ParseError::InvalidContentType(0xff)).to_alert(),
+            Some(Alert::new(AlertDescription::DecodeError))
+        );
     }
 
     #[test]
     fn invalid_content_type_encoding() {
-        let start = TLSPlaintextParser::start(&[0xff, 2, 3, 4]);
+        let start = Parser::start(&[0xff, 2, 3, 4]);
         assert!(start.parse_content_type().is_failed());
     }
 
     #[test]
     fn parse_protocol_version() {
-        let start = TLSPlaintextParser::ExpectProtocolVersion {
+        let start = Parser::ExpectProtocolVersion {
             content_type: ContentType::Handshake,
             remainder: &[0x03, 0x03, 1, 2, 3],
         };
 
         match start.parse_protocol_version() {
-            TLSPlaintextParser::ExpectLength {
+            Parser::ExpectLength {
                 content_type,
                 protocol_version,
                 remainder,
@@ -330,34 +1981,88 @@ mod test {
 
     #[test]
     fn missing_protocol_version() {
-        let start = TLSPlaintextParser::ExpectProtocolVersion {
+        let start = Parser::ExpectProtocolVersion {
             content_type: ContentType::Handshake,
             remainder: &[0x03],
         };
 
-        assert!(start.parse_protocol_version().is_failed());
+        assert!(start.parse_protocol_version().is_incomplete());
     }
 
     #[test]
-    fn invalid_protocol_version_encoding() {
-        let start = TLSPlaintextParser::ExpectProtocolVersion {
+    fn unknown_protocol_version_encoding() {
+        let start = Parser::ExpectProtocolVersion {
             content_type: ContentType::Handshake,
             remainder: &[0x03, 0x05, 1, 2, 3], // TLS v1.4?
         };
 
-        assert!(start.parse_protocol_version().is_failed());
-    }
-
+        match start.parse_protocol_version() {
+            Parser::ExpectLength {
+                protocol_version, ..
+            } => {
+                assert_eq!(protocol_version, ProtocolVersion::Unknown([0x03, 0x05]));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn phase_advances_through_a_record() {
+        let mut parser = Parser::start(&[0x17, 0x03, 0x03, 0x00, 0x02, 0xaa, 0xbb]);
+        let mut phases = vec![parser.phase()];
+        while !parser.is_halt() {
+            parser.step().unwrap();
+            phases.push(parser.phase());
+        }
+        assert_eq!(
+            phases,
+            vec![
+                Phase::ContentType,
+                Phase::ProtocolVersion,
+                Phase::Length,
+                Phase::Content,
+                Phase::Finished,
+            ]
+        );
+
+        let mut parser = Parser::start(&[0xff]);
+        assert!(parser.step().is_err());
+        assert_eq!(parser.phase(), Phase::Failed);
+    }
+
+    #[test]
+    fn describe_parser_state() {
+        let parser = Parser::ExpectLength {
+            content_type: ContentType::Handshake,
+            protocol_version: ProtocolVersion::TLSv1_2,
+            remainder: &[0x01],
+        };
+        assert_eq!(
+            parser.describe_state(),
+            "waiting for 1 more length byte (have 1)"
+        );
+
+        let parser = Parser::ExpectLength {
+            content_type: ContentType::Handshake,
+            protocol_version: ProtocolVersion::TLSv1_2,
+            remainder: &[],
+        };
+        assert!(parser.describe_state().contains("2 more length bytes"));
+        assert!(Parser::start(&[0x16])
+            .describe_state()
+            .starts_with("ready to parse the content type"));
+    }
+
     #[test]
     fn parse_length() {
-        let start = TLSPlaintextParser::ExpectLength {
+        let start = Parser::ExpectLength {
             content_type: ContentType::Handshake,
             protocol_version: ProtocolVersion::TLSv1_2,
             remainder: &[0x01, 0x00, 1, 2, 3], // 0x0100 encodes 256
         };
 
         match start.parse_length() {
-            TLSPlaintextParser::ExpectContent {
+            Parser::ExpectContent {
                 content_type: _,
                 protocol_version: _,
                 length,
@@ -372,18 +2077,18 @@ mod test {
 
     #[test]
     fn invalid_length_encoding() {
-        let start = TLSPlaintextParser::ExpectLength {
+        let start = Parser::ExpectLength {
             content_type: ContentType::Handshake,
             protocol_version: ProtocolVersion::TLSv1_2,
             remainder: &[0x01], // too few bytes
         };
 
-        assert!(start.parse_length().is_failed());
+        assert!(start.parse_length().is_incomplete());
     }
 
     #[test]
     fn plaintext_overflow() {
-        let start = TLSPlaintextParser::ExpectLength {
+        let start = Parser::ExpectLength {
             content_type: ContentType::Handshake,
             protocol_version: ProtocolVersion::TLSv1_2,
             remainder: &[0x40, 0x01, 1, 2, 3], // 0x4000 is 2 ^ 14
@@ -394,15 +2099,15 @@ mod test {
 
     #[test]
     fn parse_content() {
-        let start = TLSPlaintextParser::ExpectContent {
+        let start = Parser::ExpectContent {
             content_type: ContentType::Handshake,
             protocol_version: ProtocolVersion::TLSv1_2,
             length: 5u16,
             remainder: &[6, 9, 4, 2, 0],
         };
 
-        match start.parse_content() {
-            TLSPlaintextParser::Finished { tls_plaintext } => {
+        match start.parse_content_exact() {
+            Parser::Finished { tls_plaintext, .. } => {
                 assert_eq!(tls_plaintext.fragment, vec![6, 9, 4, 2, 0]);
             }
             _ => unreachable!(),
@@ -410,20 +2115,191 @@ mod test {
     }
 
     #[test]
-    fn parse_content_wrong_length() {
-        let start = TLSPlaintextParser::ExpectContent {
+    fn parse_content_short() {
+        let start = Parser::ExpectContent {
             content_type: ContentType::Handshake,
             protocol_version: ProtocolVersion::TLSv1_2,
             length: 10u16,
             remainder: &[6, 9, 4, 2, 0],
         };
 
-        assert!(start.parse_content().is_failed());
+        assert!(start.parse_content_exact().is_incomplete());
+    }
+
+    #[test]
+    fn parse_content_wrong_length() {
+        let start = Parser::ExpectContent {
+            content_type: ContentType::Handshake,
+            protocol_version: ProtocolVersion::TLSv1_2,
+            length: 3u16,
+            remainder: &[6, 9, 4, 2, 0],
+        };
+
+        assert!(start.parse_content_exact().is_failed());
+    }
+
+    #[test]
+    fn parse_content_prefix_with_trailing_bytes() {
+        let start = Parser::ExpectContent {
+            content_type: ContentType::Handshake,
+            protocol_version: ProtocolVersion::TLSv1_2,
+            length: 3u16,
+            remainder: &[6, 9, 4, 2, 0],
+        };
+
+        match start.parse_content_prefix() {
+            Parser::Finished {
+                tls_plaintext,
+                rest,
+            } => {
+                assert_eq!(tls_plaintext.fragment, vec![6, 9, 4]);
+                assert_eq!(rest, [2, 0]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_with_consumed_counts_header_and_fragment() {
+        let bytes = [
+            0x17, 0x03, 0x03, 0x00, 0x03, 0xaa, 0xbb, 0xcc, 0x17, 0x03, 0x03, 0x00, 0x01, 0xdd,
+        ];
+        let (record, consumed) = TLSPlaintext::parse_with_consumed(&bytes).unwrap();
+        assert_eq!(consumed, RECORD_HEADER_LEN + usize::from(record.length));
+        assert_eq!(consumed, 8);
+        assert_eq!(record.fragment, [0xaa, 0xbb, 0xcc]);
+
+        let (record, consumed) = TLSPlaintext::parse_with_consumed(&bytes[consumed..]).unwrap();
+        assert_eq!(consumed, 6);
+        assert_eq!(record.fragment, [0xdd]);
+
+        assert!(matches!(
+            TLSPlaintext::parse_with_consumed(&bytes[..7]),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn parse_plaintext_prefix_of_two_records() {
+        let bytes = [
+            0x17, 0x03, 0x03, 0x00, 0x01, 0xaa, 0x17, 0x03, 0x03, 0x00, 0x01, 0xbb,
+        ];
+        assert!(matches!(parse_plaintext(&bytes), ParseOutcome::Error(_)));
+
+        let (first, rest) = match parse_plaintext_prefix(&bytes) {
+            ParseOutcome::Complete(outcome) => outcome,
+            _ => unreachable!(),
+        };
+        assert_eq!(first.fragment, vec![0xaa]);
+        assert_eq!(rest, &bytes[6..]);
+    }
+
+    #[test]
+    fn plaintext_round_trip_through_try_from() {
+        let record = TLSPlaintext {
+            content_type: ContentType::Handshake,
+            legacy_record_version: ProtocolVersion::TLSv1_0,
+            length: 4,
+            fragment: vec![0x14, 0x00, 0x00, 0x00],
+        };
+        let parsed = TLSPlaintext::try_from(&Vec::<u8>::from(record)[..]).unwrap();
+
+        assert_eq!(parsed.content_type, ContentType::Handshake);
+        assert_eq!(parsed.legacy_record_version, ProtocolVersion::TLSv1_0);
+        assert_eq!(parsed.length, 4);
+        assert_eq!(parsed.fragment, vec![0x14, 0x00, 0x00, 0x00]);
+        assert!(matches!(
+            TLSPlaintext::try_from(&[0x16, 0x03, 0x03][..]),
+            Err(ParseError::InsufficientLengthBytes)
+        ));
+    }
+
+    #[test]
+    fn parse_all_back_to_back_records() {
+        let bytes = [
+            0x16, 0x03, 0x03, 0x00, 0x04, 0x14, 0x00, 0x00, 0x00, // handshake
+            0x17, 0x03, 0x03, 0x00, 0x02, 0xaa, 0xbb, // application_data
+        ];
+        let records = parse_all(&bytes).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].content_type, ContentType::Handshake);
+        assert_eq!(records[0].fragment, vec![0x14, 0x00, 0x00, 0x00]);
+        assert_eq!(records[1].content_type, ContentType::ApplicationData);
+        assert_eq!(records[1].fragment, vec![0xaa, 0xbb]);
+
+        assert!(parse_all(&[]).unwrap().is_empty());
+        assert!(matches!(
+            parse_all(&bytes[..bytes.len() - 1]),
+            Err(ParseError::ContentLengthMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_plaintext_short_input_is_incomplete() {
+        // The header promises five bytes of content but only two arrived
+        let outcome = parse_plaintext(&[0x16, 0x03, 0x03, 0x00, 0x05, 0, 1]);
+        assert!(matches!(outcome, ParseOutcome::Incomplete));
+
+        // A header cut off in the middle of the length
+        let outcome = parse_plaintext(&[0x16, 0x03, 0x03, 0x00]);
+        assert!(matches!(outcome, ParseOutcome::Incomplete));
+    }
+
+    #[test]
+    fn coalesce_small_messages_into_one_record() {
+        let messages = [
+            HandshakeMessage {
+                msg_type: HandshakeType::EncryptedExtensions,
+                body: vec![0, 0],
+            },
+            HandshakeMessage {
+                msg_type: HandshakeType::CertificateVerify,
+                body: vec![0x08, 0x07, 0, 0],
+            },
+            HandshakeMessage {
+                msg_type: HandshakeType::Finished,
+                body: vec![0xaa; 32],
+            },
+        ];
+        let records = serialize_flight(&messages);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content_type, ContentType::Handshake);
+        records[0].validate().unwrap();
+        let concatenation: Vec<u8> = messages.into_iter().flat_map(Vec::from).collect();
+        assert_eq!(records[0].fragment, concatenation);
+
+        let large = HandshakeMessage {
+            msg_type: HandshakeType::Certificate,
+            body: vec![0; TLS_PLAINTEXT_MAX_LENGTH as usize],
+        };
+        let records = serialize_flight(&[large]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].length, 4);
+    }
+
+    #[test]
+    fn parse_plaintext_overflow_is_error() {
+        let outcome = parse_plaintext(&[0x16, 0x03, 0x03, 0x40, 0x01, 0, 1]);
+        assert!(matches!(outcome, ParseOutcome::Error(_)));
+    }
+
+    #[test]
+    fn parse_plaintext_complete() {
+        match parse_plaintext(&[0x15, 0x03, 0x03, 0x00, 0x02, 2, 50]) {
+            ParseOutcome::Complete(tls_plaintext) => {
+                assert_eq!(tls_plaintext.content_type, ContentType::Alert);
+                assert_eq!(tls_plaintext.fragment, vec![2, 50]);
+            }
+            _ => unreachable!(),
+        }
     }
 
     #[test]
     fn complete_parsing() {
-        let mut start = TLSPlaintextParser::start(&[
+        let mut start = Parser::start(&[
             0x16, // content_type
             0x03, 0x03, // protocol_version
             0x00, 0x05, // length
@@ -436,7 +2312,7 @@ mod test {
 
         assert!(start.is_finished());
         match start {
-            TLSPlaintextParser::Finished { tls_plaintext } => {
+            Parser::Finished { tls_plaintext, .. } => {
                 assert_eq!(tls_plaintext.content_type, ContentType::Handshake);
                 assert_eq!(
                     tls_plaintext.legacy_record_version,
@@ -448,4 +2324,983 @@ mod test {
             _ => unreachable!(),
         }
     }
+
+    /// Run a TLSCiphertextParser over the bytes until it halts
+    fn parse_ciphertext(bytes: &[u8]) -> TLSCiphertextParser<'_> {
+        let mut parser = TLSCiphertextParser::start(bytes);
+        while !parser.is_halt() {
+            parser = parser.transition();
+        }
+
+        return parser;
+    }
+
+    #[test]
+    fn complete_ciphertext_parsing() {
+        let mut bytes = vec![
+            0x17, // opaque_type
+            0x03, 0x03, // legacy_record_version
+            0x00, 0x11, // length
+        ];
+        bytes.extend(0..17); // encrypted_record
+        let parser = parse_ciphertext(&bytes);
+
+        assert!(parser.is_finished());
+        match parser {
+            TLSCiphertextParser::Finished { ciphertext } => {
+                assert_eq!(ciphertext.opaque_type, ContentType::ApplicationData);
+                assert_eq!(ciphertext.legacy_record_version, ProtocolVersion::TLSv1_2);
+                assert_eq!(ciphertext.length, 17u16);
+                assert_eq!(ciphertext.encrypted_record, (0..17).collect::<Vec<u8>>());
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse_ciphertext(&bytes[..7]).is_incomplete());
+    }
+
+    #[test]
+    fn ciphertext_must_claim_application_data() {
+        let parser = parse_ciphertext(&[0x16, 0x03, 0x03, 0x00, 0x01, 0]);
+        assert_eq!(
+            parser.failure_reason(),
+            Some(&ParseError::InvalidContentType(0x16))
+        );
+    }
+
+    #[test]
+    fn ciphertext_must_claim_tls_1_2() {
+        for version in [[0x03, 0x01], [0x03, 0x04]] {
+            let bytes = [&[0x17][..], &version, &[0x00, 0x01, 0]].concat();
+            assert_eq!(
+                parse_ciphertext(&bytes).failure_reason(),
+                Some(&ParseError::InvalidProtocolVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn ciphertext_must_hold_more_than_the_tag() {
+        // A tag and nothing else, not even the content type
+        let mut bytes = vec![0x17, 0x03, 0x03, 0x00, 0x10];
+        bytes.extend_from_slice(&[0xaa; 16]);
+        let parser = parse_ciphertext(&bytes);
+        assert_eq!(
+            parser.failure_reason(),
+            Some(&ParseError::LengthUnderflow(16))
+        );
+        assert_eq!(
+            parse_ciphertext(&[0x17, 0x03, 0x03, 0x00, 0x00]).failure_reason(),
+            Some(&ParseError::LengthUnderflow(0))
+        );
+        assert_eq!(
+            TlsError::from(ParseError::LengthUnderflow(16)).to_alert(),
+            Some(Alert::new(AlertDescription::DecodeError))
+        );
+    }
+
+    #[test]
+    fn ciphertext_length_ceiling() {
+        // 2^14 + 256 is allowed, unlike for a plaintext record
+        let mut bytes = vec![0x17, 0x03, 0x03, 0x41, 0x00];
+        bytes.resize(
+            RECORD_HEADER_LEN + usize::from(TLS_CIPHERTEXT_MAX_LENGTH),
+            0,
+        );
+        assert!(parse_ciphertext(&bytes).is_finished());
+
+        assert_eq!(
+            parse_ciphertext(&[0x17, 0x03, 0x03, 0x41, 0x01]).failure_reason(),
+            Some(&ParseError::LengthOverflow(0x4101))
+        );
+    }
+
+    #[test]
+    fn read_partial_record_across_two_reads() {
+        let bytes = vec![
+            0x16, 0x03, 0x03, 0x00, 0x05, 0, 1, 2, 3, 4, // first record
+            0x17, 0x03, 0x03, 0x00, 0x03, 5, 6, 7, // second record
+        ];
+        // The first read returns the first record and half of the second
+        let mut reader = ChunkedReader {
+            inner: Cursor::new(bytes),
+            chunk_size: 14,
+        };
+        let mut record_layer = RecordLayer::new();
+
+        let records = record_layer.read_from(&mut reader).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content_type, ContentType::Handshake);
+        assert_eq!(records[0].fragment, vec![0, 1, 2, 3, 4]);
+        assert_eq!(record_layer.buffer, vec![0x17, 0x03, 0x03, 0x00]);
+
+        let records = record_layer.read_from(&mut reader).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content_type, ContentType::ApplicationData);
+        assert_eq!(records[0].fragment, vec![5, 6, 7]);
+        assert!(record_layer.buffer.is_empty());
+    }
+
+    #[test]
+    fn buffer_stays_bounded_over_many_records() {
+        let mut record = vec![0x17, 0x03, 0x03, 0x03, 0xe8];
+        record.extend_from_slice(&[0xaa; 1000]);
+        let mut reader = ChunkedReader {
+            inner: Cursor::new(record.repeat(1000)),
+            chunk_size: 4096,
+        };
+        let mut record_layer = RecordLayer::new();
+
+        let mut nrecords = 0;
+        while let Some(record) = record_layer.read_one_record(&mut reader).unwrap() {
+            assert_eq!(record.fragment.len(), 1000);
+            nrecords += 1;
+            assert!(record_layer.buffer.capacity() <= BUFFER_CAPACITY_LIMIT);
+        }
+        assert_eq!(nrecords, 1000);
+    }
+
+    #[test]
+    fn read_coalesced_records() {
+        let mut reader = Cursor::new(vec![
+            0x16, 0x03, 0x03, 0x00, 0x01, 0, // first record
+            0x15, 0x03, 0x03, 0x00, 0x02, 1, 2, // second record
+        ]);
+        let mut record_layer = RecordLayer::new();
+
+        let records = record_layer.read_from(&mut reader).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].content_type, ContentType::Alert);
+        assert_eq!(records[1].fragment, vec![1, 2]);
+    }
+
+    /// A reader whose socket has gone away
+    struct BrokenReader;
+
+    impl Read for BrokenReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        }
+    }
+
+    #[test]
+    fn read_one_record_until_clean_eof() {
+        let mut reader = ChunkedReader {
+            inner: Cursor::new(vec![
+                0x17, 0x03, 0x03, 0x00, 0x01, 0xab, // application_data
+                0x17, 0x03, 0x03, 0x00, 0x02, 0xcd, 0xef, // application_data
+            ]),
+            chunk_size: 4,
+        };
+        let mut record_layer = RecordLayer::new();
+
+        let record = record_layer.read_one_record(&mut reader).unwrap().unwrap();
+        assert_eq!(record.fragment, [0xab]);
+        let record = record_layer.read_one_record(&mut reader).unwrap().unwrap();
+        assert_eq!(record.fragment, [0xcd, 0xef]);
+        assert!(record_layer.read_one_record(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_one_record_eof_inside_body() {
+        let mut reader = Cursor::new(vec![0x17, 0x03, 0x03, 0x00, 0x04, 0xab, 0xcd]);
+        let mut record_layer = RecordLayer::new();
+
+        assert!(matches!(
+            record_layer.read_one_record(&mut reader),
+            Err(TlsError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn read_from_broken_socket() {
+        let mut record_layer = RecordLayer::new();
+
+        assert!(matches!(
+            record_layer.read_from(&mut BrokenReader),
+            Err(TlsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn read_malformed_record() {
+        let mut reader = Cursor::new(vec![0xff, 0x03, 0x03, 0x00, 0x01, 0]);
+        let mut record_layer = RecordLayer::new();
+
+        assert!(matches!(
+            record_layer.read_from(&mut reader),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn classify_alert_record() {
+        let record = TLSPlaintext {
+            content_type: ContentType::Alert,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 2,
+            fragment: vec![2, 40],
+        };
+
+        assert_eq!(
+            classify(record).unwrap(),
+            Fragment::Alert(Alert {
+                level: AlertLevel::Fatal,
+                description: AlertDescription::HandshakeFailure,
+            })
+        );
+    }
+
+    #[test]
+    fn classify_invalid_change_cipher_spec() {
+        let record = TLSPlaintext {
+            content_type: ContentType::ChangeCipherSpec,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 2,
+            fragment: vec![1, 1],
+        };
+
+        assert!(classify(record).is_err());
+    }
+
+    #[test]
+    fn step_to_completion() {
+        let mut parser = Parser::start(&[
+            0x17, // content_type
+            0x03, 0x03, // protocol_version
+            0x00, 0x03, // length
+            7, 8, 9, // content
+        ]);
+
+        while !parser.is_halt() {
+            parser.step().unwrap();
+        }
+
+        match parser {
+            Parser::Finished { tls_plaintext, .. } => {
+                assert_eq!(tls_plaintext.content_type, ContentType::ApplicationData);
+                assert_eq!(tls_plaintext.fragment, vec![7, 8, 9]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_length_in_the_wrong_state() {
+        let parser = Parser::start(&[0x16, 0x03, 0x03, 0x00, 0x00]).parse_length();
+        assert_eq!(parser.failure_reason(), Some(&ParseError::WrongState));
+    }
+
+    /// Run the parser to a halt on bytes that are all the input there is
+    fn failure_reason_at_end_of_input(bytes: &[u8]) -> Option<ParseError> {
+        let mut parser = Parser::start(bytes);
+        while !parser.is_halt() {
+            parser = parser.end_of_input().transition();
+        }
+
+        return parser.failure_reason().copied();
+    }
+
+    #[test]
+    fn failure_reason_of_each_path() {
+        let cases: &[(&[u8], ParseError)] = &[
+            (&[], ParseError::MissingContentType),
+            (&[0x16, 0x03], ParseError::MissingProtocolVersion),
+            (
+                &[0x16, 0x03, 0x03, 0x00],
+                ParseError::InsufficientLengthBytes,
+            ),
+            (&[0xff, 0x03, 0x03], ParseError::InvalidContentType(0xff)),
+            (&[0x00, 0x03, 0x03], ParseError::InvalidContentType(0x00)),
+            (
+                &[0x16, 0x03, 0x03, 0x40, 0x01],
+                ParseError::LengthOverflow(0x4001),
+            ),
+            (
+                &[0x16, 0x03, 0x03, 0x00, 0x03, 1, 2],
+                ParseError::ContentLengthMismatch {
+                    expected: 3,
+                    actual: 2,
+                },
+            ),
+            (
+                &[0x16, 0x03, 0x03, 0x00, 0x01, 1, 2],
+                ParseError::ContentLengthMismatch {
+                    expected: 1,
+                    actual: 2,
+                },
+            ),
+        ];
+        for (bytes, reason) in cases {
+            assert_eq!(
+                failure_reason_at_end_of_input(bytes),
+                Some(*reason),
+                "{:02x?}",
+                bytes
+            );
+        }
+
+        // Without end_of_input, running out of bytes is not a failure
+        let parser = Parser::start(&[0x16, 0x03]).transition().transition();
+        assert!(parser.is_incomplete());
+        assert_eq!(parser.failure_reason(), None);
+        assert_eq!(
+            failure_reason_at_end_of_input(&[0x15, 0x03, 0x03, 0x00, 0x02, 2, 0]),
+            None
+        );
+    }
+
+    #[test]
+    fn failure_reason_as_error() {
+        let err = TlsError::from(ParseError::LengthOverflow(0x4001));
+        assert!(matches!(
+            &err,
+            TlsError::RecordField { field: "length", error }
+                if matches!(error.as_ref(), TlsError::RecordOverflow)
+        ));
+        assert_eq!(
+            err.to_alert(),
+            Some(Alert::new(crate::alert::AlertDescription::RecordOverflow))
+        );
+        assert!(matches!(
+            TlsError::from(ParseError::ContentLengthMismatch {
+                expected: 3,
+                actual: 2
+            }),
+            TlsError::RecordField { error, .. } if matches!(
+                *error,
+                TlsError::LengthMismatch {
+                    declared: 3,
+                    actual: 2,
+                    ..
+                }
+            )
+        ));
+        assert!(matches!(
+            TlsError::from(ParseError::WrongState),
+            TlsError::Internal(_)
+        ));
+    }
+
+    #[test]
+    fn error_names_the_failed_phase() {
+        // A protected record must claim TLS 1.2
+        let parser = parse_ciphertext(&[0x17, 0x03, 0x04, 0x00, 0x01, 0]);
+        let err = TlsError::from(*parser.failure_reason().unwrap());
+        assert!(err.to_string().contains("protocol version"), "{}", err);
+
+        let mut parser = Parser::start(&[0x16, 0x03, 0x03, 0x40, 0x01]);
+        while parser.step().is_ok() && !parser.is_halt() {}
+        assert!(matches!(
+            parser.step(),
+            Err(TlsError::RecordField {
+                field: "length",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn step_into_failure() {
+        let mut parser = Parser::start(&[0xff, 0x03, 0x03]);
+
+        assert!(parser.step().is_err());
+        assert!(parser.is_failed());
+    }
+
+    #[test]
+    fn parse_handshake_record_header() {
+        let header = RecordHeader::parse(&[0x16, 0x03, 0x01, 0x00, 0xf3]).unwrap();
+
+        assert_eq!(header.content_type, ContentType::Handshake);
+        assert_eq!(header.version, ProtocolVersion::TLSv1_0);
+        assert_eq!(header.length, 243);
+        assert_eq!(header.record_length(), 248);
+    }
+
+    #[test]
+    fn peek_then_take_the_same_record() {
+        let bytes = [0x17, 0x03, 0x03, 0x00, 0x02, 0xab, 0xcd];
+        let mut record_layer = RecordLayer::new();
+        record_layer
+            .read_chunk(&mut Cursor::new(&bytes[..4]))
+            .unwrap();
+        assert_eq!(record_layer.peek_next_header(), None);
+
+        record_layer
+            .read_chunk(&mut Cursor::new(&bytes[4..]))
+            .unwrap();
+        let header = record_layer.peek_next_header().unwrap();
+        assert_eq!(header.content_type, ContentType::ApplicationData);
+        assert_eq!(header.length, 2);
+        // Peeking again sees the same header, since nothing was consumed
+        assert_eq!(record_layer.peek_next_header(), Some(header.clone()));
+
+        let records = record_layer.take_records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content_type(), header.content_type);
+        assert_eq!(records[0].fragment, [0xab, 0xcd]);
+        assert_eq!(record_layer.peek_next_header(), None);
+    }
+
+    #[test]
+    fn parse_invalid_record_header() {
+        assert!(RecordHeader::parse(&[0xff, 0x03, 0x03, 0x00, 0x01]).is_err());
+        assert!(RecordHeader::parse(&[0x16, 0x03, 0x03, 0x40, 0x01]).is_err());
+    }
+
+    #[test]
+    fn unknown_record_version_round_trip() {
+        let bytes = vec![0x17, 0x03, 0x99, 0x00, 0x02, 0xab, 0xcd];
+        let tls_plaintext = match parse_plaintext(&bytes) {
+            ParseOutcome::Complete(tls_plaintext) => tls_plaintext,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            tls_plaintext.legacy_record_version,
+            ProtocolVersion::Unknown([0x03, 0x99])
+        );
+
+        let serialized: Vec<u8> = tls_plaintext.into();
+        assert_eq!(serialized, bytes);
+    }
+
+    #[test]
+    fn strict_record_layer_rejects_unknown_version() {
+        let bytes = vec![0x17, 0x03, 0x99, 0x00, 0x02, 0xab, 0xcd];
+        let mut record_layer = RecordLayer::new();
+        assert_eq!(
+            record_layer
+                .read_from(&mut Cursor::new(bytes.clone()))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let mut record_layer = RecordLayer::new();
+        record_layer.strict_versions = true;
+        assert!(matches!(
+            record_layer.read_from(&mut Cursor::new(bytes)),
+            Err(TlsError::InvalidProtocolVersion)
+        ));
+    }
+
+    #[test]
+    fn convert_between_record_and_variants() {
+        let plaintext = || TLSPlaintext {
+            content_type: ContentType::Handshake,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 2,
+            fragment: vec![0xab, 0xcd],
+        };
+        let ciphertext = || TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 1,
+            encrypted_record: vec![0xef],
+        };
+
+        let record: Record<Vec<u8>> = plaintext().into();
+        assert_eq!(
+            TLSPlaintext::try_from(record).unwrap().fragment,
+            [0xab, 0xcd]
+        );
+        assert!(TLSCiphertext::try_from(Record::from(plaintext())).is_err());
+
+        let record: Record<Vec<u8>> = ciphertext().into();
+        assert_eq!(
+            TLSCiphertext::try_from(record).unwrap().encrypted_record,
+            [0xef]
+        );
+        assert!(TLSPlaintext::try_from(Record::from(ciphertext())).is_err());
+    }
+
+    #[test]
+    fn remaining_holds_the_next_record() {
+        let bytes = [
+            0x16, 0x03, 0x03, 0x00, 0x01, 0xab, // first record
+            0x17, 0x03, 0x03, 0x00, 0x01, 0xcd, // second record
+        ];
+        let mut parser: TLSPlaintextParser = TLSPlaintextParser::start(&bytes);
+        assert_eq!(parser.remaining(), bytes);
+        while !parser.is_halt() {
+            parser = match parser {
+                TLSPlaintextParser::ExpectContent { .. } => parser.parse_content_prefix(),
+                parser => parser.transition(),
+            };
+        }
+
+        assert!(parser.is_finished());
+        assert_eq!(parser.remaining(), &bytes[6..]);
+    }
+
+    #[test]
+    fn parse_with_borrowed_fragment() {
+        let bytes = [0x16, 0x03, 0x03, 0x00, 0x02, 0xab, 0xcd];
+        let mut parser: TLSPlaintextParser<&[u8]> = TLSPlaintextParser::start(&bytes);
+        while !parser.is_halt() {
+            parser = parser.transition();
+        }
+
+        let fragment = match parser {
+            TLSPlaintextParser::Finished { tls_plaintext, .. } => tls_plaintext.fragment,
+            _ => panic!("record should parse"),
+        };
+        assert_eq!(fragment, [0xab, 0xcd]);
+        // The fragment points into the input instead of a copy
+        assert!(std::ptr::eq(fragment.as_ptr(), bytes[5..].as_ptr()));
+    }
+
+    #[test]
+    fn validate_record_length() {
+        let mut record = TLSPlaintext {
+            content_type: ContentType::Handshake,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 3,
+            fragment: vec![0xab, 0xcd],
+        };
+        assert_eq!(record.declared_length(), 3);
+        assert!(matches!(
+            record.validate(),
+            Err(TlsError::LengthMismatch {
+                field: "length",
+                declared: 3,
+                actual: 2,
+            })
+        ));
+
+        record.length = 2;
+        assert!(record.validate().is_ok());
+
+        record.fragment = vec![0; usize::from(TLS_PLAINTEXT_MAX_LENGTH) + 1];
+        record.length = TLS_PLAINTEXT_MAX_LENGTH + 1;
+        assert!(matches!(record.validate(), Err(TlsError::DecodeError(_))));
+    }
+
+    #[test]
+    fn raw_record_round_trip_is_identity() {
+        // Framing is all that matters: every content type byte and version,
+        // including ones that mean nothing, round-trip unchanged
+        let mut stream = vec![];
+        for i in 0..64u16 {
+            let length = (i * 37) % 300;
+            stream.push((i * 7) as u8);
+            stream.extend_from_slice(&[(i * 13) as u8, (i * 17) as u8]);
+            stream.extend_from_slice(&length.to_be_bytes());
+            stream.extend((0..length).map(|j| (i ^ j) as u8));
+        }
+
+        let mut serialized = vec![];
+        let mut remainder = stream.as_slice();
+        while !remainder.is_empty() {
+            let (raw_record, rest) = RawRecord::parse(remainder).unwrap();
+            serialized.extend_from_slice(&raw_record.to_bytes());
+            remainder = rest;
+        }
+        assert_eq!(serialized, stream);
+
+        assert!(RawRecord::parse(&[0x16, 0x03, 0x03, 0x00]).is_err());
+        assert!(RawRecord::parse(&[0x16, 0x03, 0x03, 0x00, 0x02, 0xff]).is_err());
+    }
+
+    #[test]
+    fn sniff_tls_from_first_bytes() {
+        // The start of a ClientHello record with a TLS 1.0 record version
+        assert!(sniff_tls(&[0x16, 0x03, 0x01, 0x00, 0xc4, 0x01, 0x00]));
+        assert!(sniff_tls(&[0x17, 0x03, 0x03, 0x41, 0x00]));
+
+        assert!(!sniff_tls(b"GET / HTTP/1.1\r\nHost: example.com\r\n"));
+        assert!(!sniff_tls(&[0x16, 0x03, 0x01, 0x00]));
+        assert!(!sniff_tls(&[0x00, 0x03, 0x03, 0x00, 0x01]));
+        assert!(!sniff_tls(&[0x16, 0x03, 0x05, 0x00, 0x01]));
+        assert!(!sniff_tls(&[0x16, 0x03, 0x03, 0x00, 0x00]));
+        assert!(!sniff_tls(&[0x16, 0x03, 0x03, 0x41, 0x00]));
+    }
+
+    #[test]
+    fn opaque_record_round_trip() {
+        // An unknown content type and version, which only an opaque record
+        // can hold
+        let bytes = [0x42, 0xfe, 0xfd, 0x00, 0x03, 0x01, 0x02, 0x03];
+        let (raw_record, rest) = RawRecord::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+
+        let record: Record<Vec<u8>> = Record::from(raw_record);
+        assert_eq!(Vec::from(record), bytes);
+        let record: Record<Vec<u8>> = Record::from(RawRecord::parse(&bytes).unwrap().0);
+        assert!(TLSPlaintext::try_from(record).is_err());
+    }
+
+    #[test]
+    fn validate_framing_without_copying() {
+        let record = [0x16, 0x03, 0x03, 0x00, 0x02, 0xab, 0xcd];
+        assert_eq!(validate_record_framing(&record).unwrap(), 7);
+        // Whatever follows the record is not its concern
+        assert_eq!(
+            validate_record_framing(&[&record[..], &[0x17]].concat()).unwrap(),
+            7
+        );
+
+        assert!(matches!(
+            validate_record_framing(&record[..6]),
+            Err(TlsError::DecodeError("truncated record body"))
+        ));
+        assert!(matches!(
+            validate_record_framing(&record[..4]),
+            Err(TlsError::DecodeError("truncated record header"))
+        ));
+
+        // 0x4001 is one byte past the plaintext limit
+        let mut oversized = vec![0x16, 0x03, 0x03, 0x40, 0x01];
+        oversized.resize(RECORD_HEADER_LEN + 0x4001, 0);
+        assert!(matches!(
+            validate_record_framing(&oversized),
+            Err(TlsError::RecordOverflow)
+        ));
+    }
+
+    #[test]
+    fn record_header_length() {
+        assert_eq!(RECORD_HEADER_LEN, 5);
+
+        let bytes = [0x17, 0x03, 0x03, 0x00, 0x01, 0xab];
+        assert!(RawRecord::parse(&bytes[..RECORD_HEADER_LEN - 1]).is_err());
+        let (raw_record, _) = RawRecord::parse(&bytes).unwrap();
+        assert_eq!(raw_record.header_bytes, bytes[..RECORD_HEADER_LEN]);
+        assert_eq!(raw_record.body, bytes[RECORD_HEADER_LEN..]);
+    }
+
+    #[test]
+    fn reject_unprotected_application_data_before_keys() {
+        let mut record_layer = RecordLayer::new();
+        let records = record_layer
+            .read_from(&mut Cursor::new(vec![
+                0x17, 0x03, 0x03, 0x00, 0x01, 0xab, // application_data
+                0x15, 0x03, 0x03, 0x00, 0x02, 0x01, 0x00, // close_notify
+            ]))
+            .unwrap();
+        let outcomes: Vec<bool> = records
+            .into_iter()
+            .map(|record| record_layer.unprotect(record).is_ok())
+            .collect();
+
+        assert_eq!(outcomes, vec![false, true]);
+    }
+
+    #[test]
+    fn read_invalid_header_without_body() {
+        // Only the header has arrived, but it is already known to be invalid
+        let mut reader = Cursor::new(vec![0x16, 0x03, 0x03, 0xff, 0xff]);
+        let mut record_layer = RecordLayer::new();
+
+        assert!(record_layer.read_from(&mut reader).is_err());
+    }
+
+    fn test_traffic_key() -> TrafficKey {
+        return TrafficKey::new(CipherSuite::Aes128GcmSha256, &[0x42; 16], &[0x24; 12]).unwrap();
+    }
+
+    #[test]
+    fn seal_and_open_record() {
+        let mut key = test_traffic_key();
+        let ciphertext = seal_record(
+            &mut key,
+            0,
+            ContentType::Handshake,
+            b"finished",
+            &PaddingPolicy::None,
+        )
+        .unwrap();
+        assert_eq!(ciphertext.length as usize, 8 + 1 + TAG_LENGTH);
+
+        let (content_type, content) = open_record(&key, 0, ciphertext).unwrap();
+        assert_eq!(content_type, ContentType::Handshake);
+        assert_eq!(content, b"finished");
+    }
+
+    #[test]
+    fn sequence_restarts_with_each_epoch() {
+        let mut record_layer = RecordLayer::new();
+        assert_eq!(record_layer.write_epoch(), Epoch::Plaintext);
+        record_layer.set_write_key(Epoch::Handshake, test_traffic_key());
+        record_layer
+            .write_records(ContentType::Handshake, b"finished")
+            .unwrap();
+        record_layer
+            .write_records(ContentType::Alert, &[2, 10])
+            .unwrap();
+        assert_eq!(record_layer.write_sequence, 2);
+
+        record_layer.set_write_key(Epoch::Application, test_traffic_key());
+        assert_eq!(record_layer.write_epoch(), Epoch::Application);
+        assert_eq!(record_layer.write_sequence, 0);
+
+        // The peer's first application record opens at sequence zero
+        let mut peer_key = test_traffic_key();
+        let record = seal_record(
+            &mut peer_key,
+            0,
+            ContentType::ApplicationData,
+            b"data",
+            &PaddingPolicy::None,
+        )
+        .unwrap();
+        record_layer.set_read_key(Epoch::Application, test_traffic_key());
+        let (_, content) = record_layer
+            .unprotect(TLSPlaintext {
+                content_type: record.opaque_type,
+                legacy_record_version: record.legacy_record_version,
+                length: record.length,
+                fragment: record.encrypted_record,
+            })
+            .unwrap();
+        assert_eq!(content, b"data");
+        assert_eq!(record_layer.read_sequence, 1);
+    }
+
+    #[test]
+    fn open_under_old_key_within_window() {
+        let next_key = || {
+            return TrafficKey::new(CipherSuite::Aes128GcmSha256, &[0x43; 16], &[0x25; 12])
+                .unwrap();
+        };
+        let seal = |key: &mut TrafficKey, sequence, content: &[u8]| {
+            return seal_record(
+                key,
+                sequence,
+                ContentType::ApplicationData,
+                content,
+                &PaddingPolicy::None,
+            )
+            .unwrap();
+        };
+        let (mut old_key, mut new_key) = (test_traffic_key(), next_key());
+        let first = seal(&mut old_key, 0, b"first");
+        let late = seal(&mut old_key, 1, b"late");
+        let later = seal(&mut old_key, 2, b"later");
+        let updated = seal(&mut new_key, 0, b"updated");
+
+        let mut record_layer = RecordLayer::new();
+        record_layer.set_key_update_window(1);
+        record_layer.set_read_key(Epoch::Application, test_traffic_key());
+        assert_eq!(record_layer.open(&first).unwrap().1, b"first");
+        record_layer.set_read_key(Epoch::Application, next_key());
+
+        // The record that was overtaken by the KeyUpdate is still accepted,
+        // but only one of them
+        assert_eq!(record_layer.open(&updated).unwrap().1, b"updated");
+        assert_eq!(record_layer.open(&late).unwrap().1, b"late");
+        assert!(matches!(
+            record_layer.open(&later),
+            Err(TlsError::BadRecordMac)
+        ));
+        assert_eq!(record_layer.read_sequence, 1);
+
+        // Without a window the old key is dropped
+        let mut record_layer = RecordLayer::new();
+        record_layer.set_read_key(Epoch::Application, test_traffic_key());
+        record_layer.set_read_key(Epoch::Application, next_key());
+        assert!(matches!(
+            record_layer.open(&first),
+            Err(TlsError::BadRecordMac)
+        ));
+    }
+
+    #[test]
+    fn drain_encrypted_handshake_stream() {
+        let encrypted_extensions = HandshakeMessage {
+            msg_type: HandshakeType::EncryptedExtensions,
+            body: vec![0x00, 0x00],
+        };
+        let finished = HandshakeMessage {
+            msg_type: HandshakeType::Finished,
+            body: vec![0xaa; 32],
+        };
+        // Both messages in the first record but for the end of Finished
+        let flight = [
+            Vec::from(encrypted_extensions.clone()),
+            Vec::from(finished.clone()),
+        ]
+        .concat();
+        let mut key = test_traffic_key();
+        let mut records = vec![];
+        for (sequence, fragment) in [&flight[..20], &flight[20..]].into_iter().enumerate() {
+            records.push(
+                seal_record(
+                    &mut key,
+                    sequence as u64,
+                    ContentType::Handshake,
+                    fragment,
+                    &PaddingPolicy::None,
+                )
+                .unwrap(),
+            );
+        }
+        let mut records = records.into_iter();
+        let mut stream = EncryptedHandshakeStream::new(test_traffic_key());
+
+        stream.push_record(records.next().unwrap()).unwrap();
+        assert_eq!(stream.next_message().unwrap(), Some(encrypted_extensions));
+        assert_eq!(stream.next_message().unwrap(), None);
+        assert!(!stream.is_empty());
+        stream.push_record(records.next().unwrap()).unwrap();
+        assert_eq!(stream.next_message().unwrap(), Some(finished));
+        assert_eq!(stream.next_message().unwrap(), None);
+        assert!(stream.is_empty());
+    }
+
+    #[test]
+    fn compute_ciphertext_length() {
+        // "finished" and its content type, padded to 16, and the tag
+        assert_eq!(TLSCiphertext::compute_length(9, 7, TAG_LENGTH).unwrap(), 32);
+        let max = usize::from(TLS_CIPHERTEXT_MAX_LENGTH);
+        assert_eq!(
+            TLSCiphertext::compute_length(max - TAG_LENGTH, 0, TAG_LENGTH).unwrap(),
+            TLS_CIPHERTEXT_MAX_LENGTH
+        );
+        assert!(matches!(
+            TLSCiphertext::compute_length(max - TAG_LENGTH, 1, TAG_LENGTH),
+            Err(TlsError::RecordOverflow)
+        ));
+    }
+
+    #[test]
+    fn seal_record_twice_with_same_sequence() {
+        let mut key = test_traffic_key();
+        seal_record(
+            &mut key,
+            0,
+            ContentType::ApplicationData,
+            b"first",
+            &PaddingPolicy::None,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            seal_record(
+                &mut key,
+                0,
+                ContentType::ApplicationData,
+                b"second",
+                &PaddingPolicy::None
+            ),
+            Err(TlsError::NonceReuse(0))
+        ));
+    }
+
+    #[test]
+    fn open_record_with_empty_content() {
+        // The inner plaintext is only the content type and some padding
+        let mut key = test_traffic_key();
+        let mut in_out = vec![0x17, 0x00, 0x00, 0x00];
+        let length = (in_out.len() + TAG_LENGTH) as u16;
+        key.seal(0, &ciphertext_additional_data(length), &mut in_out)
+            .unwrap();
+        let ciphertext = TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length,
+            encrypted_record: in_out,
+        };
+
+        let (content_type, content) = open_record(&key, 0, ciphertext).unwrap();
+        assert_eq!(content_type, ContentType::ApplicationData);
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn open_record_shorter_than_tag() {
+        let ciphertext = TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length: 10,
+            encrypted_record: vec![0xaa; 10],
+        };
+
+        assert!(matches!(
+            open_record(&test_traffic_key(), 0, ciphertext),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn open_record_with_tag_only() {
+        // Sealing nothing gives a body of exactly the tag, which authenticates
+        // but leaves no room for the content type
+        let mut key = test_traffic_key();
+        let mut in_out = vec![];
+        let length = TAG_LENGTH as u16;
+        key.seal(0, &ciphertext_additional_data(length), &mut in_out)
+            .unwrap();
+        assert_eq!(in_out.len(), 16);
+        let ciphertext = TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length,
+            encrypted_record: in_out.clone(),
+        };
+        assert!(matches!(
+            open_record(&key, 0, ciphertext),
+            Err(TlsError::DecodeError(_))
+        ));
+
+        let record = TLSPlaintext {
+            content_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length,
+            fragment: in_out,
+        };
+        assert!(matches!(
+            TLSCiphertext::try_from(record),
+            Err(TlsError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn open_record_with_only_padding() {
+        let mut key = test_traffic_key();
+        let mut in_out = vec![0x00, 0x00];
+        let length = (in_out.len() + TAG_LENGTH) as u16;
+        key.seal(0, &ciphertext_additional_data(length), &mut in_out)
+            .unwrap();
+        let ciphertext = TLSCiphertext {
+            opaque_type: ContentType::ApplicationData,
+            legacy_record_version: ProtocolVersion::TLSv1_2,
+            length,
+            encrypted_record: in_out,
+        };
+
+        assert!(matches!(
+            open_record(&key, 0, ciphertext),
+            Err(TlsError::UnexpectedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn pad_to_block() {
+        let mut inner = vec![];
+        encode_inner_plaintext(
+            ContentType::ApplicationData,
+            b"0123456789",
+            &PaddingPolicy::PadToBlock(256),
+            &mut inner,
+        );
+
+        assert_eq!(inner.len(), 256);
+        assert_eq!(inner[10], 0x17);
+        assert!(inner[11..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn padding_never_exceeds_record_limit() {
+        let content = vec![0xff; TLS_PLAINTEXT_MAX_LENGTH as usize - 10];
+        let mut inner = vec![];
+        encode_inner_plaintext(
+            ContentType::ApplicationData,
+            &content,
+            &PaddingPolicy::Fixed(1000),
+            &mut inner,
+        );
+
+        assert_eq!(inner.len(), TLS_PLAINTEXT_MAX_LENGTH as usize + 1);
+    }
 }
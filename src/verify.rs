@@ -0,0 +1,414 @@
+//! Deciding whether to trust the certificate chain that the server presents,
+//! and whether the server holds the key of its certificate. The chain check
+//! is behind a trait so that tests and local servers with self-signed
+//! certificates can opt out of it explicitly.
+use crate::constants::SignatureScheme;
+use crate::crypto::asn1;
+use crate::error::TlsError;
+use ring::signature;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// What the server signs in CertificateVerify comes after 64 spaces and this
+/// context string (RFC 8446, section 4.4.3)
+const SERVER_SIGNATURE_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify";
+
+/// Checks the server's certificate chain. The end-entity certificate comes
+/// first in the Certificate message and the intermediates follow it; each
+/// certificate is DER.
+pub(crate) trait ServerCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &[u8],
+        intermediates: &[&[u8]],
+        server_name: &str,
+        now: SystemTime,
+    ) -> Result<(), TlsError>;
+}
+
+/// Accepts every chain, which leaves the connection open to anyone in the
+/// middle. Only for testing, through ClientConfig::dangerous.
+#[allow(dead_code)]
+pub(crate) struct DangerousAcceptAny;
+
+impl ServerCertVerifier for DangerousAcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &[u8],
+        _intermediates: &[&[u8]],
+        _server_name: &str,
+        _now: SystemTime,
+    ) -> Result<(), TlsError> {
+        return Ok(());
+    }
+}
+
+/// Rejects every chain. It stands in for WebPkiVerifier when the crate is
+/// built without webpki-verifier, so that nothing is trusted by accident.
+#[allow(dead_code)]
+pub(crate) struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &[u8],
+        _intermediates: &[&[u8]],
+        _server_name: &str,
+        _now: SystemTime,
+    ) -> Result<(), TlsError> {
+        return Err(TlsError::BadCertificate(
+            "no certificate verifier without the webpki-verifier feature",
+        ));
+    }
+}
+
+/// The verifier of ClientConfig::default: the bundled roots with
+/// webpki-verifier, and nothing at all without it
+pub(crate) fn default_verifier() -> Arc<dyn ServerCertVerifier + Send + Sync> {
+    #[cfg(feature = "webpki-verifier")]
+    return Arc::new(WebPkiVerifier::with_bundled_roots());
+    #[cfg(not(feature = "webpki-verifier"))]
+    return Arc::new(NoVerifier);
+}
+
+/// The content that the server signs in CertificateVerify, over the hash of
+/// the transcript up to Certificate
+pub(crate) fn server_signed_content(transcript_hash: &[u8]) -> Vec<u8> {
+    let mut content = vec![0x20; 64];
+    content.extend_from_slice(SERVER_SIGNATURE_CONTEXT);
+    content.push(0);
+    content.extend_from_slice(transcript_hash);
+
+    return content;
+}
+
+/// Check the signature of CertificateVerify with the public key of the
+/// end-entity certificate. TLS 1.3 signs with RSA only in PSS (RFC 8446,
+/// section 4.4.3), so the PKCS #1 schemes are only good for the chain.
+pub(crate) fn verify_server_signature(
+    end_entity: &[u8],
+    scheme: SignatureScheme,
+    signature: &[u8],
+    transcript_hash: &[u8],
+) -> Result<(), TlsError> {
+    let algorithm: &dyn signature::VerificationAlgorithm = match scheme {
+        SignatureScheme::EcdsaSecp256r1Sha256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::EcdsaSecp384r1Sha384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::Ed25519 => &signature::ED25519,
+        SignatureScheme::RsaPssRsaeSha256 => &signature::RSA_PSS_2048_8192_SHA256,
+        SignatureScheme::RsaPssRsaeSha384 => &signature::RSA_PSS_2048_8192_SHA384,
+        SignatureScheme::RsaPssRsaeSha512 => &signature::RSA_PSS_2048_8192_SHA512,
+        _ => {
+            return Err(TlsError::IllegalParameter(
+                "CertificateVerify scheme is not allowed in TLS 1.3",
+            ))
+        }
+    };
+    let public_key = asn1::subject_public_key(end_entity)
+        .map_err(|_| TlsError::BadCertificate("malformed certificate"))?;
+
+    return signature::UnparsedPublicKey::new(algorithm, public_key)
+        .verify(&server_signed_content(transcript_hash), signature)
+        .map_err(|_| TlsError::DecryptError("CertificateVerify does not verify"));
+}
+
+/// A trust anchor that owns its fields, so that it can come from anywhere and
+/// not just from a static root list
+#[allow(dead_code)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct OwnedTrustAnchor {
+    subject: Vec<u8>,
+    spki: Vec<u8>,
+    name_constraints: Option<Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl OwnedTrustAnchor {
+    /// The fields are DER, as in webpki::TrustAnchor
+    pub(crate) fn from_subject_spki_name_constraints(
+        subject: &[u8],
+        spki: &[u8],
+        name_constraints: Option<&[u8]>,
+    ) -> Self {
+        return Self {
+            subject: subject.to_vec(),
+            spki: spki.to_vec(),
+            name_constraints: name_constraints.map(|name_constraints| name_constraints.to_vec()),
+        };
+    }
+}
+
+/// The trust anchors that a server's chain must lead to, set up the way
+/// rustls's RootCertStore is in main.rs
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RootCertStore {
+    roots: Vec<OwnedTrustAnchor>,
+}
+
+#[allow(dead_code)]
+impl RootCertStore {
+    pub(crate) fn empty() -> Self {
+        return Self::default();
+    }
+
+    /// The Mozilla root store bundled by webpki-roots
+    pub(crate) fn with_bundled_roots() -> Self {
+        let mut store = Self::empty();
+        store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        return store;
+    }
+
+    pub(crate) fn add_trust_anchors(&mut self, anchors: impl Iterator<Item = OwnedTrustAnchor>) {
+        self.roots.extend(anchors);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        return self.roots.len();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.roots.is_empty();
+    }
+}
+
+/// Every signature algorithm that webpki implements
+#[cfg(feature = "webpki-verifier")]
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+];
+
+/// Builds a chain from the end-entity certificate to one of the trust
+/// anchors, then checks that the end-entity certificate is valid for the
+/// server name, like rustls does with a RootCertStore
+#[cfg(feature = "webpki-verifier")]
+#[allow(dead_code)]
+pub(crate) struct WebPkiVerifier {
+    roots: RootCertStore,
+}
+
+#[cfg(feature = "webpki-verifier")]
+#[allow(dead_code)]
+impl WebPkiVerifier {
+    pub(crate) fn new(roots: RootCertStore) -> Self {
+        return Self { roots };
+    }
+
+    /// Trust the Mozilla root store bundled by webpki-roots
+    pub(crate) fn with_bundled_roots() -> Self {
+        return Self::new(RootCertStore::with_bundled_roots());
+    }
+}
+
+#[cfg(feature = "webpki-verifier")]
+impl ServerCertVerifier for WebPkiVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &[u8],
+        intermediates: &[&[u8]],
+        server_name: &str,
+        now: SystemTime,
+    ) -> Result<(), TlsError> {
+        let cert = webpki::EndEntityCert::try_from(end_entity).map_err(bad_certificate)?;
+        let now = webpki::Time::try_from(now)
+            .map_err(|_| TlsError::BadCertificate("the clock is before the UNIX epoch"))?;
+        let roots: Vec<webpki::TrustAnchor> = self
+            .roots
+            .roots
+            .iter()
+            .map(|anchor| webpki::TrustAnchor {
+                subject: &anchor.subject,
+                spki: &anchor.spki,
+                name_constraints: anchor.name_constraints.as_deref(),
+            })
+            .collect();
+        cert.verify_is_valid_tls_server_cert(
+            SIGNATURE_ALGORITHMS,
+            &webpki::TlsServerTrustAnchors(&roots),
+            intermediates,
+            now,
+        )
+        .map_err(bad_certificate)?;
+        let server_name = webpki::SubjectNameRef::try_from_ascii_str(server_name)
+            .map_err(|_| TlsError::BadCertificate("invalid server name"))?;
+
+        return cert
+            .verify_is_valid_for_subject_name(server_name)
+            .map_err(bad_certificate);
+    }
+}
+
+#[cfg(feature = "webpki-verifier")]
+fn bad_certificate(err: webpki::Error) -> TlsError {
+    return TlsError::BadCertificate(match err {
+        webpki::Error::UnknownIssuer => "unknown issuer",
+        webpki::Error::CertExpired => "certificate expired",
+        webpki::Error::CertNotValidYet => "certificate not valid yet",
+        webpki::Error::CertNotValidForName => "certificate not valid for the server name",
+        webpki::Error::BadDer | webpki::Error::BadDerTime => "malformed certificate",
+        _ => "invalid certificate chain",
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    #[cfg(feature = "webpki-verifier")]
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// The Ed25519 certificate for localhost that the test server presents,
+    /// and its key
+    const SELF_SIGNED: &[u8] = include_bytes!("../testdata/self-signed/cert.der");
+    const SELF_SIGNED_KEY: &[u8] = include_bytes!("../testdata/self-signed/key.pk8");
+
+    /// cloudflare-dns.com, issued by an intermediate under DigiCert Global
+    /// Root CA, and valid for a year from October 2021
+    #[cfg(feature = "webpki-verifier")]
+    const END_ENTITY: &[u8] = include_bytes!("../testdata/cloudflare-dns/ee.der");
+    #[cfg(feature = "webpki-verifier")]
+    const INTERMEDIATE: &[u8] = include_bytes!("../testdata/cloudflare-dns/inter.der");
+
+    #[cfg(feature = "webpki-verifier")]
+    fn september_2022() -> SystemTime {
+        return UNIX_EPOCH + Duration::from_secs(1_663_495_771);
+    }
+
+    #[test]
+    fn verify_certificate_verify_signature() {
+        let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(SELF_SIGNED_KEY).unwrap();
+        assert_eq!(
+            asn1::subject_public_key(SELF_SIGNED).unwrap(),
+            key.public_key().as_ref()
+        );
+        let transcript_hash = [0xab; 32];
+        let signature = key.sign(&server_signed_content(&transcript_hash));
+        verify_server_signature(
+            SELF_SIGNED,
+            SignatureScheme::Ed25519,
+            signature.as_ref(),
+            &transcript_hash,
+        )
+        .unwrap();
+
+        // Over another transcript, with another scheme, or tampered with
+        assert!(matches!(
+            verify_server_signature(
+                SELF_SIGNED,
+                SignatureScheme::Ed25519,
+                signature.as_ref(),
+                &[0xcd; 32]
+            ),
+            Err(TlsError::DecryptError(_))
+        ));
+        assert!(matches!(
+            verify_server_signature(
+                SELF_SIGNED,
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                signature.as_ref(),
+                &transcript_hash
+            ),
+            Err(TlsError::DecryptError(_))
+        ));
+        let mut tampered = signature.as_ref().to_vec();
+        tampered[0] ^= 1;
+        assert!(matches!(
+            verify_server_signature(
+                SELF_SIGNED,
+                SignatureScheme::Ed25519,
+                &tampered,
+                &transcript_hash
+            ),
+            Err(TlsError::DecryptError(_))
+        ));
+        assert!(matches!(
+            verify_server_signature(
+                SELF_SIGNED,
+                SignatureScheme::RsaPkcs1Sha256,
+                signature.as_ref(),
+                &transcript_hash
+            ),
+            Err(TlsError::IllegalParameter(_))
+        ));
+    }
+
+    #[test]
+    fn signed_content_starts_with_context() {
+        let content = server_signed_content(&[0x01; 32]);
+
+        assert_eq!(content[..64], [0x20; 64]);
+        assert_eq!(&content[64..97], b"TLS 1.3, server CertificateVerify");
+        assert_eq!(content[97], 0);
+        assert_eq!(content[98..], [0x01; 32]);
+    }
+
+    #[test]
+    fn default_verifier_trusts_no_self_signed_chain() {
+        assert!(matches!(
+            default_verifier().verify_server_cert(SELF_SIGNED, &[], "localhost", SystemTime::now()),
+            Err(TlsError::BadCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn root_store_from_bundled_anchors() {
+        let store = RootCertStore::with_bundled_roots();
+        assert!(!store.is_empty());
+        assert_eq!(store.len(), webpki_roots::TLS_SERVER_ROOTS.0.len());
+        assert!(RootCertStore::empty().is_empty());
+    }
+
+    #[cfg(feature = "webpki-verifier")]
+    #[test]
+    fn verify_real_chain_against_bundled_roots() {
+        let verifier = WebPkiVerifier::with_bundled_roots();
+        verifier
+            .verify_server_cert(
+                END_ENTITY,
+                &[INTERMEDIATE],
+                "cloudflare-dns.com",
+                september_2022(),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            verifier.verify_server_cert(
+                END_ENTITY,
+                &[INTERMEDIATE],
+                "example.com",
+                september_2022()
+            ),
+            Err(TlsError::BadCertificate(_))
+        ));
+        assert!(matches!(
+            verifier.verify_server_cert(END_ENTITY, &[], "cloudflare-dns.com", september_2022()),
+            Err(TlsError::BadCertificate("unknown issuer"))
+        ));
+        assert!(matches!(
+            verifier.verify_server_cert(
+                END_ENTITY,
+                &[INTERMEDIATE],
+                "cloudflare-dns.com",
+                SystemTime::now()
+            ),
+            Err(TlsError::BadCertificate("certificate expired"))
+        ));
+    }
+}